@@ -1,12 +1,18 @@
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashSet};
 use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 use super::geom::Chunk;
 use super::geom::ChunkKey;
+use super::voxel_registry::VoxelReg;
+use super::world::{World, WorldType};
+use log::{trace, warn};
 
-use flamer::flame;
+use crate::metrics::{Counter, Gauge, Histogram, GEN_QUEUE, TIME_BUCKETS};
+use crate::span;
 
 #[derive(Copy, Clone, Debug)]
 pub struct GenNode {
@@ -35,15 +41,40 @@ impl PartialEq for GenNode {
 
 impl Eq for GenNode {}
 
+//A new chunk can complete the neighbourhood of itself and of any chunk around it, each of
+//those is decorated once it's complete
+fn decorate_around(world: &World, world_type: &dyn WorldType, key: &ChunkKey, reg: &VoxelReg) {
+    for x in -1..=1 {
+        for y in -1..=1 {
+            for z in -1..=1 {
+                let n_key = ChunkKey {
+                    x: key.x + x,
+                    y: key.y + y,
+                    z: key.z + z,
+                };
+                if world.neighbours_exist(&n_key) && world.mark_decorated(&n_key) {
+                    world_type.decorate(world, &n_key, reg);
+                    if let Some(structures) = world.structures.as_ref() {
+                        structures.decorate(world, &n_key, world_type, reg);
+                    }
+                }
+            }
+        }
+    }
+}
+
 pub struct ChunkGen {
     rx: Receiver<GenNode>,
     queue: BinaryHeap<GenNode>,
     shared_state: super::SharedState,
-    in_queue: HashSet<ChunkKey>,
+    //World and key of every queued chunk
+    in_queue: HashSet<(u64, ChunkKey)>,
+    generated: Arc<Counter>,
+    gen_time: Arc<Histogram>,
+    queue_len: Arc<Gauge>,
 }
 
 impl ChunkGen {
-    #[flame("ChunkGen")]
     pub fn init(shared_state: super::SharedState, rx: Receiver<GenNode>) {
         let mut gen = ChunkGen::new(rx, shared_state);
         thread::Builder::new()
@@ -54,52 +85,77 @@ impl ChunkGen {
             .unwrap();
     }
 
-    #[flame("ChunkGen")]
     fn new(rx: Receiver<GenNode>, shared_state: super::SharedState) -> Self {
+        let metrics = shared_state.metrics.clone();
         ChunkGen {
             rx,
             queue: BinaryHeap::new(),
             shared_state,
             in_queue: HashSet::new(),
+            generated: metrics.counter("chunks_generated_total", "Chunks generated"),
+            gen_time: metrics.histogram(
+                "chunk_gen_seconds",
+                "Time to generate a chunk",
+                TIME_BUCKETS,
+            ),
+            queue_len: metrics.gauge(GEN_QUEUE, "Chunks waiting to be generated"),
+        }
+    }
+
+    //Worlds and world types removed since the chunk was queued are skipped, a world removed
+    //while its chunk is generated refuses the chunk
+    fn generate(&self, node: &GenNode, start: Instant) {
+        let world = match self.shared_state.world_registry.get(&node.world_id) {
+            Some(world) => world,
+            None => return,
+        };
+        let registry = &self.shared_state.world_type_registry;
+        let world_type = match registry.get(&world.world_type) {
+            Some(world_type) => world_type,
+            None => {
+                warn!(
+                    "World {} has no registered world type {}",
+                    world.name, world.world_type
+                );
+                return;
+            }
+        };
+        let reg = &self.shared_state.voxel_registry;
+        let voxels = world_type.gen_chunk(&node.key, reg);
+        let chunk = Chunk::new(world.chunk_size(), &node.key, voxels, reg);
+        match world.insert_chunk(node.key, chunk, reg) {
+            Ok(()) => {
+                decorate_around(&world, world_type.as_ref(), &node.key, reg);
+                self.generated.inc();
+                self.gen_time.observe_duration(start.elapsed());
+            }
+            Err(e) => trace!("Dropped chunk {:?}, {}", node.key, e),
         }
     }
 
-    #[flame("ChunkGen")]
     fn run(&mut self) {
         loop {
             while !self.queue.is_empty() {
                 let node = self.queue.pop().unwrap();
-                println!("Generating: {:?}", node);
-                let world_type;
-                {
-                    let world = self.shared_state.world_registry.world(&node.world_id);
-                    world_type = self
-                        .shared_state
-                        .world_type_registry
-                        .world_type_reg
-                        .get(&world.world_type)
-                        .unwrap();
-                }
-                let voxels = world_type.gen_chunk(&node.key, &self.shared_state.voxel_registry);
-                let world = self.shared_state.world_registry.world(&node.world_id);
-                world.pc.insert_chunk(
-                    node.key,
-                    Chunk::new(
-                        world.chunk_size(),
-                        &node.key,
-                        voxels,
-                        &self.shared_state.voxel_registry,
-                    ),
-                );
-                self.in_queue.remove(&node.key);
+                self.queue_len.set(self.queue.len() as f64);
+                trace!("Generating {:?} in world {}", node.key, node.world_id);
+                let _span = span!("generate_chunk");
+                let start = Instant::now();
+                self.generate(&node, start);
+                self.in_queue.remove(&(node.world_id, node.key));
             }
 
             match self.rx.try_recv() {
                 Ok(node) => {
-                    let world = self.shared_state.world_registry.world(&node.world_id);
-                    if !world.pc.chunk_exists(&node.key) && !self.in_queue.contains(&node.key) {
+                    let id = (node.world_id, node.key);
+                    let exists = match self.shared_state.world_registry.get(&node.world_id) {
+                        Some(world) => world.pc.chunk_exists(&node.key),
+                        None => true,
+                    };
+                    if !exists && !self.in_queue.contains(&id) {
                         self.queue.push(node);
-                        self.in_queue.insert(node.key);
+                        self.in_queue.insert(id);
+                        self.queue_len.set(self.queue.len() as f64);
                     }
                 }
                 Err(TryRecvError::Empty) => {}
@@ -1,15 +1,22 @@
 use std::collections::{BinaryHeap, HashMap};
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
 use glm::Vec3;
-
-use flamer::flame;
+use log::trace;
 
 use super::chunk_gen::GenNode;
 use super::geom::normals;
 use super::geom::ChunkKey;
+use super::geom::{neighbour_idx, pack_ao, Neighbourhood};
+use super::voxel_registry::VoxelKind;
 use super::SharedState;
+use crate::metrics::{Counter, Gauge, Histogram, TICKET_QUEUE, TIME_BUCKETS};
+use crate::span;
+
+const ALL_FACES: u32 = 0b11_1111;
 
 #[derive(Debug, Ord, Eq, PartialEq, PartialOrd)]
 pub struct ChunkTicket {
@@ -24,7 +31,6 @@ pub struct ChunkTicket {
 }
 
 impl ChunkTicket {
-    #[flame("ChunkTicket")]
     pub fn new(key: ChunkKey, priority: u32, ttl: u32, world_id: u64) -> ChunkTicket {
         ChunkTicket {
             key,
@@ -53,16 +59,19 @@ pub struct ChunkUpdater {
     tx: Sender<ChunkKey>,
     tx_chunk_gen: Sender<GenNode>,
     old_cam_chunk_pos: Vec3,
+    tickets_processed: Arc<Counter>,
+    remesh_time: Arc<Histogram>,
+    queue_len: Arc<Gauge>,
 }
 
 impl ChunkUpdater {
-    #[flame("ChunkUpdater")]
     pub fn new(
         state: SharedState,
         rx: Receiver<ChunkTicket>,
         tx: Sender<ChunkKey>,
         tx_chunk_gen: Sender<GenNode>,
     ) -> ChunkUpdater {
+        let metrics = state.metrics.clone();
         ChunkUpdater {
             ticket_queue: BinaryHeap::new(),
             ticket_map: HashMap::new(),
@@ -71,10 +80,17 @@ impl ChunkUpdater {
             tx,
             tx_chunk_gen,
             old_cam_chunk_pos: Vec3::new(0.1, 0.1, 0.1),
+            tickets_processed: metrics
+                .counter("tickets_processed_total", "Chunk tickets processed"),
+            remesh_time: metrics.histogram(
+                "chunk_remesh_seconds",
+                "Time to rebuild the render data of a chunk",
+                TIME_BUCKETS,
+            ),
+            queue_len: metrics.gauge(TICKET_QUEUE, "Chunk tickets waiting"),
         }
     }
 
-    #[flame("ChunkUpdater")]
     pub fn init(
         rx: Receiver<ChunkTicket>,
         tx: Sender<ChunkKey>,
@@ -90,7 +106,6 @@ impl ChunkUpdater {
             .unwrap();
     }
 
-    #[flame("ChunkUpdater")]
     pub fn run(&mut self) {
         let mut last_tick = 0;
         loop {
@@ -108,7 +123,6 @@ impl ChunkUpdater {
         }
     }
 
-    #[flame("ChunkUpdater")]
     pub fn add_ticket(&mut self, ticket: ChunkTicket) {
         if self.ticket_map.contains_key(&ticket.key) {
             self.ticket_map.insert(ticket.key, ticket);
@@ -121,7 +135,6 @@ impl ChunkUpdater {
         }
     }
 
-    #[flame("ChunkUpdater")]
     pub fn propagate_ticket(&mut self, key: &ChunkKey) {
         if self.ticket_map[key].priority > 1 {
             for i in 0..6 {
@@ -147,13 +160,18 @@ impl ChunkUpdater {
         self.ticket_map.get_mut(key).unwrap().propagated = true;
     }
 
-    #[flame("ChunkUpdater")]
     fn update_chunk_render(&mut self, key: &ChunkKey) {
+        let _span = span!("update_chunk_render");
+        let start = Instant::now();
         let mut visible = false;
-        let world = self
+        let world = match self
             .state
             .world_registry
-            .world(&self.ticket_map[key].world_id);
+            .get(&self.ticket_map[key].world_id)
+        {
+            Some(world) => world,
+            None => return,
+        };
 
         for i in 0..6 {
             let norm = super::geom::normals(i);
@@ -171,72 +189,91 @@ impl ChunkUpdater {
         self.ticket_map.get_mut(key).unwrap().render = visible;
 
         if visible {
+            let reg = &self.state.voxel_registry;
             let mut render_data = Vec::new();
+            let mut translucent_data = Vec::new();
+            let hood = Neighbourhood::new(&world.pc, key);
+            let origin = world.pc.voxel_to_world_pos(key, &Vec3::zeros());
             for idx in 0..world.pc.chunk_tot_size() {
-                if !world
-                    .pc
-                    .voxel_in_chunk_transparency_idx(key, idx, &self.state.voxel_registry)
-                {
-                    let pos = super::geom::idx_to_pos(idx, world.chunk_size());
-                    let mut render = false;
-                    for i in 0..6 {
-                        let norm = normals(i);
-                        let n_pos = pos + norm;
-                        if world.pc.voxel_pos_in_chunk(key, &n_pos) {
-                            if world.pc.voxel_in_chunk_transparency(
-                                key,
-                                &n_pos,
-                                &self.state.voxel_registry,
-                            ) {
-                                render = true;
-                                break;
-                            }
-                        } else {
-                            let mut n_key = key.clone();
-                            n_key.x += norm.x as i32;
-                            n_key.y += norm.y as i32;
-                            n_key.z += norm.z as i32;
-                            if world.pc.chunk_exists(&n_key) {
-                                let n_world_pos = world.pc.voxel_to_world_pos(key, &n_pos);
-                                if world.pc.voxel_transparency(
-                                    &n_world_pos,
-                                    &n_key,
-                                    &self.state.voxel_registry,
-                                    world.chunk_size(),
-                                ) {
-                                    render = true;
-                                    break;
-                                }
-                            } else {
-                                render = true;
-                                break;
-                            }
+                let pos = super::geom::idx_to_pos(idx, world.chunk_size());
+                let at = |offset: Vec3| {
+                    let n_pos = pos + offset;
+                    hood.voxel([n_pos.x as i32, n_pos.y as i32, n_pos.z as i32])
+                };
+                let voxel = match at(Vec3::zeros()) {
+                    Some(voxel) => voxel,
+                    None => continue,
+                };
+                let attributes = reg.voxel_attributes(&voxel);
+                if attributes.kind == VoxelKind::Empty {
+                    continue;
+                }
+
+                //Bit i is set when the face towards normals(i) is hidden by its neighbour,
+                //neighbours in chunks that don't exist yet count as empty
+                let mut hidden_faces = 0;
+                for i in 0..6 {
+                    if let Some(neighbour) = at(normals(i)) {
+                        if !reg.face_visible(&voxel, &neighbour) {
+                            hidden_faces |= 1 << i;
                         }
                     }
+                }
+                if hidden_faces == ALL_FACES {
+                    continue;
+                }
 
-                    if render {
-                        let world_pos = world.pc.voxel_to_world_pos(key, &pos);
-                        render_data.push(world_pos.x);
-                        render_data.push(world_pos.y);
-                        render_data.push(world_pos.z);
+                //Opaque voxels around this one, including ones in neighbouring chunks, shade
+                //the corners of its faces
+                let mut occupied = [false; 27];
+                for x in -1..=1 {
+                    for y in -1..=1 {
+                        for z in -1..=1 {
+                            occupied[neighbour_idx([x, y, z])] =
+                                at(Vec3::new(x as f32, y as f32, z as f32))
+                                    .is_some_and(|n| !reg.is_transparent(&n));
+                        }
                     }
                 }
+                let ao = pack_ao(&occupied);
+
+                //Render data per voxel is its world position, the texture layers of its top,
+                //side and bottom faces, its opacity, its hidden faces and its packed ambient
+                //occlusion
+                let world_pos = origin + pos;
+                let textures = attributes.textures;
+                let data = if attributes.kind == VoxelKind::Translucent {
+                    &mut translucent_data
+                } else {
+                    &mut render_data
+                };
+                data.extend_from_slice(&[
+                    world_pos.x,
+                    world_pos.y,
+                    world_pos.z,
+                    textures.top as f32,
+                    textures.side as f32,
+                    textures.bottom as f32,
+                    attributes.opacity,
+                    hidden_faces as f32,
+                    ao[0],
+                    ao[1],
+                ]);
             }
-            drop(world);
-            if render_data.len() > 0 {
-                let w = self
-                    .state
-                    .world_registry
-                    .world(&self.ticket_map[key].world_id);
-                w.pc.chunk_set_render_data(key, render_data);
+            if render_data.len() > 0 || translucent_data.len() > 0 {
+                world.pc.chunk_set_render_data(key, render_data);
+                world.pc.chunk_set_translucent_data(key, translucent_data);
             }
         }
+        self.remesh_time.observe_duration(start.elapsed());
     }
 
-    #[flame("ChunkUpdater")]
     fn process_check_if_new_chunk(&mut self, key: &ChunkKey) -> bool {
         let ticket = self.ticket_map.get(key).unwrap();
-        let world = self.state.world_registry.world(&ticket.world_id);
+        let world = match self.state.world_registry.get(&ticket.world_id) {
+            Some(world) => world,
+            None => return true,
+        };
         if !world.pc.chunk_exists(&ticket.key) {
             self.tx_chunk_gen
                 .send(GenNode {
@@ -251,9 +288,9 @@ impl ChunkUpdater {
         }
     }
 
-    #[flame("ChunkUpdater")]
     pub fn process(&mut self) {
-        println!("Chunk Ticket Queue Len: {}", self.ticket_queue.len());
+        self.queue_len.set(self.ticket_queue.len() as f64);
+        trace!("Chunk ticket queue length {}", self.ticket_queue.len());
         {
             let cam_chunk_pos = self.state.cam_chunk_pos.read().unwrap();
             if *cam_chunk_pos != self.old_cam_chunk_pos {
@@ -263,9 +300,16 @@ impl ChunkUpdater {
         }
 
         if !self.ticket_queue.is_empty() {
+            let active_world = *self.state.active_world.read().unwrap();
             let mut next_queue = BinaryHeap::new();
             while !self.ticket_queue.is_empty() {
                 let ticket_priority = self.ticket_queue.pop().unwrap();
+                //Tickets left over from a world that was switched away from are dropped
+                if self.ticket_map[&ticket_priority.key].world_id != active_world {
+                    self.ticket_map.remove(&ticket_priority.key);
+                    continue;
+                }
+                self.tickets_processed.inc();
                 self.ticket_map.get_mut(&ticket_priority.key).unwrap().ttl -= 1;
                 if !self.process_check_if_new_chunk(&ticket_priority.key) {
                     if !self.ticket_map[&ticket_priority.key].propagated {
@@ -299,3 +343,58 @@ impl ChunkUpdater {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::Chunk;
+    use crate::metrics::Metrics;
+    use crate::voxel_registry::VoxelReg;
+    use crate::world::{World, WorldRegistry, WorldTypeRegistry};
+    use std::sync::{mpsc, RwLock};
+
+    #[test]
+    fn test_placed_voxel_changes_render_data() {
+        let mut reg = VoxelReg::new();
+        let src = "[[voxel]]\nid = \"transparent\"\nkind = \"empty\"\n\
+                   [[voxel]]\nid = \"stone\"\ntextures = { all = \"stone.png\" }";
+        crate::voxel_defs::parse(src, "test", &mut reg).unwrap();
+        let air = reg.key_from_string_id("transparent").unwrap();
+        let stone = reg.key_from_string_id("stone").unwrap();
+        let key = ChunkKey { x: 0, y: 0, z: 0 };
+        let worlds = Arc::new(WorldRegistry::new());
+        let world_id = worlds.new_world(World::new("w", 4, 1, 1.0)).unwrap();
+        let world = worlds.get(&world_id).unwrap();
+        world
+            .insert_chunk(key, Chunk::new(4, &key, vec![air; 64], &reg), &reg)
+            .unwrap();
+        world.place_voxel([1, 1, 1], stone, &reg).unwrap();
+
+        let state = SharedState {
+            voxel_registry: Arc::new(reg),
+            world_type_registry: Arc::new(WorldTypeRegistry::new()),
+            world_registry: worlds.clone(),
+            tick: Arc::new(RwLock::new(0)),
+            active_world: Arc::new(RwLock::new(world_id)),
+            cam_chunk_pos: Arc::new(RwLock::new(Vec3::zeros())),
+            clear_render: Arc::new(RwLock::new(false)),
+            chunk_size: Arc::new(4),
+            metrics: Arc::new(Metrics::new()),
+        };
+        let (_tx_ticket, rx) = mpsc::channel();
+        let (tx, _rx_keys) = mpsc::channel();
+        let (tx_chunk_gen, _rx_gen) = mpsc::channel();
+        let mut updater = ChunkUpdater::new(state.clone(), rx, tx, tx_chunk_gen);
+        updater.add_ticket(ChunkTicket::new(key, 1, 1, world_id));
+        updater.update_chunk_render(&key);
+        let before = world.pc.chunk_render(&key);
+        let version = world.pc.chunk_render_version(&key).unwrap();
+
+        world
+            .place_voxel([2, 1, 1], stone, &state.voxel_registry)
+            .unwrap();
+        updater.update_chunk_render(&key);
+        assert_ne!(world.pc.chunk_render(&key), before);
+        assert!(world.pc.chunk_render_version(&key).unwrap() > version);
+    }
+}
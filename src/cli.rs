@@ -0,0 +1,277 @@
+use std::fmt;
+
+pub const DEFAULT_CONFIG_PATH: &str = "config/renderer.toml";
+const DEFAULT_BENCH_FRAMES: u64 = 1000;
+
+pub const USAGE: &str = "\
+Usage: voxel-render [command] [options]
+
+Commands:
+  run                     open the renderer, the default
+  bench                   render a fixed number of frames headless and report frame times
+  export <file>           generate chunks without a window and save them as a region file
+  import <file>           open the renderer on a world holding the region in file
+
+Options for every command:
+  --config <file>         renderer config, default config/renderer.toml
+  --set <key>=<value>     override a config value, e.g. --set camera.fov=90
+  --help                  print this
+
+Options for run, bench and import:
+  --record <file>         record input to file
+  --replay <file>         play back recorded input
+  --headless              hide the window
+  --camera-path <file>    fly the camera along a path
+  --dump-frames <dir>     save every frame of the camera path
+  --screenshot-depth      save depth next to screenshots
+  --screenshot-tiles <n>  scale of tiled screenshots, default 4
+  --frames <n>            stop after n frames, bench defaults to 1000
+
+Options for export:
+  --from <x,y,z>          first chunk of the region, default -2,-1,-2
+  --to <x,y,z>            last chunk of the region, default 1,0,1";
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    UnknownArgument(String),
+    MissingValue(String),
+    InvalidValue(String, String),
+    MissingFile(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::UnknownArgument(arg) => write!(f, "unknown argument {}", arg),
+            CliError::MissingValue(flag) => write!(f, "{} expects a value", flag),
+            CliError::InvalidValue(flag, value) => {
+                write!(f, "invalid value \"{}\" for {}", value, flag)
+            }
+            CliError::MissingFile(command) => write!(f, "{} expects a file", command),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+//Options for commands that open the renderer
+#[derive(Debug, PartialEq)]
+pub struct RunOptions {
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub headless: bool,
+    pub camera_path: Option<String>,
+    pub dump_frames: Option<String>,
+    pub screenshot_depth: bool,
+    pub screenshot_tiles: u32,
+    pub frames: Option<u64>,
+    pub bench: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            record: None,
+            replay: None,
+            headless: false,
+            camera_path: None,
+            dump_frames: None,
+            screenshot_depth: false,
+            screenshot_tiles: 4,
+            frames: None,
+            bench: false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ExportOptions {
+    pub path: String,
+    //Inclusive chunk coordinates
+    pub from: [i32; 3],
+    pub to: [i32; 3],
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(RunOptions),
+    Export(ExportOptions),
+    Import(String, RunOptions),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli {
+    pub config: String,
+    pub overrides: Vec<String>,
+    pub command: Command,
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T, CliError> {
+    value
+        .parse()
+        .map_err(|_| CliError::InvalidValue(flag.to_string(), value))
+}
+
+fn parse_chunk(flag: &str, value: String) -> Result<[i32; 3], CliError> {
+    let parts: Vec<Option<i32>> = value.split(',').map(|p| p.trim().parse().ok()).collect();
+    match parts.as_slice() {
+        [Some(x), Some(y), Some(z)] => Ok([*x, *y, *z]),
+        _ => Err(CliError::InvalidValue(flag.to_string(), value)),
+    }
+}
+
+impl Cli {
+    pub fn from_args() -> Result<Cli, CliError> {
+        Cli::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, CliError> {
+        let mut args = args.into_iter().peekable();
+        let command = match args.peek().map(|a| a.as_str()) {
+            Some("run") | Some("bench") | Some("export") | Some("import") => args.next(),
+            _ => None,
+        };
+        let mut file = None;
+        if let Some("export") | Some("import") = command.as_deref() {
+            if args.peek().is_some_and(|a| !a.starts_with("--")) {
+                file = args.next();
+            }
+        }
+
+        let mut cli = Cli {
+            config: DEFAULT_CONFIG_PATH.to_string(),
+            overrides: Vec::new(),
+            command: Command::Help,
+        };
+        let mut run = RunOptions::default();
+        let mut from = [-2, -1, -2];
+        let mut to = [1, 0, 1];
+        let mut help = false;
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| CliError::MissingValue(arg.clone()))
+            };
+            match arg.as_str() {
+                "--config" => cli.config = value()?,
+                "--set" => cli.overrides.push(value()?),
+                "--help" | "-h" => help = true,
+                "--record" => run.record = Some(value()?),
+                "--replay" => run.replay = Some(value()?),
+                "--headless" => run.headless = true,
+                "--camera-path" => run.camera_path = Some(value()?),
+                "--dump-frames" => run.dump_frames = Some(value()?),
+                "--screenshot-depth" => run.screenshot_depth = true,
+                "--screenshot-tiles" => match parse_number(&arg, value()?)? {
+                    0 => return Err(CliError::InvalidValue(arg, "0".to_string())),
+                    tiles => run.screenshot_tiles = tiles,
+                },
+                "--frames" => run.frames = Some(parse_number(&arg, value()?)?),
+                "--from" => from = parse_chunk(&arg, value()?)?,
+                "--to" => to = parse_chunk(&arg, value()?)?,
+                _ => return Err(CliError::UnknownArgument(arg)),
+            }
+        }
+        if help {
+            return Ok(cli);
+        }
+
+        cli.command = match command.as_deref() {
+            None | Some("run") => Command::Run(run),
+            Some("bench") => {
+                run.bench = true;
+                run.headless = true;
+                run.frames = run.frames.or(Some(DEFAULT_BENCH_FRAMES));
+                Command::Run(run)
+            }
+            Some("export") => {
+                let path = file.ok_or_else(|| CliError::MissingFile("export".to_string()))?;
+                let (min, max) = (0..3).fold(([0; 3], [0; 3]), |(mut min, mut max), i| {
+                    min[i] = from[i].min(to[i]);
+                    max[i] = from[i].max(to[i]);
+                    (min, max)
+                });
+                Command::Export(ExportOptions {
+                    path,
+                    from: min,
+                    to: max,
+                })
+            }
+            _ => {
+                let path = file.ok_or_else(|| CliError::MissingFile("import".to_string()))?;
+                Command::Import(path, run)
+            }
+        };
+        Ok(cli)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, CliError> {
+        Cli::parse(args.split_whitespace().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn test_run() {
+        let cli = parse("--headless --set camera.fov=90 --screenshot-tiles 2").unwrap();
+        assert_eq!(cli.config, DEFAULT_CONFIG_PATH);
+        assert_eq!(cli.overrides, vec!["camera.fov=90".to_string()]);
+        match cli.command {
+            Command::Run(run) => {
+                assert!(run.headless && !run.bench);
+                assert_eq!(run.screenshot_tiles, 2);
+                assert_eq!(run.frames, None);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(parse("run --help").unwrap().command, Command::Help);
+    }
+
+    #[test]
+    fn test_commands() {
+        match parse("bench --frames 10").unwrap().command {
+            Command::Run(run) => assert!(run.bench && run.headless && run.frames == Some(10)),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            parse("export out.vxr --from 3,0,-1 --to 1,2,1")
+                .unwrap()
+                .command,
+            Command::Export(ExportOptions {
+                path: "out.vxr".to_string(),
+                from: [1, 0, -1],
+                to: [3, 2, 1],
+            })
+        );
+        match parse("import in.vxr --config other.toml").unwrap() {
+            Cli {
+                config,
+                command: Command::Import(path, _),
+                ..
+            } => assert_eq!((config.as_str(), path.as_str()), ("other.toml", "in.vxr")),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            parse("--fast").unwrap_err(),
+            CliError::UnknownArgument("--fast".to_string())
+        );
+        assert_eq!(
+            parse("--record").unwrap_err(),
+            CliError::MissingValue("--record".to_string())
+        );
+        assert!(parse("--screenshot-tiles 0").is_err());
+        assert!(parse("export --from 1,2").is_err());
+        assert_eq!(
+            parse("import").unwrap_err(),
+            CliError::MissingFile("import".to_string())
+        );
+    }
+}
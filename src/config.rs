@@ -0,0 +1,327 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    Override(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            ConfigError::Parse(err) => write!(f, "invalid config: {}", err),
+            ConfigError::Override(msg) => write!(f, "invalid override: {}", msg),
+            ConfigError::Invalid(msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub width: u32,
+    pub height: u32,
+    pub gl_major: u32,
+    pub gl_minor: u32,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 2560,
+            height: 1440,
+            gl_major: 4,
+            gl_minor: 4,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraConfig {
+    //None starts at the main world's spawn point
+    pub position: Option<[f32; 3]>,
+    pub speed: f32,
+    //Vertical field of view in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub mouse_sensitivity: f32,
+}
+
+impl Default for CameraConfig {
+    fn default() -> Self {
+        CameraConfig {
+            position: None,
+            speed: 20.0,
+            fov: 70.0,
+            near: 0.001,
+            far: 1000.0,
+            mouse_sensitivity: 10.0,
+        }
+    }
+}
+
+//A portal voxel placed in a world when its chunk is generated
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PortalConfig {
+    pub position: [i32; 3],
+    pub voxel: String,
+}
+
+fn default_per_chunk() -> f64 {
+    1.0
+}
+
+//A template placed on the surface of a world's chunks when they are decorated
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StructureConfig {
+    //A region file or a MagicaVoxel .vox model
+    pub template: String,
+    //Expected structures per chunk, the fraction is the chance of one more
+    #[serde(default = "default_per_chunk")]
+    pub per_chunk: f64,
+    //Voxel type the structure stands on, any that isn't transparent when left out
+    pub on: Option<String>,
+    //Voxel types of .vox colour indices, others are voxel
+    #[serde(default)]
+    pub colours: HashMap<String, String>,
+    pub voxel: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldConfig {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub world_type: String,
+    pub seed: u64,
+    pub chunk_size: usize,
+    pub voxel_size: f32,
+    //Portals map positions between worlds by the ratio of their scales
+    pub scale: f32,
+    pub portals: Vec<PortalConfig>,
+    //Parameters of the world type, checked against its schema when the world is created
+    pub params: toml::value::Table,
+    pub structures: Vec<StructureConfig>,
+}
+
+impl Default for WorldConfig {
+    fn default() -> Self {
+        WorldConfig {
+            name: "main".to_string(),
+            world_type: "flat".to_string(),
+            seed: 0,
+            chunk_size: 16,
+            voxel_size: 1.0,
+            scale: 1.0,
+            portals: Vec::new(),
+            params: toml::value::Table::new(),
+            structures: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct StreamingConfig {
+    pub tick_rate: f64,
+    //Priority of the ticket at the camera's chunk, chunks are loaded up to load_radius - 1
+    //steps along the axes from it
+    pub load_radius: u32,
+    //Ticks a ticket keeps its chunks loaded
+    pub ticket_ttl: u32,
+    //Ticks between tickets at the camera
+    pub ticket_interval: u32,
+}
+
+impl Default for StreamingConfig {
+    fn default() -> Self {
+        StreamingConfig {
+            tick_rate: 20.0,
+            load_radius: 5,
+            ticket_ttl: 20,
+            ticket_interval: 20,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub camera: CameraConfig,
+    pub world: WorldConfig,
+    //Worlds created next to the main one, chunk_size and voxel_size are taken from it
+    pub worlds: Vec<WorldConfig>,
+    pub streaming: StreamingConfig,
+}
+
+//Sets the value at a dotted path like "window.width" in table, value is parsed as a TOML value
+//and taken as a string when it isn't one
+fn apply_override(table: &mut toml::Value, spec: &str) -> Result<(), ConfigError> {
+    let eq = spec
+        .find('=')
+        .ok_or_else(|| ConfigError::Override(format!("{} isn't key=value", spec)))?;
+    let (path, raw) = (spec[..eq].trim(), spec[eq + 1..].trim());
+    let value = match toml::from_str::<toml::Value>(&format!("v = {}", raw)) {
+        Ok(parsed) => parsed["v"].clone(),
+        Err(_) => toml::Value::String(raw.to_string()),
+    };
+
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let last = keys.pop().filter(|k| !k.is_empty());
+    let last = last.ok_or_else(|| ConfigError::Override(format!("{} has no key", spec)))?;
+    let mut current = table;
+    for key in keys {
+        current = current
+            .as_table_mut()
+            .ok_or_else(|| ConfigError::Override(format!("{} isn't a table", key)))?
+            .entry(key.to_string())
+            .or_insert_with(|| toml::Value::Table(Default::default()));
+    }
+    current
+        .as_table_mut()
+        .ok_or_else(|| ConfigError::Override(format!("{} isn't in a table", path)))?
+        .insert(last.to_string(), value);
+    Ok(())
+}
+
+impl Config {
+    pub fn parse(src: &str, overrides: &[String]) -> Result<Config, ConfigError> {
+        let mut table: toml::Value = toml::from_str(src).map_err(ConfigError::Parse)?;
+        for spec in overrides.iter() {
+            apply_override(&mut table, spec)?;
+        }
+        let config: Config = table.try_into().map_err(ConfigError::Parse)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    //A missing file is the default config, overrides still apply
+    pub fn load(path: &str, overrides: &[String]) -> Result<Config, ConfigError> {
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(ConfigError::Io(path.to_string(), e)),
+        };
+        Config::parse(&src, overrides)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |msg: &str| Err(ConfigError::Invalid(msg.to_string()));
+        if self.window.width == 0 || self.window.height == 0 {
+            return invalid("window.width and window.height must be positive");
+        }
+        if (self.window.gl_major, self.window.gl_minor) < (4, 4) {
+            return invalid("the renderer needs OpenGL 4.4 or later");
+        }
+        if self.camera.fov <= 0.0 || self.camera.fov >= 180.0 {
+            return invalid("camera.fov must be between 0 and 180 degrees");
+        }
+        if self.camera.near <= 0.0 || self.camera.far <= self.camera.near {
+            return invalid("camera.near must be positive and less than camera.far");
+        }
+        if self.camera.speed < 0.0 {
+            return invalid("camera.speed can't be negative");
+        }
+        let mut names = Vec::new();
+        for world in Some(&self.world).into_iter().chain(self.worlds.iter()) {
+            if names.contains(&&world.name) {
+                return Err(ConfigError::Invalid(format!(
+                    "more than one world is named \"{}\"",
+                    world.name
+                )));
+            }
+            if world.scale <= 0.0 {
+                return invalid("world scales must be positive");
+            }
+            if world.structures.iter().any(|s| !(s.per_chunk >= 0.0)) {
+                return invalid("structure per_chunk can't be negative");
+            }
+            names.push(&world.name);
+        }
+        if self.world.chunk_size == 0 || self.world.voxel_size <= 0.0 {
+            return invalid("world.chunk_size and world.voxel_size must be positive");
+        }
+        if self.streaming.tick_rate <= 0.0 {
+            return invalid("streaming.tick_rate must be positive");
+        }
+        if self.streaming.load_radius == 0
+            || self.streaming.ticket_ttl == 0
+            || self.streaming.ticket_interval == 0
+        {
+            return invalid(
+                "streaming.load_radius, ticket_ttl and ticket_interval must be positive",
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_and_overrides() {
+        let config = Config::parse("", &[]).unwrap();
+        assert_eq!(config.window.width, 2560);
+        assert_eq!(config.world.world_type, "flat");
+
+        let src = "[window]\nwidth = 800\n[camera]\nfov = 90.0";
+        let overrides = vec![
+            "window.height=600".to_string(),
+            "camera.fov = 60.0".to_string(),
+            "world.type=flat".to_string(),
+            "camera.position=[1.0, 2.0, 3.0]".to_string(),
+        ];
+        let overrides = [overrides, vec!["world.params.height=4".to_string()]].concat();
+        let config = Config::parse(src, &overrides).unwrap();
+        assert_eq!((config.window.width, config.window.height), (800, 600));
+        assert_eq!(config.camera.fov, 60.0);
+        assert_eq!(config.camera.position, Some([1.0, 2.0, 3.0]));
+        assert_eq!(config.world.params["height"].as_integer(), Some(4));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let parse = |o: &str| Config::parse("", &[o.to_string()]);
+        assert!(matches!(
+            parse("camera.near=0.0"),
+            Err(ConfigError::Invalid(_))
+        ));
+        let structures = "[[world.structures]]\ntemplate = \"tree.vox\"\nper_chunk = -1.0";
+        assert!(matches!(
+            Config::parse(structures, &[]),
+            Err(ConfigError::Invalid(_))
+        ));
+        let worlds = "[[worlds]]\nname = \"main\"";
+        assert!(matches!(
+            Config::parse(worlds, &[]),
+            Err(ConfigError::Invalid(_))
+        ));
+        assert!(matches!(
+            parse("window.depth=24"),
+            Err(ConfigError::Parse(_))
+        ));
+        assert!(matches!(
+            parse("window.width"),
+            Err(ConfigError::Override(_))
+        ));
+        let nested = Config::parse("[window]\nwidth = 800", &["window.width.x=1".to_string()]);
+        assert!(matches!(nested, Err(ConfigError::Override(_))));
+    }
+}
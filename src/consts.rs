@@ -4,3 +4,6 @@ pub const OPAQUE_VOXEL: &str = "opaque";
 pub const TRANSPARENT_VOXEL: &str = "transparent";
 
 pub const INVALID_VOXEL_ID: u64 = 0;
+
+//Most state ids a single voxel type can use
+pub const MAX_VOXEL_STATES: u64 = 4096;
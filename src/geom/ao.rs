@@ -0,0 +1,77 @@
+//Index into a 3x3x3 block of occupancy around a voxel, each offset component is in -1..=1
+pub fn neighbour_idx(offset: [i32; 3]) -> usize {
+    ((offset[0] + 1) * 9 + (offset[1] + 1) * 3 + (offset[2] + 1)) as usize
+}
+
+//Ambient occlusion of the corners of face (see normals()) from 0, fully occluded, to 3. Bit 0
+//of a corner's index picks the side along the face's first tangent axis, bit 1 along the second
+pub fn face_ao(face: i32, occupied: &[bool; 27]) -> [u32; 4] {
+    let axis = (face / 2) as usize;
+    let (t1, t2) = ((axis + 1) % 3, (axis + 2) % 3);
+    let mut normal = [0; 3];
+    normal[axis] = if face % 2 == 0 { 1 } else { -1 };
+
+    let at = |d1: i32, d2: i32| {
+        let mut offset = normal;
+        offset[t1] += d1;
+        offset[t2] += d2;
+        occupied[neighbour_idx(offset)]
+    };
+
+    let mut out = [0; 4];
+    for (corner, ao) in out.iter_mut().enumerate() {
+        let s1 = if corner & 1 == 0 { -1 } else { 1 };
+        let s2 = if corner & 2 == 0 { -1 } else { 1 };
+        let side1 = at(s1, 0);
+        let side2 = at(0, s2);
+        //Two occluding sides hide the corner voxel too
+        *ao = if side1 && side2 {
+            0
+        } else {
+            3 - (side1 as u32 + side2 as u32 + at(s1, s2) as u32)
+        };
+    }
+    out
+}
+
+//Occlusion of every face corner, 2 bits each with faces 0-2 in the first value and 3-5 in the
+//second. 24 bits fit in a float exactly so it can go in the render data
+pub fn pack_ao(occupied: &[bool; 27]) -> [f32; 2] {
+    let mut packed = [0u32; 2];
+    for face in 0..6 {
+        for (corner, ao) in face_ao(face, occupied).iter().enumerate() {
+            let shift = ((face as usize % 3) * 4 + corner) * 2;
+            packed[face as usize / 3] |= ao << shift;
+        }
+    }
+    [packed[0] as f32, packed[1] as f32]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_voxel_is_unoccluded() {
+        let occupied = [false; 27];
+        assert_eq!(face_ao(2, &occupied), [3; 4]);
+        assert_eq!(pack_ao(&occupied), [0xff_ffff as f32; 2]);
+    }
+
+    #[test]
+    fn test_face_ao_corners() {
+        //Voxel on top of a floor with a wall along -x one level up
+        let mut occupied = [false; 27];
+        for y in -1..=0 {
+            for z in -1..=1 {
+                occupied[neighbour_idx([-1, y + 1, z])] = true;
+            }
+        }
+        //The top face's tangents are z then x, corners 0 and 1 are on the wall's side
+        assert_eq!(face_ao(2, &occupied), [1, 1, 3, 3]);
+        assert_eq!(face_ao(0, &occupied), [3; 4]);
+
+        occupied[neighbour_idx([0, 1, -1])] = true;
+        assert_eq!(face_ao(2, &occupied), [0, 1, 2, 3]);
+    }
+}
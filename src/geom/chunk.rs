@@ -4,13 +4,19 @@ use super::ChunkKey;
 use crate::VoxelReg;
 
 use glm::Vec3;
-
-use flamer::flame;
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct Chunk {
     v: Vec<u64>,
     render_data: Vec<f32>,
+    translucent_data: Vec<f32>,
+    //Bumped whenever the render data changes, so renderers know to upload it again
+    render_version: u64,
+    //Priority of the structure voxel in each cell structures wrote to, the highest wins
+    //whichever order they are placed in
+    structure_priority: HashMap<usize, u64>,
+    decorated: bool,
     pub world_pos_min: Vec3,
 
     transparent_north: bool,
@@ -22,7 +28,6 @@ pub struct Chunk {
 }
 
 impl Chunk {
-    #[flame("Chunk")]
     pub fn new(size: usize, key: &ChunkKey, v: Vec<u64>, vox_reg: &VoxelReg) -> Chunk {
         let rx = (key.x * size as i32) as f32;
         let ry = (key.y * size as i32) as f32;
@@ -34,6 +39,10 @@ impl Chunk {
             v,
             world_pos_min,
             render_data: Vec::new(),
+            translucent_data: Vec::new(),
+            render_version: 0,
+            structure_priority: HashMap::new(),
+            decorated: false,
 
             transparent_north: true,
             transparent_east: true,
@@ -51,56 +60,78 @@ impl Chunk {
         c
     }
 
-    #[flame("Chunk")]
     pub fn set_render_data(&mut self, render_data: Vec<f32>) {
+        if self.render_data != render_data {
+            self.render_version += 1;
+        }
         self.render_data = render_data;
     }
 
-    #[flame("Chunk")]
     pub fn get_render_date(&self) -> &Vec<f32> {
         &self.render_data
     }
 
-    #[flame("Chunk")]
-    fn calc_idx_point(&self, point: &Vec3, chunk_size: usize) -> usize {
-        super::calc_idx(
-            point.x as usize,
-            point.y as usize,
-            point.z as usize,
-            chunk_size,
-        )
+    //Render data of translucent voxels, drawn in a separate blended pass
+    pub fn set_translucent_data(&mut self, translucent_data: Vec<f32>) {
+        if self.translucent_data != translucent_data {
+            self.render_version += 1;
+        }
+        self.translucent_data = translucent_data;
+    }
+
+    pub fn render_version(&self) -> u64 {
+        self.render_version
+    }
+
+    pub fn translucent_data(&self) -> &Vec<f32> {
+        &self.translucent_data
     }
 
-    #[flame("Chunk")]
     pub fn voxel_to_world_pos(&self, pos: &Vec3) -> Vec3 {
         pos + self.world_pos_min
     }
 
-    #[flame("Chunk")]
-    pub fn check_voxel_transparency(&self, pos: &Vec3, reg: &VoxelReg, chunk_size: usize) -> bool {
-        let in_chunk_pos = pos - self.world_pos_min;
-        self.check_voxel_in_chunk_transparency(&in_chunk_pos, reg, chunk_size)
+    pub fn voxel(&self, idx: usize) -> u64 {
+        self.v[idx]
     }
 
-    #[flame("Chunk")]
-    pub fn check_voxel_in_chunk_transparency(
-        &self,
-        pos: &Vec3,
-        reg: &VoxelReg,
+    //Voxels are state ids, every combination of a type's state properties has its own
+    pub fn set_voxel(&mut self, idx: usize, state: u64, chunk_size: usize, vox_reg: &VoxelReg) {
+        self.v[idx] = state;
+        let pos = util::idx_to_pos(idx, chunk_size);
+        self.update_transparency(&state, &pos, chunk_size, vox_reg);
+    }
+
+    //Places a structure's voxel, it only replaces voxels that are transparent in the generated
+    //terrain and voxels of structures with a lower priority
+    pub fn place_structure_voxel(
+        &mut self,
+        idx: usize,
+        state: u64,
+        priority: u64,
         chunk_size: usize,
-    ) -> bool {
-        let idx = self.calc_idx_point(pos, chunk_size);
-        self.check_voxel_in_chunk_transparency_idx(idx, reg)
+        vox_reg: &VoxelReg,
+    ) {
+        match self.structure_priority.get(&idx) {
+            Some(placed) if *placed >= priority => return,
+            None if !vox_reg.is_transparent(&self.v[idx]) => return,
+            _ => {}
+        }
+        self.structure_priority.insert(idx, priority);
+        self.set_voxel(idx, state, chunk_size, vox_reg);
+    }
+
+    //True the first time it's called, so each chunk is decorated once
+    pub fn mark_decorated(&mut self) -> bool {
+        !std::mem::replace(&mut self.decorated, true)
     }
 
-    #[flame("Chunk")]
     pub fn check_voxel_in_chunk_transparency_idx(&self, idx: usize, reg: &VoxelReg) -> bool {
         let vox_type = self.v[idx as usize];
         reg.is_transparent(&vox_type)
     }
 
     //Norm is the normal key (see normals() in geom::utils) used to generate the the key to find this chunk
-    #[flame("Chunk")]
     pub fn is_transparent(&self, norm: i32) -> bool {
         match norm {
             0 => self.transparent_west,
@@ -113,7 +144,6 @@ impl Chunk {
         }
     }
 
-    #[flame("Chunk")]
     fn update_transparency(
         &mut self,
         voxel_type: &u64,
@@ -221,18 +251,4 @@ impl Chunk {
             }
         }
     }
-
-    #[flame("Chunk")]
-    pub fn voxel_pos_in_chunk(&self, pos: &Vec3, chunk_size: usize) -> bool {
-        let size = chunk_size as f32;
-        if pos.x >= size || pos.x < 0.0 {
-            return false;
-        } else if pos.y >= size || pos.y < 0.0 {
-            return false;
-        } else if pos.z >= size || pos.z < 0.0 {
-            return false;
-        } else {
-            true
-        }
-    }
 }
@@ -1,9 +1,13 @@
+mod ao;
 mod chunk;
+mod neighbourhood;
 
 mod point_cloud;
 mod util;
 
+pub use self::ao::{neighbour_idx, pack_ao};
 pub use self::chunk::Chunk;
+pub use self::neighbourhood::Neighbourhood;
 pub use self::point_cloud::ChunkKey;
 pub use self::point_cloud::PointCloud;
 
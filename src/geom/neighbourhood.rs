@@ -0,0 +1,91 @@
+use super::{calc_idx, Chunk, ChunkKey, PointCloud};
+
+//A chunk's voxels and a one voxel border from the 26 chunks around it, copied once so meshing
+//doesn't look up a chunk for every neighbour of every voxel. Voxels of chunks that don't exist
+//are None
+pub struct Neighbourhood {
+    chunk_size: usize,
+    voxels: Vec<Option<u64>>,
+}
+
+impl Neighbourhood {
+    pub fn new(pc: &PointCloud, key: &ChunkKey) -> Neighbourhood {
+        let chunk_size = pc.chunk_size();
+        let side = chunk_size + 2;
+        let mut hood = Neighbourhood {
+            chunk_size,
+            voxels: vec![None; side * side * side],
+        };
+        let n = chunk_size as i32;
+        //Positions along an axis the chunk at offset d covers
+        let span = |d: i32| match d {
+            -1 => -1..0,
+            0 => 0..n,
+            _ => n..n + 1,
+        };
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let n_key = ChunkKey {
+                        x: key.x + dx,
+                        y: key.y + dy,
+                        z: key.z + dz,
+                    };
+                    pc.with_chunk(&n_key, |chunk: &Chunk| {
+                        for x in span(dx) {
+                            for y in span(dy) {
+                                for z in span(dz) {
+                                    let [cx, cy, cz] =
+                                        [x - dx * n, y - dy * n, z - dz * n].map(|p| p as usize);
+                                    let voxel = chunk.voxel(calc_idx(cx, cy, cz, chunk_size));
+                                    let idx = hood.idx([x, y, z]);
+                                    hood.voxels[idx] = Some(voxel);
+                                }
+                            }
+                        }
+                    });
+                }
+            }
+        }
+        hood
+    }
+
+    fn idx(&self, pos: [i32; 3]) -> usize {
+        let [x, y, z] = pos.map(|p| (p + 1) as usize);
+        calc_idx(x, y, z, self.chunk_size + 2)
+    }
+
+    //Voxel at a position relative to the chunk, each component in -1..=chunk_size
+    pub fn voxel(&self, pos: [i32; 3]) -> Option<u64> {
+        self.voxels[self.idx(pos)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::VoxelReg;
+
+    #[test]
+    fn test_neighbourhood() {
+        let mut reg = VoxelReg::new();
+        let src: String = (0..16)
+            .map(|i| format!("[[voxel]]\nid = \"v{}\"\nkind = \"empty\"\n", i))
+            .collect();
+        crate::voxel_defs::parse(&src, "test", &mut reg).unwrap();
+        let voxel = |i: usize| reg.key_from_string_id(&format!("v{}", i)).unwrap();
+        let pc = PointCloud::new(2);
+        //The chunk at x = 1 holds the voxels after those of the chunk at x = 0
+        for x in 0..2 {
+            let key = ChunkKey { x, y: 0, z: 0 };
+            let voxels = (0..8).map(|i| voxel(x as usize * 8 + i)).collect();
+            pc.insert_chunk(key, Chunk::new(2, &key, voxels, &reg));
+        }
+        let hood = Neighbourhood::new(&pc, &ChunkKey { x: 0, y: 0, z: 0 });
+        assert_eq!(hood.voxel([1, 1, 0]), Some(voxel(calc_idx(1, 1, 0, 2))));
+        //x = 2 is the first layer of the chunk at x = 1
+        assert_eq!(hood.voxel([2, 0, 1]), Some(voxel(8 + calc_idx(0, 0, 1, 2))));
+        assert_eq!(hood.voxel([-1, 0, 0]), None);
+        assert_eq!(hood.voxel([0, 2, 0]), None);
+    }
+}
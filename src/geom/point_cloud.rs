@@ -4,7 +4,6 @@ use crate::VoxelReg;
 use dashmap::DashMap;
 use std::fmt;
 
-use flamer::flame;
 use glm::Vec3;
 
 #[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, PartialOrd, Ord)]
@@ -21,7 +20,6 @@ impl fmt::Display for ChunkKey {
 }
 
 impl ChunkKey {
-    #[flame("ChunkKey")]
     pub fn new(point: Vec3) -> ChunkKey {
         return ChunkKey {
             x: point.x as i32,
@@ -39,7 +37,6 @@ pub struct PointCloud {
 }
 
 impl PointCloud {
-    #[flame("PointCloud")]
     pub fn new(chunk_size: usize) -> PointCloud {
         return PointCloud {
             c: DashMap::new(),
@@ -48,22 +45,29 @@ impl PointCloud {
         };
     }
 
-    #[flame("PointCloud")]
     pub fn insert_chunk(&self, key: ChunkKey, c: Chunk) {
         self.c.insert(key, c);
     }
 
-    #[flame("PointCloud")]
+    pub fn chunk_count(&self) -> usize {
+        self.c.len()
+    }
+
     pub fn chunk_exists(&self, key: &ChunkKey) -> bool {
         self.c.contains_key(key)
     }
 
-    #[flame("PointCloud")]
     pub fn chunk_set_render_data(&self, key: &ChunkKey, render_data: Vec<f32>) {
         self.c.get_mut(key).unwrap().set_render_data(render_data);
     }
 
-    #[flame("PointCloud")]
+    pub fn chunk_set_translucent_data(&self, key: &ChunkKey, translucent_data: Vec<f32>) {
+        self.c
+            .get_mut(key)
+            .unwrap()
+            .set_translucent_data(translucent_data);
+    }
+
     pub fn chunk_is_transparent(&self, key: &ChunkKey, norm_key: i32) -> bool {
         if self.chunk_exists(key) {
             self.c.get(key).unwrap().is_transparent(norm_key)
@@ -72,69 +76,67 @@ impl PointCloud {
         }
     }
 
-    #[flame("PointCloud")]
     pub fn chunk_tot_size(&self) -> usize {
         self.tot_chunk_size
     }
 
-    #[flame("PointCloud")]
-    pub fn voxel_in_chunk_transparency(
-        &self,
-        key: &ChunkKey,
-        in_chunk_pos: &Vec3,
-        reg: &VoxelReg,
-    ) -> bool {
-        let idx = super::calc_idx(
-            in_chunk_pos.x as usize,
-            in_chunk_pos.y as usize,
-            in_chunk_pos.z as usize,
-            self.chunk_size,
-        );
-        self.voxel_in_chunk_transparency_idx(key, idx, reg)
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
     }
 
-    #[flame("PointCloud")]
-    pub fn voxel_in_chunk_transparency_idx(
-        &self,
-        key: &ChunkKey,
-        idx: usize,
-        reg: &VoxelReg,
-    ) -> bool {
+    //Runs f on the chunk at key while holding it, None if it doesn't exist
+    pub fn with_chunk<R>(&self, key: &ChunkKey, f: impl FnOnce(&Chunk) -> R) -> Option<R> {
+        self.c.get(key).map(|c| f(&c))
+    }
+
+    pub fn voxel_in_chunk_idx(&self, key: &ChunkKey, idx: usize) -> u64 {
+        self.c.get(key).unwrap().voxel(idx)
+    }
+
+    pub fn set_voxel_in_chunk_idx(&self, key: &ChunkKey, idx: usize, state: u64, reg: &VoxelReg) {
         self.c
-            .get(key)
+            .get_mut(key)
             .unwrap()
-            .check_voxel_in_chunk_transparency_idx(idx, reg)
+            .set_voxel(idx, state, self.chunk_size, reg);
     }
 
-    #[flame("PointCloud")]
-    pub fn voxel_transparency(
+    pub fn place_structure_voxel_in_chunk(
         &self,
-        voxel_world_pos: &Vec3,
         key: &ChunkKey,
+        idx: usize,
+        state: u64,
+        priority: u64,
         reg: &VoxelReg,
-        chunk_size: usize,
-    ) -> bool {
+    ) {
+        self.c.get_mut(key).unwrap().place_structure_voxel(
+            idx,
+            state,
+            priority,
+            self.chunk_size,
+            reg,
+        );
+    }
+
+    //False if the chunk doesn't exist or was already decorated
+    pub fn mark_chunk_decorated(&self, key: &ChunkKey) -> bool {
         self.c
-            .get(key)
-            .unwrap()
-            .check_voxel_transparency(voxel_world_pos, reg, chunk_size)
+            .get_mut(key)
+            .map_or(false, |mut c| c.mark_decorated())
     }
 
-    #[flame("PointCloud")]
     pub fn voxel_to_world_pos(&self, key: &ChunkKey, voxel_pos: &Vec3) -> Vec3 {
         self.c.get(key).unwrap().voxel_to_world_pos(voxel_pos)
     }
 
-    #[flame("PointCloud")]
-    pub fn voxel_pos_in_chunk(&self, key: &ChunkKey, voxel_pos: &Vec3) -> bool {
-        self.c
-            .get(key)
-            .unwrap()
-            .voxel_pos_in_chunk(voxel_pos, self.chunk_size)
+    pub fn chunk_render_version(&self, key: &ChunkKey) -> Option<u64> {
+        self.with_chunk(key, |c| c.render_version())
     }
 
-    #[flame("PointCloud")]
     pub fn chunk_render(&self, key: &ChunkKey) -> Vec<f32> {
         self.c.get(key).unwrap().get_render_date().clone()
     }
+
+    pub fn chunk_translucent(&self, key: &ChunkKey) -> Vec<f32> {
+        self.c.get(key).unwrap().translucent_data().clone()
+    }
 }
@@ -0,0 +1,338 @@
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, RgbaImage};
+
+use super::consts::{INVALID_VOXEL_ID, OPAQUE_VOXEL, TRANSPARENT_VOXEL};
+use super::geom::{calc_idx, ChunkKey};
+use super::voxel_registry::VoxelReg;
+use super::world::{WorldError, WorldType};
+use super::world_factory::{Param, ParamSpec, WorldParams, WorldTypeFactory};
+
+type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+//How columns outside the image are sampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeMode {
+    //The nearest edge pixel
+    Clamp,
+    //The image repeats
+    Tile,
+}
+
+impl EdgeMode {
+    pub fn parse(name: &str) -> Option<EdgeMode> {
+        match name {
+            "clamp" => Some(EdgeMode::Clamp),
+            "tile" => Some(EdgeMode::Tile),
+            _ => None,
+        }
+    }
+
+    //Pixel along an axis of size pixels for a voxel coordinate
+    pub fn pixel(self, pos: i64, size: u32) -> u32 {
+        match self {
+            EdgeMode::Clamp => pos.max(0).min(size as i64 - 1) as u32,
+            EdgeMode::Tile => pos.rem_euclid(size as i64) as u32,
+        }
+    }
+}
+
+pub struct HeightmapSettings {
+    //Height in voxels of a white pixel above a black one
+    pub height: f64,
+    //Height of a black pixel
+    pub base: i32,
+    pub mode: EdgeMode,
+    //Voxel type below the surface layers
+    pub fill: u64,
+    //Voxel type of the surface layers where the splat map doesn't pick one
+    pub surface: u64,
+    pub surface_depth: i32,
+    //Surface voxel types of the splat map's red, green and blue channels
+    pub splat_voxels: [u64; 3],
+}
+
+//Terrain from a greyscale heightmap with a pixel per voxel column, pixel (0, 0) at x = 0, z = 0
+pub struct HeightmapWorldType {
+    chunk_size: usize,
+    width: u32,
+    depth: u32,
+    //Voxel height of the top voxel in each column, row by row
+    heights: Vec<i32>,
+    //Surface voxel of each column
+    surface: Vec<u64>,
+    mode: EdgeMode,
+    fill: u64,
+    surface_depth: i32,
+}
+
+impl HeightmapWorldType {
+    pub const FACTORY: WorldTypeFactory = WorldTypeFactory {
+        name: "heightmap",
+        params: &[
+            ParamSpec {
+                name: "file",
+                default: Param::Text(""),
+            },
+            ParamSpec {
+                name: "splat",
+                default: Param::Text(""),
+            },
+            ParamSpec {
+                name: "mode",
+                default: Param::Text("clamp"),
+            },
+            ParamSpec {
+                name: "height",
+                default: Param::Float(64.0),
+            },
+            ParamSpec {
+                name: "base",
+                default: Param::Int(0),
+            },
+            ParamSpec {
+                name: "voxel",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+            ParamSpec {
+                name: "surface",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+            ParamSpec {
+                name: "surface_depth",
+                default: Param::Int(1),
+            },
+            ParamSpec {
+                name: "splat_r",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+            ParamSpec {
+                name: "splat_g",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+            ParamSpec {
+                name: "splat_b",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+        ],
+        build: HeightmapWorldType::build,
+    };
+
+    //Reads the images when the world type is created, 8 bit images are widened to 16 bits
+    fn build(params: &WorldParams) -> Result<Box<dyn WorldType>, WorldError> {
+        let param = |msg: String| Err(WorldError::Param(msg));
+        let file = params.text("file");
+        if file.is_empty() {
+            return param("heightmap needs a file".to_string());
+        }
+        let mode = match EdgeMode::parse(params.text("mode")) {
+            Some(mode) => mode,
+            None => return param(format!("mode {} isn't clamp or tile", params.text("mode"))),
+        };
+        let base = params.int("base");
+        let surface_depth = params.int("surface_depth");
+        if base < i32::MIN as i64 / 2 || base > i32::MAX as i64 / 2 {
+            return param(format!("base {} is out of range", base));
+        }
+        if surface_depth < 0 || surface_depth > i32::MAX as i64 {
+            return param(format!("surface_depth {} is out of range", surface_depth));
+        }
+        if !(params.float("height").abs() < i32::MAX as f64 / 2.0) {
+            return param(format!("height {} is out of range", params.float("height")));
+        }
+
+        let image_err = |path: &str| {
+            let path = path.to_string();
+            move |e| WorldError::Image(path, e)
+        };
+        let heightmap = match image::open(file).map_err(image_err(file))? {
+            DynamicImage::ImageLuma16(heightmap) => heightmap,
+            other => widen(&other.to_luma8()),
+        };
+        let splat = match params.text("splat") {
+            "" => None,
+            path => Some(image::open(path).map_err(image_err(path))?.to_rgba8()),
+        };
+        let settings = HeightmapSettings {
+            height: params.float("height"),
+            base: base as i32,
+            mode,
+            fill: params.voxel("voxel"),
+            surface: params.voxel("surface"),
+            surface_depth: surface_depth as i32,
+            splat_voxels: [
+                params.voxel("splat_r"),
+                params.voxel("splat_g"),
+                params.voxel("splat_b"),
+            ],
+        };
+        Ok(Box::new(HeightmapWorldType::new(
+            params.chunk_size,
+            &heightmap,
+            splat.as_ref(),
+            &settings,
+        )))
+    }
+
+    //A splat map of another size is stretched over the heightmap
+    pub fn new(
+        chunk_size: usize,
+        heightmap: &Gray16Image,
+        splat: Option<&RgbaImage>,
+        settings: &HeightmapSettings,
+    ) -> HeightmapWorldType {
+        let (width, depth) = heightmap.dimensions();
+        let mut heights = Vec::with_capacity((width * depth) as usize);
+        let mut surface = Vec::with_capacity((width * depth) as usize);
+        for z in 0..depth {
+            for x in 0..width {
+                let value = heightmap.get_pixel(x, z)[0] as f64 / u16::MAX as f64;
+                heights.push(settings.base + (value * settings.height).floor() as i32);
+                let voxel = splat.map_or(settings.surface, |splat| {
+                    let sx = (x as u64 * splat.width() as u64 / width as u64) as u32;
+                    let sz = (z as u64 * splat.height() as u64 / depth as u64) as u32;
+                    splat_voxel(splat.get_pixel(sx, sz).0, settings)
+                });
+                surface.push(voxel);
+            }
+        }
+        HeightmapWorldType {
+            chunk_size,
+            width,
+            depth,
+            heights,
+            surface,
+            mode: settings.mode,
+            fill: settings.fill,
+            surface_depth: settings.surface_depth,
+        }
+    }
+
+    //Index into heights and surface of the column at a voxel position
+    fn column(&self, x: i64, z: i64) -> usize {
+        let px = self.mode.pixel(x, self.width);
+        let pz = self.mode.pixel(z, self.depth);
+        (px + pz * self.width) as usize
+    }
+}
+
+//Spreads 8 bit values over the 16 bit range so white is the full height, image's own conversion
+//stops short of it
+fn widen(image: &GrayImage) -> Gray16Image {
+    ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+        Luma([image.get_pixel(x, y)[0] as u16 * 257])
+    })
+}
+
+//The strongest of the red, green and blue channels picks the voxel, black is the plain surface
+fn splat_voxel(rgba: [u8; 4], settings: &HeightmapSettings) -> u64 {
+    let (channel, weight) =
+        rgba[..3].iter().enumerate().fold(
+            (0, 0),
+            |best, (i, w)| if *w > best.1 { (i, *w) } else { best },
+        );
+    if weight == 0 {
+        settings.surface
+    } else {
+        settings.splat_voxels[channel]
+    }
+}
+
+impl WorldType for HeightmapWorldType {
+    fn gen_chunk(&self, key: &ChunkKey, reg: &VoxelReg) -> Vec<u64> {
+        let transparent_voxel = reg
+            .key_from_string_id(TRANSPARENT_VOXEL)
+            .unwrap_or(INVALID_VOXEL_ID);
+        let size = self.chunk_size;
+        let mut c = vec![transparent_voxel; size * size * size];
+        let origin = [key.x, key.y, key.z].map(|k| k as i64 * size as i64);
+        for x in 0..size {
+            for z in 0..size {
+                let column = self.column(origin[0] + x as i64, origin[2] + z as i64);
+                let height = self.heights[column] as i64;
+                let surface_from = height - self.surface_depth as i64;
+                for y in 0..size {
+                    let world_y = origin[1] + y as i64;
+                    if world_y > height {
+                        break;
+                    }
+                    c[calc_idx(x, y, z, size)] = if world_y > surface_from {
+                        self.surface[column]
+                    } else {
+                        self.fill
+                    };
+                }
+            }
+        }
+        c
+    }
+
+    fn world_type(&self) -> &'static str {
+        "HeightmapWorldType"
+    }
+
+    //Above the middle of the image
+    fn spawn_point(&self) -> Option<[f32; 3]> {
+        let (x, z) = (self.width / 2, self.depth / 2);
+        let height = self.heights[self.column(x as i64, z as i64)];
+        Some([x as f32 + 0.5, height as f32 + 5.0, z as f32 + 0.5])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(mode: EdgeMode) -> HeightmapSettings {
+        HeightmapSettings {
+            height: 10.0,
+            base: 2,
+            mode,
+            fill: 1,
+            surface: 2,
+            surface_depth: 1,
+            splat_voxels: [10, 11, 12],
+        }
+    }
+
+    #[test]
+    fn test_edge_modes() {
+        assert_eq!(EdgeMode::Clamp.pixel(-5, 4), 0);
+        assert_eq!(EdgeMode::Clamp.pixel(9, 4), 3);
+        assert_eq!(EdgeMode::Tile.pixel(-1, 4), 3);
+        assert_eq!(EdgeMode::Tile.pixel(9, 4), 1);
+        assert_eq!(EdgeMode::parse("wrap"), None);
+    }
+
+    #[test]
+    fn test_heights_and_splat() {
+        //Black at x = 0, white at x = 1
+        let heightmap = widen(&ImageBuffer::from_fn(2, 1, |x, _| Luma([x as u8 * 255])));
+        let splat = RgbaImage::from_fn(1, 1, |_, _| image::Rgba([0, 200, 30, 255]));
+        let world = HeightmapWorldType::new(4, &heightmap, Some(&splat), &settings(EdgeMode::Tile));
+        assert_eq!(world.heights, vec![2, 12]);
+        assert_eq!(world.surface, vec![11, 11]);
+        //x = 3 tiles back to the white column
+        assert_eq!(world.column(3, 5), 1);
+
+        let mut reg = VoxelReg::new();
+        let src = "[[voxel]]\nid = \"transparent\"\nkind = \"empty\"";
+        crate::voxel_defs::parse(src, "test", &mut reg).unwrap();
+        let air = reg.key_from_string_id(TRANSPARENT_VOXEL).unwrap();
+        let c = world.gen_chunk(&ChunkKey { x: 0, y: 0, z: 0 }, &reg);
+        let column = |x| {
+            (0..4)
+                .map(|y| c[calc_idx(x, y, 0, 4)])
+                .collect::<Vec<u64>>()
+        };
+        assert_eq!(column(0), vec![1, 1, 11, air]);
+        assert_eq!(column(1), vec![1, 1, 1, 1]);
+        let c = world.gen_chunk(&ChunkKey { x: 0, y: 3, z: 0 }, &reg);
+        assert_eq!(c[calc_idx(1, 0, 0, 4)], 11);
+        assert_eq!(c[calc_idx(1, 1, 0, 4)], air);
+        assert_eq!(c[calc_idx(0, 0, 0, 4)], air);
+
+        let clamped = HeightmapWorldType::new(4, &heightmap, None, &settings(EdgeMode::Clamp));
+        assert_eq!(clamped.column(-3, 0), 0);
+        assert_eq!(clamped.column(7, 0), 1);
+        assert_eq!(clamped.surface, vec![2, 2]);
+    }
+}
@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use glfw::{Key, Modifiers, MouseButton};
+use log::warn;
+use serde::Deserialize;
+
+#[derive(Hash, Eq, PartialEq, Copy, Clone, Debug)]
+pub enum Input {
+    Key(Key),
+    Mouse(MouseButton),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub input: Input,
+    pub mods: Modifiers,
+}
+
+//Held actions fire every frame while a bound input is down, press actions once per press
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    #[default]
+    Held,
+    Press,
+}
+
+#[derive(Clone, Debug)]
+pub struct ActionBindings {
+    pub trigger: Trigger,
+    pub bindings: Vec<Binding>,
+}
+
+pub type BindingMap = HashMap<String, ActionBindings>;
+
+#[derive(Deserialize)]
+struct ActionConfig {
+    keys: Vec<String>,
+    #[serde(default)]
+    trigger: Trigger,
+}
+
+#[derive(Debug)]
+pub enum BindingError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    UnknownInput { action: String, input: String },
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            BindingError::Parse(err) => write!(f, "invalid bindings file: {}", err),
+            BindingError::UnknownInput { action, input } => {
+                write!(f, "unknown input \"{}\" bound to \"{}\"", input, action)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BindingError {}
+
+const KEY_NAMES: &[(&str, Key)] = &[
+    ("A", Key::A),
+    ("B", Key::B),
+    ("C", Key::C),
+    ("D", Key::D),
+    ("E", Key::E),
+    ("F", Key::F),
+    ("G", Key::G),
+    ("H", Key::H),
+    ("I", Key::I),
+    ("J", Key::J),
+    ("K", Key::K),
+    ("L", Key::L),
+    ("M", Key::M),
+    ("N", Key::N),
+    ("O", Key::O),
+    ("P", Key::P),
+    ("Q", Key::Q),
+    ("R", Key::R),
+    ("S", Key::S),
+    ("T", Key::T),
+    ("U", Key::U),
+    ("V", Key::V),
+    ("W", Key::W),
+    ("X", Key::X),
+    ("Y", Key::Y),
+    ("Z", Key::Z),
+    ("0", Key::Num0),
+    ("1", Key::Num1),
+    ("2", Key::Num2),
+    ("3", Key::Num3),
+    ("4", Key::Num4),
+    ("5", Key::Num5),
+    ("6", Key::Num6),
+    ("7", Key::Num7),
+    ("8", Key::Num8),
+    ("9", Key::Num9),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("Space", Key::Space),
+    ("Tab", Key::Tab),
+    ("Enter", Key::Enter),
+    ("Escape", Key::Escape),
+    ("Backspace", Key::Backspace),
+    ("Insert", Key::Insert),
+    ("Delete", Key::Delete),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("Up", Key::Up),
+    ("Down", Key::Down),
+    ("Left", Key::Left),
+    ("Right", Key::Right),
+    ("Minus", Key::Minus),
+    ("Equal", Key::Equal),
+    ("Comma", Key::Comma),
+    ("Period", Key::Period),
+    ("Slash", Key::Slash),
+    ("Semicolon", Key::Semicolon),
+    ("Apostrophe", Key::Apostrophe),
+    ("GraveAccent", Key::GraveAccent),
+    ("LeftBracket", Key::LeftBracket),
+    ("RightBracket", Key::RightBracket),
+    ("Backslash", Key::Backslash),
+    ("LeftShift", Key::LeftShift),
+    ("RightShift", Key::RightShift),
+    ("LeftControl", Key::LeftControl),
+    ("RightControl", Key::RightControl),
+    ("LeftAlt", Key::LeftAlt),
+    ("RightAlt", Key::RightAlt),
+];
+
+const MOUSE_NAMES: &[(&str, MouseButton)] = &[
+    ("MouseLeft", MouseButton::Button1),
+    ("MouseRight", MouseButton::Button2),
+    ("MouseMiddle", MouseButton::Button3),
+    ("Mouse1", MouseButton::Button1),
+    ("Mouse2", MouseButton::Button2),
+    ("Mouse3", MouseButton::Button3),
+    ("Mouse4", MouseButton::Button4),
+    ("Mouse5", MouseButton::Button5),
+    ("Mouse6", MouseButton::Button6),
+    ("Mouse7", MouseButton::Button7),
+    ("Mouse8", MouseButton::Button8),
+];
+
+pub fn parse_key(name: &str) -> Option<Key> {
+    KEY_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, k)| *k)
+}
+
+pub fn key_name(key: Key) -> Option<&'static str> {
+    KEY_NAMES.iter().find(|(_, k)| *k == key).map(|(n, _)| *n)
+}
+
+pub fn mouse_button_name(button: MouseButton) -> &'static str {
+    //Skips the MouseLeft/MouseRight/MouseMiddle aliases so every button has one name
+    MOUSE_NAMES
+        .iter()
+        .skip(3)
+        .find(|(_, b)| *b == button)
+        .map(|(n, _)| *n)
+        .unwrap()
+}
+
+pub fn parse_mouse_button(name: &str) -> Option<MouseButton> {
+    MOUSE_NAMES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, b)| *b)
+}
+
+fn parse_modifier(name: &str) -> Option<Modifiers> {
+    match name.to_ascii_lowercase().as_str() {
+        "shift" => Some(Modifiers::Shift),
+        "ctrl" | "control" => Some(Modifiers::Control),
+        "alt" => Some(Modifiers::Alt),
+        "super" => Some(Modifiers::Super),
+        _ => None,
+    }
+}
+
+//Parses bindings like "W", "Ctrl+S" or "Shift+Mouse1", the input always comes last
+pub fn parse_binding(s: &str) -> Option<Binding> {
+    let mut parts: Vec<&str> = s.split('+').map(|p| p.trim()).collect();
+    let input_name = parts.pop()?;
+    let input = match parse_key(input_name) {
+        Some(key) => Input::Key(key),
+        None => Input::Mouse(parse_mouse_button(input_name)?),
+    };
+    let mut mods = Modifiers::empty();
+    for part in parts {
+        mods |= parse_modifier(part)?;
+    }
+    Some(Binding { input, mods })
+}
+
+pub fn parse(src: &str) -> Result<BindingMap, BindingError> {
+    let config: HashMap<String, ActionConfig> = toml::from_str(src).map_err(BindingError::Parse)?;
+    let mut map = BindingMap::new();
+    for (action, entry) in config {
+        let mut bindings = Vec::new();
+        for key in entry.keys.iter() {
+            match parse_binding(key) {
+                Some(binding) => bindings.push(binding),
+                None => {
+                    return Err(BindingError::UnknownInput {
+                        action,
+                        input: key.clone(),
+                    })
+                }
+            }
+        }
+        map.insert(
+            action,
+            ActionBindings {
+                trigger: entry.trigger,
+                bindings,
+            },
+        );
+    }
+    Ok(map)
+}
+
+pub fn load(path: &str) -> Result<BindingMap, BindingError> {
+    let src = fs::read_to_string(path).map_err(|e| BindingError::Io(path.to_string(), e))?;
+    parse(&src)
+}
+
+pub fn load_or_default(path: &str) -> BindingMap {
+    match load(path) {
+        Ok(map) => map,
+        Err(err) => {
+            warn!("Using default key bindings, {}", err);
+            default_bindings()
+        }
+    }
+}
+
+pub fn default_bindings() -> BindingMap {
+    let defaults: &[(&str, Trigger, Key)] = &[
+        ("move_forward", Trigger::Held, Key::W),
+        ("move_left", Trigger::Held, Key::A),
+        ("move_back", Trigger::Held, Key::S),
+        ("move_right", Trigger::Held, Key::D),
+        ("move_up", Trigger::Held, Key::Space),
+        ("move_down", Trigger::Held, Key::LeftShift),
+        ("reload_bindings", Trigger::Press, Key::F5),
+        ("screenshot", Trigger::Press, Key::F2),
+        ("screenshot_tiled", Trigger::Press, Key::F12),
+        ("toggle_hud", Trigger::Press, Key::F3),
+        ("next_world", Trigger::Press, Key::F4),
+        ("remove_world", Trigger::Press, Key::Delete),
+        ("restore_worlds", Trigger::Press, Key::Insert),
+        ("toggle_ssao", Trigger::Press, Key::F6),
+        ("toggle_bloom", Trigger::Press, Key::F7),
+        ("toggle_tonemap", Trigger::Press, Key::F8),
+        ("toggle_gamma", Trigger::Press, Key::F9),
+        ("toggle_fxaa", Trigger::Press, Key::F10),
+    ];
+    let mut map = BindingMap::new();
+    for (action, trigger, key) in defaults {
+        map.insert(
+            action.to_string(),
+            ActionBindings {
+                trigger: *trigger,
+                bindings: vec![Binding {
+                    input: Input::Key(*key),
+                    mods: Modifiers::empty(),
+                }],
+            },
+        );
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_binding_with_modifiers() {
+        let binding = parse_binding("Ctrl+Shift+W").unwrap();
+        assert_eq!(binding.input, Input::Key(Key::W));
+        assert_eq!(binding.mods, Modifiers::Control | Modifiers::Shift);
+    }
+
+    #[test]
+    fn test_parse_binding_mouse() {
+        let binding = parse_binding("mouseleft").unwrap();
+        assert_eq!(binding.input, Input::Mouse(MouseButton::Button1));
+        assert!(parse_binding("Hyper+W").is_none());
+    }
+
+    #[test]
+    fn test_parse_bindings_file() {
+        let map = parse(
+            r#"
+            [move_forward]
+            keys = ["W", "Up"]
+
+            [reload_bindings]
+            keys = ["F5"]
+            trigger = "press"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(map["move_forward"].bindings.len(), 2);
+        assert_eq!(map["move_forward"].trigger, Trigger::Held);
+        assert_eq!(map["reload_bindings"].trigger, Trigger::Press);
+    }
+
+    #[test]
+    fn test_parse_bindings_unknown_input() {
+        let err = parse("[jump]\nkeys = [\"Nope\"]").unwrap_err();
+        match err {
+            BindingError::UnknownInput { action, input } => {
+                assert_eq!(action, "jump");
+                assert_eq!(input, "Nope");
+            }
+            _ => panic!("expected unknown input error"),
+        }
+    }
+}
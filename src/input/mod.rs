@@ -0,0 +1,216 @@
+pub mod bindings;
+pub mod record;
+
+use crate::Camera;
+use std::collections::HashMap;
+
+use glfw::{Action, Modifiers};
+use log::warn;
+
+use self::bindings::{Binding, BindingMap, Input, Trigger};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum InputEvent {
+    Button(Input, Modifiers, Action),
+    Cursor(f32, f32),
+}
+
+pub fn dispatch(event: &InputEvent, ks: &mut KeyState, cs: &mut CursorState, cam: &mut Camera) {
+    match *event {
+        InputEvent::Button(input, mods, action) => ks.set_state(input, mods, action),
+        InputEvent::Cursor(x, y) => cs.process(x, y, cam),
+    }
+}
+
+pub struct KeyState<'a> {
+    actions: HashMap<String, State<'a>>,
+    bindings: HashMap<Input, Vec<(Modifiers, String)>>,
+    active: HashMap<Input, Vec<String>>,
+}
+
+pub struct State<'a> {
+    held: u32,
+    pressed: bool,
+    trigger: Trigger,
+    action: Box<dyn FnMut(&mut Camera) + 'a>,
+}
+
+impl<'a> State<'a> {
+    fn process(&mut self, cam: &mut Camera) {
+        let fire = match self.trigger {
+            Trigger::Held => self.held > 0,
+            Trigger::Press => self.pressed,
+        };
+        if fire {
+            (self.action)(cam);
+        }
+        self.pressed = false;
+    }
+}
+
+impl<'a> KeyState<'a> {
+    pub fn new() -> Self {
+        KeyState {
+            actions: HashMap::new(),
+            bindings: HashMap::new(),
+            active: HashMap::new(),
+        }
+    }
+
+    pub fn add_action(&mut self, name: &str, action: impl FnMut(&mut Camera) + 'a) {
+        self.actions.entry(name.to_string()).or_insert(State {
+            held: 0,
+            pressed: false,
+            trigger: Trigger::Held,
+            action: Box::new(action),
+        });
+    }
+
+    //Replaces every binding, actions missing from the map are left unbound
+    pub fn set_bindings(&mut self, map: BindingMap) {
+        self.bindings.clear();
+        self.release_all();
+        for (name, entry) in map {
+            match self.actions.get_mut(&name) {
+                Some(state) => state.trigger = entry.trigger,
+                None => warn!("Key binding for unknown action: {}", name),
+            }
+            for binding in entry.bindings {
+                self.bind(&name, binding);
+            }
+        }
+    }
+
+    pub fn rebind(&mut self, name: &str, trigger: Trigger, bindings: Vec<Binding>) {
+        for entries in self.bindings.values_mut() {
+            entries.retain(|(_, action)| action != name);
+        }
+        self.release_all();
+        if let Some(state) = self.actions.get_mut(name) {
+            state.trigger = trigger;
+        }
+        for binding in bindings {
+            self.bind(name, binding);
+        }
+    }
+
+    fn bind(&mut self, name: &str, binding: Binding) {
+        self.bindings
+            .entry(binding.input)
+            .or_insert_with(Vec::new)
+            .push((binding.mods, name.to_string()));
+    }
+
+    fn release_all(&mut self) {
+        self.active.clear();
+        for state in self.actions.values_mut() {
+            state.held = 0;
+            state.pressed = false;
+        }
+    }
+
+    pub fn process_all_states(&mut self, cam: &mut Camera) {
+        for (_, state) in self.actions.iter_mut() {
+            state.process(cam);
+        }
+    }
+
+    pub fn set_state(&mut self, input: Input, mods: Modifiers, action: Action) {
+        match action {
+            Action::Press => {
+                let mut pressed = Vec::new();
+                if let Some(entries) = self.bindings.get(&input) {
+                    //Only the bindings with the most modifiers that are all held fire, so
+                    //Ctrl+S doesn't fire S as well
+                    let matching = || entries.iter().filter(|(m, _)| mods.contains(*m));
+                    let most = matching().map(|(m, _)| m.bits().count_ones()).max();
+                    for (binding_mods, name) in matching() {
+                        if Some(binding_mods.bits().count_ones()) != most {
+                            continue;
+                        }
+                        if let Some(state) = self.actions.get_mut(name) {
+                            state.held += 1;
+                            state.pressed = true;
+                            pressed.push(name.clone());
+                        }
+                    }
+                }
+                self.active.insert(input, pressed);
+            }
+            Action::Release => {
+                if let Some(names) = self.active.remove(&input) {
+                    for name in names {
+                        if let Some(state) = self.actions.get_mut(&name) {
+                            state.held = state.held.saturating_sub(1);
+                        }
+                    }
+                }
+            }
+            Action::Repeat => {}
+        }
+    }
+}
+
+pub struct CursorState {
+    last_x: f32,
+    last_y: f32,
+    sensitivity: f32,
+}
+
+impl CursorState {
+    pub fn new(x: f32, y: f32, sensitivity: f32) -> CursorState {
+        CursorState {
+            last_x: x,
+            last_y: y,
+            sensitivity,
+        }
+    }
+
+    pub fn process(&mut self, x: f32, y: f32, cam: &mut Camera) {
+        let x_offset = (x - self.last_x) * self.sensitivity * cam.delta_time as f32;
+        let y_offset = (self.last_y - y) * self.sensitivity * cam.delta_time as f32;
+        self.last_x = x;
+        self.last_y = y;
+
+        cam.rotate(x_offset, y_offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glfw::Key;
+
+    #[test]
+    fn test_most_specific_binding() {
+        let mut keys = KeyState::new();
+        keys.add_action("back", |_: &mut Camera| {});
+        keys.add_action("save", |_: &mut Camera| {});
+        let s = Input::Key(Key::S);
+        keys.bind("back", bindings::parse_binding("S").unwrap());
+        keys.bind("save", bindings::parse_binding("Ctrl+S").unwrap());
+        let held = |keys: &KeyState| (keys.actions["back"].held, keys.actions["save"].held);
+
+        keys.set_state(s, Modifiers::Control, Action::Press);
+        assert_eq!(held(&keys), (0, 1));
+        keys.set_state(s, Modifiers::Control, Action::Release);
+        //Modifiers without a binding of their own still fire the plain one
+        keys.set_state(s, Modifiers::Shift, Action::Press);
+        assert_eq!(held(&keys), (1, 0));
+    }
+
+    #[test]
+    fn test_rebind() {
+        let mut keys = KeyState::new();
+        keys.add_action("back", |_: &mut Camera| {});
+        keys.bind("back", bindings::parse_binding("S").unwrap());
+        let new = bindings::parse_binding("B").unwrap();
+        keys.rebind("back", Trigger::Press, vec![new]);
+
+        keys.set_state(Input::Key(Key::S), Modifiers::empty(), Action::Press);
+        assert_eq!(keys.actions["back"].held, 0);
+        keys.set_state(Input::Key(Key::B), Modifiers::empty(), Action::Press);
+        assert_eq!(keys.actions["back"].held, 1);
+        assert!(keys.actions["back"].pressed);
+    }
+}
@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+
+use glfw::{Action, Modifiers};
+use log::error;
+
+use super::bindings::{self, Input};
+use super::InputEvent;
+
+const HEADER: &str = "# voxel-render input recording v1";
+
+#[derive(Debug)]
+pub enum RecordError {
+    Io(String, io::Error),
+    Parse { line: usize, msg: String },
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::Io(path, err) => write!(f, "failed to access {}: {}", path, err),
+            RecordError::Parse { line, msg } => write!(f, "line {}: {}", line, msg),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+//A frame marks the start of a main loop iteration, every event up to the next frame belongs to it
+#[derive(Debug, PartialEq)]
+pub struct RecordedFrame {
+    pub tick: u32,
+    pub delta_time: f64,
+    pub events: Vec<InputEvent>,
+}
+
+pub struct InputRecorder {
+    out: BufWriter<File>,
+}
+
+impl InputRecorder {
+    pub fn create(path: &str) -> Result<InputRecorder, RecordError> {
+        let file = File::create(path).map_err(|e| RecordError::Io(path.to_string(), e))?;
+        let mut out = BufWriter::new(file);
+        writeln!(out, "{}", HEADER).map_err(|e| RecordError::Io(path.to_string(), e))?;
+        Ok(InputRecorder { out })
+    }
+
+    pub fn record_frame(&mut self, tick: u32, delta_time: f64, events: &[InputEvent]) {
+        let mut lines = vec![format!("{} frame {}", tick, delta_time)];
+        for event in events {
+            if let Some(line) = format_event(event) {
+                lines.push(format!("{} {}", tick, line));
+            }
+        }
+        for line in lines {
+            if let Err(err) = writeln!(self.out, "{}", line) {
+                error!("Failed to write input recording: {}", err);
+                return;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(err) = self.out.flush() {
+            error!("Failed to flush input recording: {}", err);
+        }
+    }
+}
+
+pub struct InputReplay {
+    frames: VecDeque<RecordedFrame>,
+}
+
+impl InputReplay {
+    pub fn load(path: &str) -> Result<InputReplay, RecordError> {
+        let src = fs::read_to_string(path).map_err(|e| RecordError::Io(path.to_string(), e))?;
+        InputReplay::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<InputReplay, RecordError> {
+        let mut frames: VecDeque<RecordedFrame> = VecDeque::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: &str| RecordError::Parse {
+                line: i + 1,
+                msg: msg.to_string(),
+            };
+            let mut parts = line.split_whitespace();
+            let tick = parts
+                .next()
+                .and_then(|t| t.parse::<u32>().ok())
+                .ok_or_else(|| err("missing tick"))?;
+            let kind = parts.next().ok_or_else(|| err("missing event type"))?;
+            let args: Vec<&str> = parts.collect();
+            if kind == "frame" {
+                let delta_time = args
+                    .get(0)
+                    .and_then(|d| d.parse::<f64>().ok())
+                    .ok_or_else(|| err("invalid frame delta time"))?;
+                frames.push_back(RecordedFrame {
+                    tick,
+                    delta_time,
+                    events: Vec::new(),
+                });
+            } else {
+                let event = parse_event(kind, &args).ok_or_else(|| err("invalid event"))?;
+                match frames.back_mut() {
+                    Some(frame) => frame.events.push(event),
+                    None => return Err(err("event before first frame")),
+                }
+            }
+        }
+        Ok(InputReplay { frames })
+    }
+
+    pub fn next_frame(&mut self) -> Option<RecordedFrame> {
+        self.frames.pop_front()
+    }
+}
+
+fn action_name(action: Action) -> &'static str {
+    match action {
+        Action::Press => "press",
+        Action::Release => "release",
+        Action::Repeat => "repeat",
+    }
+}
+
+fn parse_action(name: &str) -> Option<Action> {
+    match name {
+        "press" => Some(Action::Press),
+        "release" => Some(Action::Release),
+        "repeat" => Some(Action::Repeat),
+        _ => None,
+    }
+}
+
+//Keys without a name can't be bound to anything, so they are left out of the recording
+fn format_event(event: &InputEvent) -> Option<String> {
+    match event {
+        InputEvent::Button(Input::Key(key), mods, action) => Some(format!(
+            "key {} {} {}",
+            bindings::key_name(*key)?,
+            action_name(*action),
+            mods.bits()
+        )),
+        InputEvent::Button(Input::Mouse(button), mods, action) => Some(format!(
+            "mouse {} {} {}",
+            bindings::mouse_button_name(*button),
+            action_name(*action),
+            mods.bits()
+        )),
+        InputEvent::Cursor(x, y) => Some(format!("cursor {} {}", x, y)),
+    }
+}
+
+fn parse_event(kind: &str, args: &[&str]) -> Option<InputEvent> {
+    match (kind, args) {
+        ("key", [name, action, mods]) | ("mouse", [name, action, mods]) => {
+            let input = if kind == "key" {
+                Input::Key(bindings::parse_key(name)?)
+            } else {
+                Input::Mouse(bindings::parse_mouse_button(name)?)
+            };
+            let mods = Modifiers::from_bits_truncate(mods.parse().ok()?);
+            Some(InputEvent::Button(input, mods, parse_action(action)?))
+        }
+        ("cursor", [x, y]) => Some(InputEvent::Cursor(x.parse().ok()?, y.parse().ok()?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glfw::{Key, MouseButton};
+
+    #[test]
+    fn test_events_round_trip() {
+        let events = vec![
+            InputEvent::Button(Input::Key(Key::W), Modifiers::Shift, Action::Press),
+            InputEvent::Button(
+                Input::Mouse(MouseButton::Button1),
+                Modifiers::empty(),
+                Action::Release,
+            ),
+            InputEvent::Cursor(1280.5, 719.25),
+        ];
+        let mut src = format!("{}\n7 frame 0.016666666666666666\n", HEADER);
+        for event in events.iter() {
+            src.push_str(&format!("7 {}\n", format_event(event).unwrap()));
+        }
+        src.push_str("8 frame 0.017\n");
+
+        let mut replay = InputReplay::parse(&src).unwrap();
+        let first = replay.next_frame().unwrap();
+        assert_eq!(first.tick, 7);
+        assert_eq!(first.delta_time, 1.0 / 60.0);
+        assert_eq!(first.events, events);
+        assert!(replay.next_frame().unwrap().events.is_empty());
+        assert!(replay.next_frame().is_none());
+    }
+
+    #[test]
+    fn test_event_before_frame_is_rejected() {
+        match InputReplay::parse("3 cursor 1 2") {
+            Err(RecordError::Parse { line, .. }) => assert_eq!(line, 1),
+            _ => panic!("expected parse error"),
+        }
+    }
+}
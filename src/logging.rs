@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use serde::Deserialize;
+
+//Overrides the filter in the config file, e.g. VOXEL_LOG=info,chunk_gen=debug
+pub const FILTER_ENV: &str = "VOXEL_LOG";
+//Overrides json_file in the config file
+pub const JSON_FILE_ENV: &str = "VOXEL_LOG_JSON";
+
+const CRATE_NAME: &str = "voxel_render";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Debug)]
+pub enum LogError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    InvalidLevel(String),
+    AlreadyInitialized,
+}
+
+impl fmt::Display for LogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogError::Io(path, err) => write!(f, "failed to open {}: {}", path, err),
+            LogError::Parse(err) => write!(f, "invalid logging config: {}", err),
+            LogError::InvalidLevel(level) => write!(f, "unknown log level \"{}\"", level),
+            LogError::AlreadyInitialized => write!(f, "logging is already initialized"),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+//Subsystem a log target belongs to. Targets default to the module path, so
+//voxel_render::render::scene logs as render and the crate root as main
+pub fn subsystem(target: &str) -> &str {
+    let mut parts = target.split("::");
+    match (parts.next(), parts.next()) {
+        (Some(CRATE_NAME), Some(module)) => module,
+        (Some(CRATE_NAME), None) => "main",
+        (Some(other), _) => other,
+        (None, _) => "main",
+    }
+}
+
+//A default level and levels for single subsystems, parsed from "info,chunk_gen=debug,render=off"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    default: LevelFilter,
+    subsystems: HashMap<String, LevelFilter>,
+}
+
+impl Filter {
+    pub fn parse(spec: &str) -> Result<Filter, LogError> {
+        let level = |s: &str| {
+            LevelFilter::from_str(s.trim()).map_err(|_| LogError::InvalidLevel(s.to_string()))
+        };
+        let mut filter = Filter {
+            default: LevelFilter::Info,
+            subsystems: HashMap::new(),
+        };
+        for part in spec.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            match part.find('=') {
+                Some(i) => {
+                    let name = part[..i].trim().to_string();
+                    filter.subsystems.insert(name, level(&part[i + 1..])?);
+                }
+                None => filter.default = level(part)?,
+            }
+        }
+        Ok(filter)
+    }
+
+    pub fn level(&self, subsystem: &str) -> LevelFilter {
+        *self.subsystems.get(subsystem).unwrap_or(&self.default)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.subsystems
+            .values()
+            .fold(self.default, |max, level| max.max(*level))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub filter: String,
+    pub console: bool,
+    pub json_file: Option<String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            filter: "info".to_string(),
+            console: true,
+            json_file: None,
+        }
+    }
+}
+
+impl LogConfig {
+    pub fn load(path: &str) -> Result<LogConfig, LogError> {
+        let src = fs::read_to_string(path).map_err(|e| LogError::Io(path.to_string(), e))?;
+        toml::from_str(&src).map_err(LogError::Parse)
+    }
+
+    //Environment variables override the file, a missing file is the default config. Errors
+    //are returned with the config to use so they can be logged once logging is up
+    pub fn load_with_env(path: &str) -> (LogConfig, Option<LogError>) {
+        let (mut config, err) = match LogConfig::load(path) {
+            Ok(config) => (config, None),
+            Err(LogError::Io(_, ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                (LogConfig::default(), None)
+            }
+            Err(e) => (LogConfig::default(), Some(e)),
+        };
+        if let Ok(filter) = env::var(FILTER_ENV) {
+            config.filter = filter;
+        }
+        if let Ok(path) = env::var(JSON_FILE_ENV) {
+            config.json_file = Some(path);
+        }
+        (config, err)
+    }
+}
+
+//One line of the JSON log file
+pub fn json_line(
+    time: Duration,
+    level: Level,
+    subsystem: &str,
+    message: &str,
+    duration: Option<Duration>,
+) -> String {
+    let mut entry = serde_json::json!({
+        "time": time.as_secs_f64(),
+        "level": level.as_str(),
+        "target": subsystem,
+        "message": message,
+    });
+    if let Some(duration) = duration {
+        entry["duration_ms"] = serde_json::json!(duration.as_secs_f64() * 1000.0);
+    }
+    entry.to_string()
+}
+
+pub struct Logger {
+    filter: Filter,
+    console: bool,
+    json: Option<Mutex<BufWriter<File>>>,
+    start: Instant,
+}
+
+impl Logger {
+    pub fn new(config: &LogConfig) -> Result<Logger, LogError> {
+        let json = match config.json_file.as_ref() {
+            Some(path) => {
+                let file = File::create(path).map_err(|e| LogError::Io(path.clone(), e))?;
+                Some(Mutex::new(BufWriter::new(file)))
+            }
+            None => None,
+        };
+        Ok(Logger {
+            filter: Filter::parse(&config.filter)?,
+            console: config.console,
+            json,
+            start: Instant::now(),
+        })
+    }
+
+    fn enabled_for(&self, level: Level, subsystem: &str) -> bool {
+        level <= self.filter.level(subsystem)
+    }
+
+    fn write(&self, level: Level, subsystem: &str, message: &str, duration: Option<Duration>) {
+        let time = self.start.elapsed();
+        if self.console {
+            let message = match duration {
+                Some(d) => format!("{} took {:.3} ms", message, d.as_secs_f64() * 1000.0),
+                None => message.to_string(),
+            };
+            println!(
+                "[{:>9.3}s {:<5} {}] {}",
+                time.as_secs_f64(),
+                level,
+                subsystem,
+                message
+            );
+        }
+        if let Some(json) = self.json.as_ref() {
+            let line = json_line(time, level, subsystem, message, duration);
+            let mut json = json.lock().unwrap();
+            //A log that can't be written has nowhere to report to
+            let _ = writeln!(json, "{}", line);
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.enabled_for(metadata.level(), subsystem(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let message = record.args().to_string();
+            self.write(record.level(), subsystem(record.target()), &message, None);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(json) = self.json.as_ref() {
+            let _ = json.lock().unwrap().flush();
+        }
+    }
+}
+
+//Installs the logger behind the log macros
+pub fn init(config: &LogConfig) -> Result<(), LogError> {
+    let logger = Logger::new(config)?;
+    let max_level = logger.filter.max_level();
+    LOGGER
+        .set(logger)
+        .map_err(|_| LogError::AlreadyInitialized)?;
+    log::set_logger(LOGGER.get().unwrap()).map_err(|_| LogError::AlreadyInitialized)?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+//Times the scope it lives in and logs the duration at trace level when dropped, create with
+//span!("name") so the target is the calling module. With the profiling feature every span is
+//also recorded for the trace file
+pub struct Span {
+    target: &'static str,
+    name: &'static str,
+    //None when nothing would record the span
+    start: Option<Instant>,
+}
+
+impl Span {
+    //Without the profiling feature and below trace level a span only costs the level check
+    pub fn new(target: &'static str, name: &'static str) -> Span {
+        let recorded = cfg!(feature = "profiling") || log::max_level() >= LevelFilter::Trace;
+        Span {
+            target,
+            name,
+            start: if recorded { Some(Instant::now()) } else { None },
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        let duration = start.elapsed();
+        #[cfg(feature = "profiling")]
+        crate::profiling::record(self.target, self.name, start, duration);
+        if let Some(logger) = LOGGER.get() {
+            let subsystem = subsystem(self.target);
+            if logger.enabled_for(Level::Trace, subsystem) {
+                logger.write(Level::Trace, subsystem, self.name, Some(duration));
+            }
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! span {
+    ($name:expr) => {
+        $crate::logging::Span::new(module_path!(), $name)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsystem() {
+        assert_eq!(subsystem("voxel_render::render::scene"), "render");
+        assert_eq!(subsystem("voxel_render::chunk_gen"), "chunk_gen");
+        assert_eq!(subsystem("voxel_render"), "main");
+        assert_eq!(subsystem("input"), "input");
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = Filter::parse("warn, chunk_gen=debug,render=off").unwrap();
+        assert_eq!(filter.level("chunk_gen"), LevelFilter::Debug);
+        assert_eq!(filter.level("render"), LevelFilter::Off);
+        assert_eq!(filter.level("input"), LevelFilter::Warn);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+        assert_eq!(Filter::parse("").unwrap().level("main"), LevelFilter::Info);
+        assert!(Filter::parse("render=loud").is_err());
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line = json_line(
+            Duration::from_millis(1500),
+            Level::Warn,
+            "render",
+            "a \"quoted\" message",
+            Some(Duration::from_micros(2500)),
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["time"], 1.5);
+        assert_eq!(value["level"], "WARN");
+        assert_eq!(value["target"], "render");
+        assert_eq!(value["message"], "a \"quoted\" message");
+        assert_eq!(value["duration_ms"], 2.5);
+        assert!(!line.contains('\n'));
+    }
+
+    //Tests run without a logger, so the max level stays off
+    #[cfg(not(feature = "profiling"))]
+    #[test]
+    fn test_span_off() {
+        assert!(crate::span!("off").start.is_none());
+    }
+}
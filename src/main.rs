@@ -9,59 +9,88 @@ extern crate dashmap;
 extern crate gl;
 extern crate glfw;
 extern crate image;
+extern crate log;
 extern crate nalgebra_glm as glm;
-
-extern crate flame;
-
-extern crate flamer;
-
-use flame as f;
-use flamer::flame;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
 
 use glfw::{Action, Context, Key};
 use glm::{Vec2, Vec3};
+use log::{error, info, warn};
 
-use std::ffi::CString;
-use std::fs::File;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::sync::mpsc::Receiver;
 use std::sync::{mpsc, Arc, RwLock};
 
 mod chunk_gen;
 mod chunk_updater;
+mod cli;
+mod config;
 mod consts;
 mod geom;
+mod heightmap;
 mod input;
+mod logging;
+mod metrics;
+#[cfg(feature = "profiling")]
+mod profiling;
+mod region;
 mod render;
 mod shader;
+mod structures;
 mod texture;
+mod voxel_defs;
 mod voxel_registry;
 mod world;
+mod world_factory;
 
 use chunk_gen::ChunkGen;
 use chunk_updater::ChunkTicket;
 use chunk_updater::ChunkUpdater;
+use cli::{Cli, Command, RunOptions};
+use config::{Config, WorldConfig};
 use geom::ChunkKey;
+use input::bindings;
+use input::bindings::Input;
+use input::record::{InputRecorder, InputReplay};
 use input::CursorState;
+use input::InputEvent;
 use input::KeyState;
+use logging::LogConfig;
+use metrics::{Metrics, MetricsConfig, MetricsExporter};
+use region::Region;
+use render::camera_path::{CameraPath, PathPlayback};
+use render::capture;
+use render::hud::{FrameTimer, Hud, HudStats};
+use render::post::PostConfig;
+use render::scene::Scene;
+use render::sun::SunConfig;
 use render::Camera;
 use render::ChunkRender;
-use shader::Shader;
-use texture::generate_texture;
-use voxel_registry::Material;
-use voxel_registry::VoxelReg;
-use world::FlatWorldType;
+use structures::{Placement, Structures, Template};
+use texture::{generate_texture_array, load_layers};
+use voxel_registry::{IdTable, IdTableError, VoxelReg};
+use world::RegionWorldType;
 use world::World;
 use world::WorldRegistry;
+use world::WorldType;
 use world::WorldTypeRegistry;
 
-const SCREEN_WIDTH: u32 = 2560;
-const SCREEN_HEIGHT: u32 = 1440;
-const GL_MAJOR_VERSION: u32 = 4;
-const GL_MINOR_VERSION: u32 = 4;
 const WINDOW_NAME: &'static str = "Voxel Renderer";
-
-const VOXEL_SIZE: f32 = 1.0;
-const CHUNK_SIZE: usize = 16;
+const KEY_BINDINGS_PATH: &str = "config/bindings.toml";
+const VOXEL_DEFINITIONS_PATH: &str = "config/voxels.toml";
+const VOXEL_ID_TABLE_PATH: &str = "config/voxel_ids.toml";
+const SUN_CONFIG_PATH: &str = "config/sun.toml";
+const POST_CONFIG_PATH: &str = "config/post.toml";
+const LOG_CONFIG_PATH: &str = "config/logging.toml";
+const METRICS_CONFIG_PATH: &str = "config/metrics.toml";
+#[cfg(feature = "profiling")]
+const PROFILE_PATH: &str = "profile.json";
+const DEFAULT_CAMERA_POSITION: [f32; 3] = [0.0, 5.0, 0.0];
+const FRAME_DUMP_FPS: f64 = 60.0;
+const SCREENSHOT_DIR: &str = "screenshots";
 
 #[derive(Clone)]
 pub struct SharedState {
@@ -73,24 +102,321 @@ pub struct SharedState {
     cam_chunk_pos: Arc<RwLock<Vec3>>,
     clear_render: Arc<RwLock<bool>>,
     chunk_size: Arc<usize>,
+    metrics: Arc<Metrics>,
 }
 
-#[flame]
 fn main() {
+    #[cfg(feature = "profiling")]
+    profiling::init();
+    let (log_config, log_config_err) = LogConfig::load_with_env(LOG_CONFIG_PATH);
+    if let Err(e) = logging::init(&log_config) {
+        println!("Failed to start logging, {}", e);
+    }
+    if let Some(e) = log_config_err {
+        warn!("Using the default logging config, {}", e);
+    }
+    let cli = match Cli::from_args() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Command::Help = cli.command {
+        println!("{}", cli::USAGE);
+        return;
+    }
+    let config = Config::load(&cli.config, &cli.overrides).unwrap_or_else(|e| {
+        error!("{}", e);
+        log::logger().flush();
+        std::process::exit(1);
+    });
+    let voxreg = load_voxel_registry();
+    let world_types = WorldTypeRegistry::with_builtin();
+    let chunk_size = config.world.chunk_size;
+
+    match cli.command {
+        Command::Run(options) => {
+            let world_type = config_world_type(&world_types, &config.world, chunk_size, &voxreg);
+            run(&config, &options, voxreg, world_types, world_type);
+        }
+        Command::Import(path, options) => match Region::load(&path) {
+            Ok(region) => {
+                for entry in region.palette().iter() {
+                    if entry.to_key(&voxreg).is_none() {
+                        warn!("{} has unknown voxel type {}", path, entry.string_id);
+                    }
+                }
+                let world_type = world_types
+                    .register_world_type(Box::new(RegionWorldType { chunk_size, region }));
+                run(&config, &options, voxreg, world_types, world_type);
+            }
+            Err(e) => error!("Failed to import {}", e),
+        },
+        Command::Export(options) => {
+            let id = config_world_type(&world_types, &config.world, chunk_size, &voxreg);
+            let world_type = world_types
+                .get(&id)
+                .expect("world type was just registered");
+            let region = Region::generate(
+                world_type.as_ref(),
+                &voxreg,
+                chunk_size,
+                options.from,
+                options.to,
+            );
+            match region.and_then(|region| region.save(&options.path)) {
+                Ok(()) => info!(
+                    "Exported chunks {:?} to {:?} to {}",
+                    options.from, options.to, options.path
+                ),
+                Err(e) => error!("Failed to export: {}", e),
+            }
+        }
+        Command::Help => {}
+    }
+
+    #[cfg(feature = "profiling")]
+    match profiling::write(PROFILE_PATH) {
+        Ok(()) => info!("Saved profile {}", PROFILE_PATH),
+        Err(e) => error!("Failed to save profile {}: {}", PROFILE_PATH, e),
+    }
+    log::logger().flush();
+}
+
+//For errors that stop startup, logged so they reach the log file too
+fn exit_with_error(message: String) -> ! {
+    error!("{}", message);
+    log::logger().flush();
+    std::process::exit(1);
+}
+
+//Registers the world type a world's config selects, exits when the type or its parameters are
+//invalid
+fn config_world_type(
+    world_types: &WorldTypeRegistry,
+    world: &WorldConfig,
+    chunk_size: usize,
+    voxreg: &VoxelReg,
+) -> u64 {
+    info!(
+        "World {} of type {} with seed {}",
+        world.name, world.world_type, world.seed
+    );
+    let id = world_types.instantiate(
+        &world.world_type,
+        world.seed,
+        chunk_size,
+        &world.params,
+        voxreg,
+    );
+    id.unwrap_or_else(|e| {
+        error!(
+            "World {}: {}, world types are {}",
+            world.name,
+            e,
+            world_types.factory_names().join(", ")
+        );
+        log::logger().flush();
+        std::process::exit(1);
+    })
+}
+
+//Structure templates of a world's config, ones that fail to load are left out
+fn load_structures(world_config: &WorldConfig, voxreg: &VoxelReg) -> Option<Structures> {
+    let mut placements = Vec::new();
+    for structure in world_config.structures.iter() {
+        let on = match structure
+            .on
+            .as_ref()
+            .map(|id| voxreg.key_from_string_id(id))
+        {
+            Some(None) => {
+                warn!(
+                    "Structure {} stands on an unknown voxel type",
+                    structure.template
+                );
+                continue;
+            }
+            on => on.flatten(),
+        };
+        match Template::load(structure, voxreg) {
+            Ok(template) => placements.push(Placement {
+                template: Arc::new(template),
+                per_chunk: structure.per_chunk,
+                on,
+            }),
+            Err(e) => warn!("Leaving out structure {}, {}", structure.template, e),
+        }
+    }
+    if placements.is_empty() {
+        None
+    } else {
+        Some(Structures {
+            seed: world_config.seed,
+            placements,
+        })
+    }
+}
+
+//Creates a world of a registered world type, runs the type's init hook and queues its portals
+fn create_world(
+    state: (&WorldTypeRegistry, &WorldRegistry),
+    world_config: &WorldConfig,
+    world_type: u64,
+    chunk_size: usize,
+    voxreg: &VoxelReg,
+) -> u64 {
+    let (world_types, worlds) = state;
+    let mut world = World::new(
+        &world_config.name,
+        chunk_size,
+        world_type,
+        world_config.scale,
+    );
+    world.structures = load_structures(world_config, voxreg);
+    if let Some(world_type) = world_types.get(&world_type) {
+        world_type.init_world(&world, voxreg);
+    }
+    place_portals(&world, world_config, voxreg);
+    worlds
+        .new_world(world)
+        .unwrap_or_else(|e| exit_with_error(e.to_string()))
+}
+
+//Queues the portal voxels of a world's config, they're written when their chunks generate
+fn place_portals(world: &World, world_config: &WorldConfig, voxreg: &VoxelReg) {
+    for portal in world_config.portals.iter() {
+        match voxreg.key_from_string_id(&portal.voxel) {
+            Some(voxel) => {
+                if let Err(e) = world.place_voxel(portal.position, voxel, voxreg) {
+                    warn!("Failed to place portal, {}", e);
+                }
+            }
+            None => warn!(
+                "Portal in world {} has unknown voxel type {}",
+                world.name, portal.voxel
+            ),
+        }
+    }
+}
+
+//The world after from in the registry, wrapping around to the first
+fn world_after(state: &SharedState, from: u64) -> Option<u64> {
+    let ids = state.world_registry.ids();
+    let next = ids.iter().position(|id| *id == from).map_or(0, |i| i + 1);
+    ids.get(next % ids.len().max(1)).copied()
+}
+
+//Removes a world and unregisters its world type, switching to the next world first when it's
+//the active one. The world type is returned so the world can be created again
+fn remove_world(
+    state: &SharedState,
+    renderer: &mut ChunkRender,
+    cam: &mut Camera,
+    id: u64,
+) -> Option<Arc<dyn WorldType>> {
+    let next = match world_after(state, id).filter(|next| *next != id) {
+        Some(next) => next,
+        None => {
+            warn!("The only world can't be removed");
+            return None;
+        }
+    };
+    if *state.active_world.read().unwrap() == id {
+        switch_world(state, renderer, cam, next);
+    }
+    let removed = state.world_registry.remove_world(&id).and_then(|world| {
+        let world_type = state
+            .world_type_registry
+            .unregister_world_type(&world.world_type)?;
+        info!("Removed world {}", world.name);
+        Ok(world_type)
+    });
+    removed
+        .map_err(|e| warn!("Failed to remove world, {}", e))
+        .ok()
+}
+
+//Name of the world a portal voxel at the camera leads to
+fn portal_at(state: &SharedState, world_id: u64, pos: &Vec3) -> Option<String> {
+    let world = state.world_registry.get(&world_id)?;
+    let voxel = world.voxel([pos.x, pos.y, pos.z].map(|p| p.floor() as i32))?;
+    let target = state.voxel_registry.property(&voxel, "portal")?;
+    target.as_str().map(|name| name.to_string())
+}
+
+//Makes a world active, the camera moves to the matching position by the ratio of the worlds'
+//scales and the chunks of the old world are dropped
+fn switch_world(state: &SharedState, renderer: &mut ChunkRender, cam: &mut Camera, to: u64) {
+    let from = *state.active_world.read().unwrap();
+    let to_world = match state.world_registry.get(&to) {
+        Some(to_world) => to_world,
+        None => return,
+    };
+    //A removed active world can still be left, the camera keeps its position then
+    let ratio = state
+        .world_registry
+        .get(&from)
+        .map_or(1.0, |from_world| to_world.scale / from_world.scale);
+    cam.pos *= ratio;
+    *state.active_world.write().unwrap() = to;
+    *state.cam_chunk_pos.write().unwrap() = cam.chunk_pos(*state.chunk_size);
+    *state.clear_render.write().unwrap() = true;
+    renderer.reset();
+    info!("Switched to world {}", to_world.name);
+}
+
+fn load_voxel_registry() -> VoxelReg {
+    let mut voxreg = VoxelReg::new();
+    match IdTable::load(VOXEL_ID_TABLE_PATH) {
+        Ok(table) => voxreg.reserve_ids(&table),
+        Err(IdTableError::Io(_, ref e)) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("Ignoring voxel id table, {}", e),
+    }
+    voxel_defs::load(VOXEL_DEFINITIONS_PATH, &mut voxreg)
+        .unwrap_or_else(|e| exit_with_error(e.to_string()));
+    for required in [consts::OPAQUE_VOXEL, consts::TRANSPARENT_VOXEL].iter() {
+        if voxreg.key_from_string_id(required).is_none() {
+            exit_with_error(format!(
+                "{} must define the voxel type {}",
+                VOXEL_DEFINITIONS_PATH, required
+            ));
+        }
+    }
+    if let Err(e) = voxreg.id_table().save(VOXEL_ID_TABLE_PATH) {
+        error!("{}", e);
+    }
+    voxreg
+}
+
+fn run(
+    config: &Config,
+    options: &RunOptions,
+    voxreg: VoxelReg,
+    world_type_reg: WorldTypeRegistry,
+    world_type: u64,
+) {
+    let chunk_size = config.world.chunk_size;
+    let streaming = &config.streaming;
+
     //GLFW init
     let mut glfw = glfw::init(glfw::FAIL_ON_ERRORS).unwrap();
     glfw.window_hint(glfw::WindowHint::ContextVersion(
-        GL_MAJOR_VERSION,
-        GL_MINOR_VERSION,
+        config.window.gl_major,
+        config.window.gl_minor,
     ));
     glfw.window_hint(glfw::WindowHint::OpenGlProfile(
         glfw::OpenGlProfileHint::Core,
     ));
+    if options.headless {
+        glfw.window_hint(glfw::WindowHint::Visible(false));
+    }
 
     let (mut window, events) = glfw
         .create_window(
-            SCREEN_WIDTH,
-            SCREEN_HEIGHT,
+            config.window.width,
+            config.window.height,
             WINDOW_NAME,
             glfw::WindowMode::Windowed,
         )
@@ -98,6 +424,7 @@ fn main() {
 
     window.make_current();
     window.set_key_polling(true);
+    window.set_mouse_button_polling(true);
     window.set_framebuffer_size_polling(true);
     window.set_cursor_mode(glfw::CursorMode::Disabled);
     window.set_cursor_pos_polling(true);
@@ -105,70 +432,117 @@ fn main() {
     //GL init
     gl::load_with(|symbol| window.get_proc_address(symbol) as *const _);
 
-    let program = Shader::new("src/shaders/raybox.vert", "src/shaders/colored.frag");
-
     //Setings init
-    let screen_size = Vec2::new(SCREEN_WIDTH as f32, SCREEN_HEIGHT as f32);
+    let (width, height) = (config.window.width as f32, config.window.height as f32);
+    let screen_size = Vec2::new(width, height);
+
+    let world_reg = WorldRegistry::new();
+    let registries = (&world_type_reg, &world_reg);
+    let active_world = create_world(registries, &config.world, world_type, chunk_size, &voxreg);
+    for world_config in config.worlds.iter() {
+        let world_type = config_world_type(&world_type_reg, world_config, chunk_size, &voxreg);
+        create_world(registries, world_config, world_type, chunk_size, &voxreg);
+    }
+
+    let spawn = world_type_reg
+        .get(&world_type)
+        .and_then(|t| t.spawn_point());
+    let [x, y, z] = config
+        .camera
+        .position
+        .or(spawn)
+        .unwrap_or(DEFAULT_CAMERA_POSITION);
     let mut cam = Camera::new(
-        glm::vec3(0.0, 5.0, 0.0),
+        glm::vec3(x, y, z),
         glm::vec3(0.0, 1.0, 0.0),
-        20.0,
-        70.0,
-        0.001,
-        1000.0,
-        SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
-    );
-    let mut voxreg = VoxelReg::new();
-    voxreg.register_voxel_type(
-        consts::OPAQUE_VOXEL,
-        false,
-        Material {
-            ambient: Vec3::new(1.0, 1.0, 1.0),
-            diffuse: Vec3::new(0.8, 0.8, 0.8),
-            specular: Vec3::new(0.5, 0.8, 0.1),
-            shininess: 0.1,
-        },
+        config.camera.speed,
+        config.camera.fov,
+        config.camera.near,
+        config.camera.far,
+        width / height,
     );
-    voxreg.register_voxel_type(
-        consts::TRANSPARENT_VOXEL,
-        true,
-        Material {
-            ambient: Vec3::new(0.0, 0.0, 0.0),
-            diffuse: Vec3::new(0.0, 0.0, 0.0),
-            specular: Vec3::new(0.0, 0.0, 0.0),
-            shininess: 0.0,
-        },
-    );
-
-    let mut world_type_reg = WorldTypeRegistry::new();
-    world_type_reg.register_world_type(Box::new(FlatWorldType {
-        chunk_size: CHUNK_SIZE,
-    }));
 
-    let mut world_reg = WorldRegistry::new();
-    let active_world = world_reg.new_world(World::new(true, CHUNK_SIZE, 1));
+    let (texture_layers, texture_errors) = load_layers(voxreg.texture_paths());
+    for err in texture_errors.iter() {
+        warn!("{}", err);
+    }
 
     let shared_state = SharedState {
         voxel_registry: Arc::new(voxreg),
         world_type_registry: Arc::new(world_type_reg),
         world_registry: Arc::new(world_reg),
         tick: Arc::new(RwLock::new(1)),
-        cam_chunk_pos: Arc::new(RwLock::new(cam.chunk_pos(CHUNK_SIZE))),
+        cam_chunk_pos: Arc::new(RwLock::new(cam.chunk_pos(chunk_size))),
         active_world: Arc::new(RwLock::new(active_world)),
         clear_render: Arc::new(RwLock::new(true)),
-        chunk_size: Arc::new(CHUNK_SIZE),
+        chunk_size: Arc::new(chunk_size),
+        metrics: Arc::new(Metrics::new()),
     };
 
     //Camera Movement
     let mut keys = KeyState::new();
-    let mut cursor = CursorState::new(SCREEN_WIDTH as f32 / 2.0, SCREEN_HEIGHT as f32 / 2.0, 10.0);
-
-    keys.add_state(Key::W, Camera::move_forward);
-    keys.add_state(Key::A, Camera::move_left);
-    keys.add_state(Key::S, Camera::move_back);
-    keys.add_state(Key::D, Camera::move_right);
-    keys.add_state(Key::Space, Camera::move_up);
-    keys.add_state(Key::LeftShift, Camera::move_down);
+    let mut cursor = CursorState::new(width / 2.0, height / 2.0, config.camera.mouse_sensitivity);
+
+    let reload_bindings = Rc::new(Cell::new(false));
+    let take_screenshot = Rc::new(Cell::new(false));
+    let take_tiled_screenshot = Rc::new(Cell::new(false));
+    let toggle_hud = Rc::new(Cell::new(false));
+    let next_world = Rc::new(Cell::new(false));
+    let remove_active_world = Rc::new(Cell::new(false));
+    let restore_worlds = Rc::new(Cell::new(false));
+    let toggled_passes: Rc<RefCell<Vec<&str>>> = Rc::new(RefCell::new(Vec::new()));
+
+    keys.add_action("move_forward", Camera::move_forward);
+    keys.add_action("move_left", Camera::move_left);
+    keys.add_action("move_back", Camera::move_back);
+    keys.add_action("move_right", Camera::move_right);
+    keys.add_action("move_up", Camera::move_up);
+    keys.add_action("move_down", Camera::move_down);
+    {
+        let reload_bindings = reload_bindings.clone();
+        keys.add_action("reload_bindings", move |_: &mut Camera| {
+            reload_bindings.set(true)
+        });
+    }
+    {
+        let take_screenshot = take_screenshot.clone();
+        keys.add_action("screenshot", move |_: &mut Camera| {
+            take_screenshot.set(true)
+        });
+    }
+    {
+        let take_tiled_screenshot = take_tiled_screenshot.clone();
+        keys.add_action("screenshot_tiled", move |_: &mut Camera| {
+            take_tiled_screenshot.set(true)
+        });
+    }
+    {
+        let toggle_hud = toggle_hud.clone();
+        keys.add_action("toggle_hud", move |_: &mut Camera| toggle_hud.set(true));
+    }
+    {
+        let next_world = next_world.clone();
+        keys.add_action("next_world", move |_: &mut Camera| next_world.set(true));
+    }
+    {
+        let remove_active_world = remove_active_world.clone();
+        keys.add_action("remove_world", move |_: &mut Camera| {
+            remove_active_world.set(true)
+        });
+    }
+    {
+        let restore_worlds = restore_worlds.clone();
+        keys.add_action("restore_worlds", move |_: &mut Camera| {
+            restore_worlds.set(true)
+        });
+    }
+    for pass in render::post::pass_names() {
+        let toggled_passes = toggled_passes.clone();
+        keys.add_action(&format!("toggle_{}", pass), move |_: &mut Camera| {
+            toggled_passes.borrow_mut().push(pass)
+        });
+    }
+    keys.set_bindings(bindings::load_or_default(KEY_BINDINGS_PATH));
 
     //World Gen
     let (tx_chunk_gen, rx_chunk_gen) = mpsc::channel();
@@ -184,52 +558,132 @@ fn main() {
 
     //Render setup
     let mut renderer: ChunkRender;
+    let mut scene: Scene;
+    let mut hud: Hud;
+    let sun_config = SunConfig::load_or_default(SUN_CONFIG_PATH);
+    let post_config = PostConfig::load_or_default(POST_CONFIG_PATH);
+    let voxel_texture = generate_texture_array(&texture_layers);
 
     unsafe {
         renderer = ChunkRender::new(&shared_state, rx_render);
-        program.use_program();
-        gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+        scene = Scene::new(
+            sun_config.shadows.clone(),
+            &post_config,
+            voxel_texture,
+            config.world.voxel_size,
+            render::sky::fog_distance(streaming.load_radius, chunk_size),
+        )
+        .unwrap_or_else(|e| exit_with_error(e.to_string()));
+        hud = Hud::new().unwrap_or_else(|e| exit_with_error(e.to_string()));
         gl::Enable(gl::DEPTH_TEST);
         gl::Enable(gl::PROGRAM_POINT_SIZE);
     }
 
-    generate_texture("src/texture/T_UV_Map.jpg".to_string());
-
-    let ticks_per_second = 20.0;
-    let tick_step = 1.0 / ticks_per_second;
+    let tick_step = 1.0 / streaming.tick_rate;
 
     let mut last_time = 0.0;
     let mut last_ticket_tick = 0;
+    let mut force_ticket = false;
+    //Set while the camera is in a portal, so arriving in one doesn't lead straight back
+    let mut in_portal = false;
+    //Worlds removed while running by name, with their world types
+    let mut removed_worlds: Vec<(String, Arc<dyn WorldType>)> = Vec::new();
+
+    let mut recorder = options.record.as_ref().map(|path| {
+        InputRecorder::create(path)
+            .unwrap_or_else(|e| exit_with_error(format!("Failed to record input: {}", e)))
+    });
+    let mut replay = options.replay.as_ref().map(|path| {
+        InputReplay::load(path)
+            .unwrap_or_else(|e| exit_with_error(format!("Failed to load replay {}: {}", path, e)))
+    });
+
+    let mut path_playback = options.camera_path.as_ref().map(|path| {
+        PathPlayback::new(CameraPath::load(path).unwrap_or_else(|e| {
+            exit_with_error(format!("Failed to load camera path {}: {}", path, e))
+        }))
+    });
+    let mut dumped_frames = 0;
+    let mut frame_timer = FrameTimer::new(glfw.get_time());
+
+    let metrics = shared_state.metrics.clone();
+    let frames = metrics.counter("frames_total", "Frames drawn");
+    let frame_time = metrics.histogram(
+        "frame_seconds",
+        "Time between frames",
+        metrics::TIME_BUCKETS,
+    );
+    let loaded_chunks = metrics.gauge("chunks_loaded", "Chunks in the active world");
+    let vbo_bytes = metrics.gauge("chunk_vbo_bytes", "Bytes of chunk render data on the GPU");
+    let ticket_queue = metrics.gauge(metrics::TICKET_QUEUE, "Chunk tickets waiting");
+    let gen_queue = metrics.gauge(metrics::GEN_QUEUE, "Chunks waiting to be generated");
+    let mut exporter = MetricsExporter::new(
+        metrics.clone(),
+        MetricsConfig::load_or_default(METRICS_CONFIG_PATH),
+    );
+    let start_time = glfw.get_time();
+    let mut last_frame = start_time;
 
     while !window.should_close() {
         {
-            let cur_time = glfw.get_time();
-            cam.update(glfw.get_time());
-
-            if last_time + tick_step <= cur_time {
-                println!(
-                    "cur time: {}, last time {}, step size {}, current step {}",
-                    cur_time,
-                    last_time,
-                    tick_step,
-                    cur_time - last_time
-                );
-                let mut tick = shared_state.tick.write().unwrap();
-                *tick = tick.wrapping_add(1);
+            let _span = span!("frame");
+            let live_events = process_events(&mut window, &events);
+
+            let replay_frame = replay.as_mut().and_then(|r| r.next_frame());
+            if replay.is_some() && replay_frame.is_none() {
+                info!("Input replay finished");
+                replay = None;
+                if options.headless {
+                    window.set_should_close(true);
+                }
+            }
+
+            let frame_events = match replay_frame {
+                Some(frame) => {
+                    cam.update_delta(frame.delta_time);
+                    if frame.tick != *shared_state.tick.read().unwrap() {
+                        *shared_state.tick.write().unwrap() = frame.tick;
+                        *shared_state.cam_chunk_pos.write().unwrap() = cam.chunk_pos(chunk_size);
+                    }
+                    frame.events
+                }
+                None => {
+                    let cur_time = glfw.get_time();
+                    if path_playback.is_some() && options.dump_frames.is_some() {
+                        cam.update_delta(1.0 / FRAME_DUMP_FPS);
+                    } else {
+                        cam.update(glfw.get_time());
+                    }
+
+                    if last_time + tick_step <= cur_time {
+                        let mut tick = shared_state.tick.write().unwrap();
+                        *tick = tick.wrapping_add(1);
 
-                let mut cam_chunk_pos = shared_state.cam_chunk_pos.write().unwrap();
-                *cam_chunk_pos = cam.chunk_pos(CHUNK_SIZE);
-                last_time = cur_time;
+                        let mut cam_chunk_pos = shared_state.cam_chunk_pos.write().unwrap();
+                        *cam_chunk_pos = cam.chunk_pos(chunk_size);
+                        last_time = cur_time;
+                    }
+                    live_events
+                }
+            };
+
+            if let Some(recorder) = recorder.as_mut() {
+                let tick = *shared_state.tick.read().unwrap();
+                recorder.record_frame(tick, cam.delta_time, &frame_events);
             }
 
-            if *shared_state.tick.read().unwrap() >= last_ticket_tick + 20 {
-                let cam_chunk_pos = cam.chunk_pos(CHUNK_SIZE);
+            if force_ticket
+                || *shared_state.tick.read().unwrap()
+                    >= last_ticket_tick + streaming.ticket_interval
+            {
+                force_ticket = false;
+                let cam_chunk_pos = cam.chunk_pos(chunk_size);
                 let key = ChunkKey::new(cam_chunk_pos);
                 tx_chunk_ticket
                     .send(ChunkTicket::new(
                         key,
-                        5,
-                        20,
+                        streaming.load_radius,
+                        streaming.ticket_ttl,
                         *shared_state.active_world.read().unwrap(),
                     ))
                     .unwrap();
@@ -237,42 +691,199 @@ fn main() {
             }
 
             //Events
-            process_events(&mut window, &events, &mut keys, &mut cursor, &mut cam);
+            for event in frame_events.iter() {
+                input::dispatch(event, &mut keys, &mut cursor, &mut cam);
+            }
             keys.process_all_states(&mut cam);
+            if reload_bindings.replace(false) {
+                keys.set_bindings(bindings::load_or_default(KEY_BINDINGS_PATH));
+            }
+            if toggle_hud.replace(false) {
+                hud.visible = !hud.visible;
+            }
+            for pass in toggled_passes.borrow_mut().drain(..) {
+                if let Some(enabled) = scene.toggle_post_pass(pass) {
+                    info!("{} {}", pass, if enabled { "on" } else { "off" });
+                }
+            }
+
+            let path_playing = match path_playback.as_mut() {
+                Some(playback) => {
+                    let delta_time = cam.delta_time as f32;
+                    playback.advance(&mut cam, delta_time)
+                }
+                None => false,
+            };
+            if path_playback.is_some() && !path_playing {
+                info!("Camera path finished");
+                path_playback = None;
+                if options.dump_frames.is_some() {
+                    window.set_should_close(true);
+                }
+            }
+
+            //Worlds
+            if remove_active_world.replace(false) {
+                let world_id = *shared_state.active_world.read().unwrap();
+                let name = shared_state
+                    .world_registry
+                    .get(&world_id)
+                    .map(|world| world.name.clone());
+                let removed = remove_world(&shared_state, &mut renderer, &mut cam, world_id);
+                if let (Some(name), Some(world_type)) = (name, removed) {
+                    removed_worlds.push((name, world_type));
+                    force_ticket = true;
+                }
+            }
+            //Removed worlds are created again from their configs, with the world types they had
+            if restore_worlds.replace(false) {
+                let registries = (
+                    shared_state.world_type_registry.as_ref(),
+                    shared_state.world_registry.as_ref(),
+                );
+                for (name, world_type) in removed_worlds.drain(..) {
+                    let world_config = std::iter::once(&config.world)
+                        .chain(config.worlds.iter())
+                        .find(|world_config| world_config.name == name);
+                    if let Some(world_config) = world_config {
+                        let world_type = registries.0.register_shared(world_type);
+                        let voxreg = &shared_state.voxel_registry;
+                        create_world(registries, world_config, world_type, chunk_size, voxreg);
+                        info!("Restored world {}", name);
+                    }
+                }
+            }
+            let world_id = *shared_state.active_world.read().unwrap();
+            let mut switch_to = None;
+            if next_world.replace(false) {
+                switch_to = world_after(&shared_state, world_id);
+            }
+            let portal = portal_at(&shared_state, world_id, &cam.pos);
+            if let (Some(target), false) = (portal.as_ref(), in_portal) {
+                switch_to = shared_state.world_registry.find(target);
+                if switch_to.is_none() {
+                    warn!("Portal leads to unknown world {}", target);
+                }
+            }
+            in_portal = portal.is_some();
+            if let Some(to) = switch_to.filter(|to| *to != world_id) {
+                switch_world(&shared_state, &mut renderer, &mut cam, to);
+                force_ticket = true;
+            }
 
             //Render
+            scene.reload_if_changed();
+            hud.reload_if_changed();
+            let sun = sun_config.sun(*shared_state.tick.read().unwrap());
             unsafe {
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
-                gl::BindVertexArray(renderer.vao);
+                scene.draw(&mut renderer, &cam, &cam.projection(), &screen_size, &sun);
+            }
 
-                let mv = cam.view();
+            let (fb_width, fb_height) = window.get_framebuffer_size();
+            if take_screenshot.replace(false) {
+                let depth = if options.screenshot_depth {
+                    Some((scene.depth_framebuffer(), cam.near_plane, cam.far_plane))
+                } else {
+                    None
+                };
+                match capture::screenshot(SCREENSHOT_DIR, fb_width as u32, fb_height as u32, depth)
+                {
+                    Ok(path) => info!("Saved screenshot {}", path.display()),
+                    Err(e) => error!("Failed to save screenshot: {}", e),
+                }
+            }
+            if take_tiled_screenshot.replace(false) {
                 let p = cam.projection();
-                let mvp = p * mv;
-                let inv_p = glm::inverse(&p);
-                let inv_mv = glm::inverse(&mv);
-
-                program.set_float(&CString::new("voxelSize").unwrap(), VOXEL_SIZE);
-                program.set_mat4(&CString::new("mvp").unwrap(), &mvp);
-                program.set_mat4(&CString::new("invP").unwrap(), &inv_p);
-                program.set_mat4(&CString::new("invMv").unwrap(), &inv_mv);
-                program.set_vec2(&CString::new("screenSize").unwrap(), &screen_size);
-                renderer.process(&cam);
+                let result = capture::screenshot_tiled(
+                    SCREENSHOT_DIR,
+                    options.screenshot_tiles,
+                    fb_width as u32,
+                    fb_height as u32,
+                    |tile| unsafe {
+                        scene.draw(&mut renderer, &cam, &(tile * p), &screen_size, &sun)
+                    },
+                );
+                match result {
+                    Ok(path) => info!("Saved screenshot {}", path.display()),
+                    Err(e) => error!("Failed to save screenshot: {}", e),
+                }
+                //The back buffer holds the last tile, draw the frame again before swapping
+                unsafe {
+                    scene.draw(&mut renderer, &cam, &p, &screen_size, &sun);
+                }
+            }
+
+            if let (true, Some(dir)) = (path_playing, options.dump_frames.as_ref()) {
+                if let Err(e) =
+                    capture::save_frame(dir, dumped_frames, fb_width as u32, fb_height as u32)
+                {
+                    error!("Failed to save frame {}: {}", dumped_frames, e);
+                }
+                dumped_frames += 1;
+            }
+
+            let now = glfw.get_time();
+            frame_timer.frame(now);
+            frames.inc();
+            frame_time.observe(now - last_frame);
+            last_frame = now;
+            if options.frames.is_some_and(|n| frames.get() >= n) {
+                window.set_should_close(true);
+            }
+            let world_id = *shared_state.active_world.read().unwrap();
+            let world = shared_state.world_registry.get(&world_id);
+            loaded_chunks.set(world.map_or(0, |world| world.pc.chunk_count()) as f64);
+            vbo_bytes.set(renderer.vbo_bytes() as f64);
+            exporter.poll();
+
+            //The overlay is drawn last so screenshots and dumped frames leave it out
+            if hud.visible {
+                let stats = HudStats {
+                    fps: frame_timer.fps,
+                    frame_ms: frame_timer.frame_ms,
+                    cam_pos: cam.pos,
+                    chunk: ChunkKey::new(cam.chunk_pos(chunk_size)),
+                    loaded_chunks: loaded_chunks.get() as usize,
+                    meshed_chunks: renderer.stats.meshed,
+                    visible_chunks: renderer.stats.visible,
+                    drawn_chunks: renderer.stats.drawn,
+                    ticket_queue: ticket_queue.get() as usize,
+                    gen_queue: gen_queue.get() as usize,
+                    vbo_bytes: vbo_bytes.get() as usize,
+                };
+                let fb_size = Vec2::new(fb_width as f32, fb_height as f32);
+                unsafe {
+                    hud.draw(&stats.lines(), &fb_size);
+                }
             }
         }
         window.swap_buffers();
         glfw.poll_events();
     }
-    f::dump_html(File::create("flamegraph.html").unwrap()).unwrap();
+    if let Some(recorder) = recorder.as_mut() {
+        recorder.flush();
+    }
+    exporter.export();
+
+    if options.bench {
+        let seconds = glfw.get_time() - start_time;
+        let generated = metrics.counter("chunks_generated_total", "Chunks generated");
+        println!(
+            "{} frames in {:.2} s, {:.1} fps, {:.2} ms per frame, {} chunks generated",
+            frames.get(),
+            seconds,
+            frames.get() as f64 / seconds,
+            frame_time.sum() * 1000.0 / frame_time.count().max(1) as f64,
+            generated.get()
+        );
+    }
 }
 
-#[flame]
 fn process_events(
     window: &mut glfw::Window,
     events: &Receiver<(f64, glfw::WindowEvent)>,
-    ks: &mut KeyState,
-    cs: &mut CursorState,
-    cam: &mut Camera,
-) {
+) -> Vec<InputEvent> {
+    let mut input_events = Vec::new();
     for (_, event) in glfw::flush_messages(events) {
         match event {
             glfw::WindowEvent::FramebufferSize(width, height) => {
@@ -284,23 +895,20 @@ fn process_events(
                 window.set_should_close(true)
             }
 
-            glfw::WindowEvent::Key(_, _, _, _) => {
-                if let glfw::WindowEvent::Key(key, _, action, _) = event {
-                    if action == Action::Press {
-                        ks.set_state(key, true);
-                    } else if action == Action::Release {
-                        ks.set_state(key, false)
-                    }
-                }
+            glfw::WindowEvent::Key(key, _, action, mods) => {
+                input_events.push(InputEvent::Button(Input::Key(key), mods, action));
             }
 
-            glfw::WindowEvent::CursorPos(_, _) => {
-                if let glfw::WindowEvent::CursorPos(x, y) = event {
-                    cs.process(x as f32, y as f32, cam);
-                }
+            glfw::WindowEvent::MouseButton(button, action, mods) => {
+                input_events.push(InputEvent::Button(Input::Mouse(button), mods, action));
+            }
+
+            glfw::WindowEvent::CursorPos(x, y) => {
+                input_events.push(InputEvent::Cursor(x as f32, y as f32));
             }
 
             _ => {}
         }
     }
+    input_events
 }
@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+
+//Every exported metric name starts with this
+const PREFIX: &str = "voxel_";
+
+//Upper bounds of the histogram buckets for timings, in seconds
+pub const TIME_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+
+//Gauges written by one subsystem and read by others
+pub const TICKET_QUEUE: &str = "chunk_tickets";
+pub const GEN_QUEUE: &str = "chunk_gen_queue";
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for MetricsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricsError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            MetricsError::Parse(err) => write!(f, "invalid metrics config: {}", err),
+            MetricsError::Invalid(msg) => write!(f, "invalid metrics config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for MetricsError {}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+//An f64 stored as its bits
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    fn add(&self, value: f64) {
+        let mut current = self.0.load(Ordering::Relaxed);
+        loop {
+            let new = (f64::from_bits(current) + value).to_bits();
+            match self
+                .0
+                .compare_exchange_weak(current, new, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+}
+
+pub struct Histogram {
+    bounds: Vec<f64>,
+    //One count per bound and a last one for values above every bound
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum: Gauge,
+}
+
+impl Histogram {
+    fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: Gauge(AtomicU64::new(0f64.to_bits())),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.add(value);
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum.get()
+    }
+}
+
+#[derive(Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+struct Entry {
+    help: &'static str,
+    metric: Metric,
+}
+
+//Registry shared by every thread. Metrics are created on first use and hot paths keep the
+//returned handle, so recording never takes the registry lock
+pub struct Metrics {
+    entries: Mutex<BTreeMap<&'static str, Entry>>,
+    start: Instant,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            entries: Mutex::new(BTreeMap::new()),
+            start: Instant::now(),
+        }
+    }
+
+    fn get_or_insert(&self, name: &'static str, help: &'static str, new: Metric) -> Metric {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.entry(name).or_insert(Entry { help, metric: new });
+        entry.metric.clone()
+    }
+
+    //Panics if name is already registered as another kind of metric
+    pub fn counter(&self, name: &'static str, help: &'static str) -> Arc<Counter> {
+        match self.get_or_insert(
+            name,
+            help,
+            Metric::Counter(Arc::new(Counter(AtomicU64::new(0)))),
+        ) {
+            Metric::Counter(counter) => counter,
+            _ => panic!("metric {} is not a counter", name),
+        }
+    }
+
+    pub fn gauge(&self, name: &'static str, help: &'static str) -> Arc<Gauge> {
+        let gauge = Gauge(AtomicU64::new(0f64.to_bits()));
+        match self.get_or_insert(name, help, Metric::Gauge(Arc::new(gauge))) {
+            Metric::Gauge(gauge) => gauge,
+            _ => panic!("metric {} is not a gauge", name),
+        }
+    }
+
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        help: &'static str,
+        bounds: &[f64],
+    ) -> Arc<Histogram> {
+        let histogram = Histogram::new(bounds);
+        match self.get_or_insert(name, help, Metric::Histogram(Arc::new(histogram))) {
+            Metric::Histogram(histogram) => histogram,
+            _ => panic!("metric {} is not a histogram", name),
+        }
+    }
+
+    //Every metric in the Prometheus text exposition format
+    pub fn prometheus(&self) -> String {
+        let entries = self.entries.lock().unwrap();
+        let mut out = String::new();
+        for (name, entry) in entries.iter() {
+            let name = format!("{}{}", PREFIX, name);
+            let kind = match entry.metric {
+                Metric::Counter(_) => "counter",
+                Metric::Gauge(_) => "gauge",
+                Metric::Histogram(_) => "histogram",
+            };
+            writeln!(out, "# HELP {} {}", name, entry.help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            match &entry.metric {
+                Metric::Counter(counter) => writeln!(out, "{} {}", name, counter.get()).unwrap(),
+                Metric::Gauge(gauge) => writeln!(out, "{} {}", name, gauge.get()).unwrap(),
+                Metric::Histogram(histogram) => {
+                    let mut cumulative = 0;
+                    for (i, bucket) in histogram.buckets.iter().enumerate() {
+                        cumulative += bucket.load(Ordering::Relaxed);
+                        let le = match histogram.bounds.get(i) {
+                            Some(bound) => bound.to_string(),
+                            None => "+Inf".to_string(),
+                        };
+                        writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative).unwrap();
+                    }
+                    writeln!(out, "{}_sum {}", name, histogram.sum()).unwrap();
+                    writeln!(out, "{}_count {}", name, histogram.count()).unwrap();
+                }
+            }
+        }
+        out
+    }
+
+    //One "seconds,metric,value" line per value, histograms give their count and sum. The long
+    //format keeps working when metrics are registered part way through a session
+    pub fn csv_rows(&self) -> String {
+        let time = self.start.elapsed().as_secs_f64();
+        let entries = self.entries.lock().unwrap();
+        let mut out = String::new();
+        for (name, entry) in entries.iter() {
+            match &entry.metric {
+                Metric::Counter(counter) => {
+                    writeln!(out, "{:.3},{},{}", time, name, counter.get()).unwrap()
+                }
+                Metric::Gauge(gauge) => {
+                    writeln!(out, "{:.3},{},{}", time, name, gauge.get()).unwrap()
+                }
+                Metric::Histogram(histogram) => {
+                    writeln!(out, "{:.3},{}_count,{}", time, name, histogram.count()).unwrap();
+                    writeln!(out, "{:.3},{}_sum,{}", time, name, histogram.sum()).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    //Seconds between writes to the files
+    pub interval: f64,
+    pub prometheus_file: Option<String>,
+    pub csv_file: Option<String>,
+    //Address to serve the Prometheus text on, e.g. "127.0.0.1:9184"
+    pub http_address: Option<String>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            interval: 5.0,
+            prometheus_file: None,
+            csv_file: None,
+            http_address: None,
+        }
+    }
+}
+
+impl MetricsConfig {
+    pub fn parse(src: &str) -> Result<MetricsConfig, MetricsError> {
+        let config: MetricsConfig = toml::from_str(src).map_err(MetricsError::Parse)?;
+        if config.interval <= 0.0 {
+            return Err(MetricsError::Invalid(
+                "interval must be positive".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<MetricsConfig, MetricsError> {
+        let src = fs::read_to_string(path).map_err(|e| MetricsError::Io(path.to_string(), e))?;
+        MetricsConfig::parse(&src)
+    }
+
+    pub fn load_or_default(path: &str) -> MetricsConfig {
+        match MetricsConfig::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Not exporting metrics, {}", err);
+                MetricsConfig::default()
+            }
+        }
+    }
+}
+
+//Writes the metrics out as configured, polled from the frame loop
+pub struct MetricsExporter {
+    metrics: Arc<Metrics>,
+    config: MetricsConfig,
+    csv: Option<BufWriter<File>>,
+    last_export: Option<Instant>,
+}
+
+impl MetricsExporter {
+    pub fn new(metrics: Arc<Metrics>, config: MetricsConfig) -> MetricsExporter {
+        let csv = config.csv_file.as_ref().and_then(|path| {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            match file {
+                //Runs append to the same file, only a new one gets the header
+                Ok(file) => {
+                    let empty = file.metadata().is_ok_and(|m| m.len() == 0);
+                    let mut csv = BufWriter::new(file);
+                    if empty {
+                        let _ = writeln!(csv, "seconds,metric,value");
+                    }
+                    Some(csv)
+                }
+                Err(e) => {
+                    error!("Failed to open metrics csv {}: {}", path, e);
+                    None
+                }
+            }
+        });
+        if let Some(address) = config.http_address.as_ref() {
+            if let Err(e) = serve_http(address, metrics.clone()) {
+                error!("Failed to serve metrics on {}: {}", address, e);
+            }
+        }
+        MetricsExporter {
+            metrics,
+            config,
+            csv,
+            last_export: None,
+        }
+    }
+
+    pub fn poll(&mut self) {
+        let due = match self.last_export {
+            Some(last) => last.elapsed().as_secs_f64() >= self.config.interval,
+            None => true,
+        };
+        if due {
+            self.export();
+        }
+    }
+
+    pub fn export(&mut self) {
+        self.last_export = Some(Instant::now());
+        if let Some(path) = self.config.prometheus_file.as_ref() {
+            //Replaced in one rename so readers never see half a file
+            let tmp = format!("{}.tmp", path);
+            let result =
+                fs::write(&tmp, self.metrics.prometheus()).and_then(|_| fs::rename(&tmp, path));
+            if let Err(e) = result {
+                error!("Failed to write metrics to {}: {}", path, e);
+            }
+        }
+        if let Some(csv) = self.csv.as_mut() {
+            let result = csv
+                .write_all(self.metrics.csv_rows().as_bytes())
+                .and_then(|_| csv.flush());
+            if let Err(e) = result {
+                error!("Failed to write metrics csv: {}", e);
+            }
+        }
+    }
+}
+
+//Answers every request on address with the Prometheus text, from its own thread
+fn serve_http(address: &str, metrics: Arc<Metrics>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    info!("Serving metrics on http://{}/metrics", address);
+    thread::Builder::new()
+        .name("MetricsHttp".to_string())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                //The request itself doesn't matter, read enough of it to be polite. A client
+                //that sends nothing mustn't hold up the others
+                let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request);
+                let body = metrics.prometheus();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prometheus_text() {
+        let metrics = Metrics::new();
+        metrics.counter("frames_total", "Frames drawn").add(3);
+        metrics.gauge("queue", "Queue length").set(2.5);
+        let histogram = metrics.histogram("frame_seconds", "Frame time", &[0.01, 0.1]);
+        histogram.observe(0.005);
+        histogram.observe(0.05);
+        histogram.observe(2.0);
+
+        let text = metrics.prometheus();
+        assert!(text.contains("# TYPE voxel_frames_total counter\nvoxel_frames_total 3\n"));
+        assert!(text.contains("voxel_queue 2.5\n"));
+        assert!(text.contains("voxel_frame_seconds_bucket{le=\"0.01\"} 1\n"));
+        assert!(text.contains("voxel_frame_seconds_bucket{le=\"0.1\"} 2\n"));
+        assert!(text.contains("voxel_frame_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("voxel_frame_seconds_sum 2.055\n"));
+        assert!(text.contains("voxel_frame_seconds_count 3\n"));
+    }
+
+    #[test]
+    fn test_handles_share_values() {
+        let metrics = Metrics::new();
+        metrics.counter("chunks", "Chunks").inc();
+        metrics.counter("chunks", "Chunks").inc();
+        assert_eq!(metrics.counter("chunks", "Chunks").get(), 2);
+
+        let rows = metrics.csv_rows();
+        assert_eq!(rows.lines().count(), 1);
+        assert!(rows.trim_end().ends_with(",chunks,2"));
+    }
+
+    #[test]
+    fn test_config() {
+        let config = MetricsConfig::parse("csv_file = \"metrics.csv\"").unwrap();
+        assert_eq!(config.csv_file.as_deref(), Some("metrics.csv"));
+        assert!(config.http_address.is_none());
+        assert!(MetricsConfig::parse("interval = 0").is_err());
+        assert!(MetricsConfig::parse("port = 80").is_err());
+    }
+
+    #[test]
+    fn test_csv_header_once() {
+        let path = std::env::temp_dir().join(format!("metrics_{}.csv", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let config = MetricsConfig {
+            csv_file: Some(path.to_str().unwrap().to_string()),
+            ..MetricsConfig::default()
+        };
+        for _ in 0..2 {
+            MetricsExporter::new(Arc::new(Metrics::new()), config.clone()).export();
+        }
+        let csv = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(csv.matches("seconds,metric,value").count(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_kind_mismatch() {
+        let metrics = Metrics::new();
+        metrics.counter("chunks", "Chunks");
+        metrics.gauge("chunks", "Chunks");
+    }
+}
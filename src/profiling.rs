@@ -0,0 +1,168 @@
+//Chrome trace-event output for the timed spans, only built with the profiling feature. The
+//file opens in chrome://tracing or https://ui.perfetto.dev
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::logging::subsystem;
+
+static PROFILER: OnceLock<Profiler> = OnceLock::new();
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    //0 until the thread records its first span
+    static THREAD_ID: Cell<u64> = Cell::new(0);
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    name: &'static str,
+    category: &'static str,
+    thread: u64,
+    //Since the profiler started
+    start: Duration,
+    duration: Duration,
+}
+
+struct Profiler {
+    start: Instant,
+    events: Mutex<Vec<Event>>,
+    threads: Mutex<Vec<(u64, String)>>,
+}
+
+fn profiler() -> &'static Profiler {
+    PROFILER.get_or_init(|| Profiler {
+        start: Instant::now(),
+        events: Mutex::new(Vec::new()),
+        threads: Mutex::new(Vec::new()),
+    })
+}
+
+//Small ids in the order threads first record a span, with their names kept for the trace
+fn thread_id(profiler: &Profiler) -> u64 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+            let name = thread::current().name().unwrap_or("unnamed").to_string();
+            profiler.threads.lock().unwrap().push((id.get(), name));
+        }
+        id.get()
+    })
+}
+
+//Starts the trace clock, spans recorded before this are timed from the first one
+pub fn init() {
+    profiler();
+}
+
+//A span named name in the module target that ran for duration from start
+pub fn record(target: &'static str, name: &'static str, start: Instant, duration: Duration) {
+    let profiler = profiler();
+    let event = Event {
+        name,
+        category: subsystem(target),
+        thread: thread_id(profiler),
+        start: start.saturating_duration_since(profiler.start),
+        duration,
+    };
+    profiler.events.lock().unwrap().push(event);
+}
+
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000.0
+}
+
+fn trace_json(events: &[Event], threads: &[(u64, String)]) -> Value {
+    let names = threads.iter().map(|(id, name)| {
+        json!({
+            "name": "thread_name",
+            "ph": "M",
+            "pid": 1,
+            "tid": id,
+            "args": { "name": name },
+        })
+    });
+    let spans = events.iter().map(|event| {
+        json!({
+            "name": event.name,
+            "cat": event.category,
+            "ph": "X",
+            "ts": micros(event.start),
+            "dur": micros(event.duration),
+            "pid": 1,
+            "tid": event.thread,
+        })
+    });
+    json!({
+        "traceEvents": names.chain(spans).collect::<Vec<Value>>(),
+        "displayTimeUnit": "ms",
+    })
+}
+
+//Writes every span recorded so far to path
+pub fn write(path: &str) -> io::Result<()> {
+    let profiler = profiler();
+    let events = profiler.events.lock().unwrap();
+    let threads = profiler.threads.lock().unwrap();
+    fs::write(path, trace_json(&events, &threads).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_json() {
+        let events = vec![Event {
+            name: "generate_chunk",
+            category: "chunk_gen",
+            thread: 2,
+            start: Duration::from_millis(3),
+            duration: Duration::from_micros(250),
+        }];
+        let threads = vec![(2, "ChunkGenerator".to_string())];
+        let trace = trace_json(&events, &threads);
+        let trace_events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(trace_events.len(), 2);
+        assert_eq!(trace_events[0]["ph"], "M");
+        assert_eq!(trace_events[0]["args"]["name"], "ChunkGenerator");
+        let span = &trace_events[1];
+        assert_eq!(span["ph"], "X");
+        assert_eq!(span["cat"], "chunk_gen");
+        assert_eq!(span["ts"], 3000.0);
+        assert_eq!(span["dur"], 250.0);
+        assert_eq!(span["tid"], 2);
+    }
+
+    #[test]
+    fn test_record_thread_ids() {
+        let start = Instant::now();
+        record(
+            "voxel_render::chunk_gen",
+            "a",
+            start,
+            Duration::from_micros(1),
+        );
+        thread::Builder::new()
+            .name("other".to_string())
+            .spawn(move || record("voxel_render", "b", start, Duration::from_micros(1)))
+            .unwrap()
+            .join()
+            .unwrap();
+        let profiler = profiler();
+        let events = profiler.events.lock().unwrap();
+        let a = events.iter().find(|e| e.name == "a").unwrap();
+        let b = events.iter().find(|e| e.name == "b").unwrap();
+        assert_eq!(a.category, "chunk_gen");
+        assert_eq!(b.category, "main");
+        assert_ne!(a.thread, b.thread);
+        let threads = profiler.threads.lock().unwrap();
+        assert!(threads.contains(&(b.thread, "other".to_string())));
+    }
+}
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use super::consts::TRANSPARENT_VOXEL;
+use super::geom::{calc_idx, ChunkKey};
+use super::voxel_registry::VoxelReg;
+use super::world::WorldType;
+
+//Region files start with this and a version, followed by the origin and size, the palette and
+//the voxels as runs of palette indices, all little endian
+const MAGIC: &[u8; 4] = b"VXRG";
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum RegionError {
+    Io(String, io::Error),
+    Format(String),
+}
+
+impl fmt::Display for RegionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegionError::Io(path, err) => write!(f, "failed to access {}: {}", path, err),
+            RegionError::Format(msg) => write!(f, "invalid region: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RegionError {}
+
+impl From<io::Error> for RegionError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => RegionError::Format("file is truncated".to_string()),
+            _ => RegionError::Io("region".to_string(), err),
+        }
+    }
+}
+
+//A voxel state by its type's string id and the offset of the state from the type id, so regions
+//stay valid when ids change between sessions
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PaletteEntry {
+    pub string_id: String,
+    pub state: u32,
+}
+
+impl PaletteEntry {
+    pub fn new(string_id: &str, state: u32) -> PaletteEntry {
+        PaletteEntry {
+            string_id: string_id.to_string(),
+            state,
+        }
+    }
+
+    //Voxels the registry doesn't know are saved as transparent
+    pub fn from_key(reg: &VoxelReg, key: &u64) -> PaletteEntry {
+        match reg.type_of(key) {
+            Some(type_key) => match reg.string_id(&type_key) {
+                Some(string_id) => PaletteEntry::new(string_id, (key - type_key) as u32),
+                None => PaletteEntry::new(TRANSPARENT_VOXEL, 0),
+            },
+            None => PaletteEntry::new(TRANSPARENT_VOXEL, 0),
+        }
+    }
+
+    //None when the type isn't registered, a state the type doesn't have is its default state
+    pub fn to_key(&self, reg: &VoxelReg) -> Option<u64> {
+        let type_key = reg.key_from_string_id(&self.string_id)?;
+        let key = type_key + self.state as u64;
+        if reg.type_of(&key) == Some(type_key) {
+            Some(key)
+        } else {
+            Some(type_key)
+        }
+    }
+}
+
+//A box of voxels placed at origin in the world
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub origin: [i32; 3],
+    pub size: [u32; 3],
+    palette: Vec<PaletteEntry>,
+    //Palette indices with x changing fastest, then y, then z
+    voxels: Vec<u16>,
+}
+
+impl Region {
+    pub fn new(origin: [i32; 3], size: [u32; 3], fill: PaletteEntry) -> Region {
+        let volume = size.iter().map(|s| *s as usize).product();
+        Region {
+            origin,
+            size,
+            palette: vec![fill],
+            voxels: vec![0; volume],
+        }
+    }
+
+    pub fn palette(&self) -> &[PaletteEntry] {
+        &self.palette
+    }
+
+    fn index(&self, pos: [u32; 3]) -> usize {
+        let [x, y, z] = pos.map(|p| p as usize);
+        let [sx, sy, _] = self.size.map(|s| s as usize);
+        x + sx * (y + sy * z)
+    }
+
+    //Position in the region of a voxel position in the world, None outside it
+    pub fn local_pos(&self, world_pos: [i32; 3]) -> Option<[u32; 3]> {
+        let mut local = [0; 3];
+        for i in 0..3 {
+            let p = world_pos[i] as i64 - self.origin[i] as i64;
+            if p < 0 || p >= self.size[i] as i64 {
+                return None;
+            }
+            local[i] = p as u32;
+        }
+        Some(local)
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, pos: [u32; 3]) -> &PaletteEntry {
+        &self.palette[self.palette_index(pos)]
+    }
+
+    pub fn palette_index(&self, pos: [u32; 3]) -> usize {
+        self.voxels[self.index(pos)] as usize
+    }
+
+    pub fn set(&mut self, pos: [u32; 3], entry: &PaletteEntry) -> Result<(), RegionError> {
+        let palette_idx = match self.palette.iter().position(|e| e == entry) {
+            Some(i) => i,
+            None if self.palette.len() <= u16::MAX as usize => {
+                self.palette.push(entry.clone());
+                self.palette.len() - 1
+            }
+            None => return Err(RegionError::Format("too many voxel states".to_string())),
+        };
+        let idx = self.index(pos);
+        self.voxels[idx] = palette_idx as u16;
+        Ok(())
+    }
+
+    //Generates the chunks from one chunk key to another, inclusive, into a region
+    pub fn generate(
+        world_type: &dyn WorldType,
+        reg: &VoxelReg,
+        chunk_size: usize,
+        from: [i32; 3],
+        to: [i32; 3],
+    ) -> Result<Region, RegionError> {
+        let size = chunk_size as i32;
+        let chunks = [0, 1, 2].map(|i| (to[i] - from[i] + 1) as u32);
+        let mut region = Region::new(
+            from.map(|c| c * size),
+            chunks.map(|c| c * chunk_size as u32),
+            PaletteEntry::new(TRANSPARENT_VOXEL, 0),
+        );
+        let mut entries = HashMap::new();
+        for cx in from[0]..=to[0] {
+            for cy in from[1]..=to[1] {
+                for cz in from[2]..=to[2] {
+                    let key = ChunkKey {
+                        x: cx,
+                        y: cy,
+                        z: cz,
+                    };
+                    let voxels = world_type.gen_chunk(&key, reg);
+                    for x in 0..chunk_size {
+                        for y in 0..chunk_size {
+                            for z in 0..chunk_size {
+                                let voxel = voxels[calc_idx(x, y, z, chunk_size)];
+                                let entry = entries
+                                    .entry(voxel)
+                                    .or_insert_with(|| PaletteEntry::from_key(reg, &voxel));
+                                let pos = [
+                                    ((cx - from[0]) * size) as u32 + x as u32,
+                                    ((cy - from[1]) * size) as u32 + y as u32,
+                                    ((cz - from[2]) * size) as u32 + z as u32,
+                                ];
+                                region.set(pos, entry)?;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(region)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        for o in self.origin.iter() {
+            out.write_all(&o.to_le_bytes())?;
+        }
+        for s in self.size.iter() {
+            out.write_all(&s.to_le_bytes())?;
+        }
+        out.write_all(&(self.palette.len() as u32).to_le_bytes())?;
+        for entry in self.palette.iter() {
+            out.write_all(&(entry.string_id.len() as u16).to_le_bytes())?;
+            out.write_all(entry.string_id.as_bytes())?;
+            out.write_all(&entry.state.to_le_bytes())?;
+        }
+
+        let mut runs: Vec<(u32, u16)> = Vec::new();
+        for voxel in self.voxels.iter() {
+            match runs.last_mut() {
+                Some((count, idx)) if idx == voxel && *count < u32::MAX => *count += 1,
+                _ => runs.push((1, *voxel)),
+            }
+        }
+        out.write_all(&(runs.len() as u32).to_le_bytes())?;
+        for (count, idx) in runs {
+            out.write_all(&count.to_le_bytes())?;
+            out.write_all(&idx.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(input: &mut R) -> Result<Region, RegionError> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(RegionError::Format("not a region file".to_string()));
+        }
+        let version = read_u32(input)?;
+        if version != VERSION {
+            return Err(RegionError::Format(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+        let mut origin = [0; 3];
+        for o in origin.iter_mut() {
+            *o = read_u32(input)? as i32;
+        }
+        let mut size = [0; 3];
+        for s in size.iter_mut() {
+            *s = read_u32(input)?;
+        }
+
+        let palette_len = read_u32(input)? as usize;
+        if palette_len == 0 || palette_len > u16::MAX as usize + 1 {
+            return Err(RegionError::Format(format!(
+                "palette of {} entries",
+                palette_len
+            )));
+        }
+        let mut palette = Vec::with_capacity(palette_len);
+        for _ in 0..palette_len {
+            let mut len = [0u8; 2];
+            input.read_exact(&mut len)?;
+            let mut string_id = vec![0u8; u16::from_le_bytes(len) as usize];
+            input.read_exact(&mut string_id)?;
+            let string_id = String::from_utf8(string_id)
+                .map_err(|_| RegionError::Format("voxel id isn't UTF-8".to_string()))?;
+            palette.push(PaletteEntry {
+                string_id,
+                state: read_u32(input)?,
+            });
+        }
+
+        let volume = size
+            .iter()
+            .try_fold(1usize, |v, s| v.checked_mul(*s as usize))
+            .ok_or_else(|| RegionError::Format("region is too large".to_string()))?;
+        let mut voxels = Vec::new();
+        for _ in 0..read_u32(input)? {
+            let count = read_u32(input)? as usize;
+            let mut idx = [0u8; 2];
+            input.read_exact(&mut idx)?;
+            let idx = u16::from_le_bytes(idx);
+            if idx as usize >= palette.len() || voxels.len() + count > volume {
+                return Err(RegionError::Format("voxel data is corrupt".to_string()));
+            }
+            voxels.extend(std::iter::repeat(idx).take(count));
+        }
+        if voxels.len() != volume {
+            return Err(RegionError::Format(format!(
+                "{} voxels for a region of {}",
+                voxels.len(),
+                volume
+            )));
+        }
+
+        Ok(Region {
+            origin,
+            size,
+            palette,
+            voxels,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), RegionError> {
+        let io_err = |e| RegionError::Io(path.to_string(), e);
+        let mut out = BufWriter::new(File::create(path).map_err(io_err)?);
+        self.write(&mut out)
+            .and_then(|_| out.flush())
+            .map_err(io_err)
+    }
+
+    pub fn load(path: &str) -> Result<Region, RegionError> {
+        let file = File::open(path).map_err(|e| RegionError::Io(path.to_string(), e))?;
+        Region::read(&mut BufReader::new(file)).map_err(|e| match e {
+            RegionError::Io(_, err) => RegionError::Io(path.to_string(), err),
+            e => e,
+        })
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let air = PaletteEntry::new("transparent", 0);
+        let stone = PaletteEntry::new("stone", 3);
+        let mut region = Region::new([-16, 0, 4], [3, 2, 5], air.clone());
+        region.set([2, 1, 4], &stone).unwrap();
+        region.set([0, 0, 0], &stone).unwrap();
+
+        let mut bytes = Vec::new();
+        region.write(&mut bytes).unwrap();
+        let read = Region::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read, region);
+        assert_eq!(read.get([2, 1, 4]), &stone);
+        assert_eq!(read.get([1, 1, 4]), &air);
+        assert_eq!(read.palette().len(), 2);
+
+        assert!(Region::read(&mut &bytes[..bytes.len() - 3]).is_err());
+        assert!(Region::read(&mut &b"nope"[..]).is_err());
+    }
+
+    #[test]
+    fn test_local_pos() {
+        let region = Region::new([-4, 0, 2], [4, 4, 4], PaletteEntry::new("a", 0));
+        assert_eq!(region.local_pos([-4, 0, 2]), Some([0, 0, 0]));
+        assert_eq!(region.local_pos([-1, 3, 5]), Some([3, 3, 3]));
+        assert_eq!(region.local_pos([0, 0, 2]), None);
+        assert_eq!(region.local_pos([-4, -1, 2]), None);
+    }
+}
@@ -0,0 +1,212 @@
+use super::Camera;
+
+use std::fmt;
+use std::fs;
+use std::io;
+
+use glm::{Quat, Vec3};
+use serde::Deserialize;
+
+#[derive(Debug)]
+pub enum PathError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            PathError::Parse(err) => write!(f, "invalid camera path: {}", err),
+            PathError::Invalid(msg) => write!(f, "invalid camera path: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+#[derive(Deserialize)]
+struct PathConfig {
+    keyframe: Vec<KeyframeConfig>,
+}
+
+#[derive(Deserialize)]
+struct KeyframeConfig {
+    time: f32,
+    pos: [f32; 3],
+    yaw: f32,
+    pitch: f32,
+}
+
+struct Keyframe {
+    time: f32,
+    pos: Vec3,
+    rot: Quat,
+}
+
+pub struct CameraPath {
+    keyframes: Vec<Keyframe>,
+}
+
+//Rotation taking +x to the camera front for the given yaw and pitch in degrees
+fn orientation(yaw: f32, pitch: f32) -> Quat {
+    glm::quat_angle_axis(-yaw.to_radians(), &Vec3::new(0.0, 1.0, 0.0))
+        * glm::quat_angle_axis(pitch.to_radians(), &Vec3::new(0.0, 0.0, 1.0))
+}
+
+fn catmull_rom(p0: &Vec3, p1: &Vec3, p2: &Vec3, p3: &Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
+
+impl CameraPath {
+    pub fn load(path: &str) -> Result<CameraPath, PathError> {
+        let src = fs::read_to_string(path).map_err(|e| PathError::Io(path.to_string(), e))?;
+        CameraPath::parse(&src)
+    }
+
+    pub fn parse(src: &str) -> Result<CameraPath, PathError> {
+        let config: PathConfig = toml::from_str(src).map_err(PathError::Parse)?;
+        if config.keyframe.is_empty() {
+            return Err(PathError::Invalid("no keyframes".to_string()));
+        }
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        for (i, k) in config.keyframe.iter().enumerate() {
+            if let Some(prev) = keyframes.last() {
+                if k.time <= prev.time {
+                    return Err(PathError::Invalid(format!(
+                        "keyframe {} at time {} is not after the previous keyframe",
+                        i, k.time
+                    )));
+                }
+            }
+            keyframes.push(Keyframe {
+                time: k.time,
+                pos: Vec3::new(k.pos[0], k.pos[1], k.pos[2]),
+                rot: orientation(k.yaw, k.pitch),
+            });
+        }
+        Ok(CameraPath { keyframes })
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time
+    }
+
+    //Returns position, yaw and pitch at time t, clamped to the ends of the path
+    pub fn sample(&self, t: f32) -> (Vec3, f32, f32) {
+        let last = self.keyframes.len() - 1;
+        let seg = self
+            .keyframes
+            .iter()
+            .rposition(|k| k.time <= t)
+            .unwrap_or(0)
+            .min(last.saturating_sub(1));
+        let k1 = &self.keyframes[seg];
+        let k2 = &self.keyframes[(seg + 1).min(last)];
+
+        let u = if k2.time > k1.time {
+            ((t - k1.time) / (k2.time - k1.time)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+
+        //The ends of the path get mirrored phantom points so the spline keeps its direction
+        let p0 = if seg > 0 {
+            self.keyframes[seg - 1].pos
+        } else {
+            k1.pos * 2.0 - k2.pos
+        };
+        let p3 = if seg + 2 <= last {
+            self.keyframes[seg + 2].pos
+        } else {
+            k2.pos * 2.0 - k1.pos
+        };
+        let pos = catmull_rom(&p0, &k1.pos, &k2.pos, &p3, u);
+
+        let rot = glm::quat_slerp(&k1.rot, &k2.rot, u);
+        let front = glm::quat_rotate_vec3(&rot, &Vec3::new(1.0, 0.0, 0.0));
+        let yaw = front.z.atan2(front.x).to_degrees();
+        let pitch = front.y.clamp(-1.0, 1.0).asin().to_degrees();
+        (pos, yaw, pitch)
+    }
+}
+
+pub struct PathPlayback {
+    path: CameraPath,
+    time: f32,
+}
+
+impl PathPlayback {
+    pub fn new(path: CameraPath) -> PathPlayback {
+        PathPlayback { path, time: 0.0 }
+    }
+
+    //Moves the camera along the path, returns false once the end has been reached
+    pub fn advance(&mut self, cam: &mut Camera, delta_time: f32) -> bool {
+        let (pos, yaw, pitch) = self.path.sample(self.time);
+        cam.pos = pos;
+        cam.set_orientation(yaw, pitch);
+        let finished = self.time >= self.path.duration();
+        self.time += delta_time;
+        !finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = r#"
+        [[keyframe]]
+        time = 0.0
+        pos = [0.0, 0.0, 0.0]
+        yaw = 0.0
+        pitch = 0.0
+
+        [[keyframe]]
+        time = 1.0
+        pos = [10.0, 0.0, 0.0]
+        yaw = 90.0
+        pitch = 0.0
+
+        [[keyframe]]
+        time = 2.0
+        pos = [20.0, 0.0, 0.0]
+        yaw = 90.0
+        pitch = 30.0
+    "#;
+
+    #[test]
+    fn test_sample_hits_keyframes() {
+        let path = CameraPath::parse(PATH).unwrap();
+        let (pos, yaw, pitch) = path.sample(1.0);
+        assert!((pos - Vec3::new(10.0, 0.0, 0.0)).norm() < 1e-4);
+        assert!((yaw - 90.0).abs() < 1e-3);
+        assert!(pitch.abs() < 1e-3);
+
+        let (pos, _, pitch) = path.sample(5.0);
+        assert!((pos - Vec3::new(20.0, 0.0, 0.0)).norm() < 1e-4);
+        assert!((pitch - 30.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_sample_interpolates() {
+        let path = CameraPath::parse(PATH).unwrap();
+        let (pos, yaw, _) = path.sample(0.5);
+        assert!((pos.x - 5.0).abs() < 1e-4);
+        assert!((yaw - 45.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_keyframes_must_be_ordered() {
+        let src = PATH.replace("time = 2.0", "time = 0.5");
+        assert!(CameraPath::parse(&src).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::io;
+use std::os::raw::c_void;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use glm::{Mat4, Vec3};
+use image::{ImageBuffer, ImageResult, Luma, RgbaImage};
+
+type GrayImage16 = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+//Reads the colour buffer of the bound read framebuffer, flipped so row 0 is the top of the image
+pub unsafe fn read_pixels(width: u32, height: u32) -> RgbaImage {
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        0,
+        0,
+        width as i32,
+        height as i32,
+        gl::RGBA,
+        gl::UNSIGNED_BYTE,
+        data.as_mut_ptr() as *mut c_void,
+    );
+    let img = RgbaImage::from_raw(width, height, data).unwrap();
+    image::imageops::flip_vertical(&img)
+}
+
+pub fn save_frame(dir: &str, index: u32, width: u32, height: u32) -> ImageResult<()> {
+    fs::create_dir_all(dir)?;
+    let path = Path::new(dir).join(format!("frame_{:05}.png", index));
+    let img = unsafe { read_pixels(width, height) };
+    img.save(path)
+}
+
+//Maps a window depth buffer value back to eye space distance, divided by far so it fits in [0, 1]
+pub fn linearize_depth(depth: f32, near: f32, far: f32) -> f32 {
+    let z = depth * 2.0 - 1.0;
+    let linear = (2.0 * near * far) / (far + near - z * (far - near));
+    linear / far
+}
+
+//Reads the depth attachment of framebuffer, linearized between the near and far planes
+pub unsafe fn read_depth(
+    framebuffer: u32,
+    width: u32,
+    height: u32,
+    near: f32,
+    far: f32,
+) -> GrayImage16 {
+    let mut data = vec![0f32; (width * height) as usize];
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, framebuffer);
+    gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+    gl::ReadPixels(
+        0,
+        0,
+        width as i32,
+        height as i32,
+        gl::DEPTH_COMPONENT,
+        gl::FLOAT,
+        data.as_mut_ptr() as *mut c_void,
+    );
+    gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+    let pixels = data
+        .iter()
+        .map(|d| (linearize_depth(*d, near, far).clamp(0.0, 1.0) * 65535.0) as u16)
+        .collect();
+    let img = GrayImage16::from_raw(width, height, pixels).unwrap();
+    image::imageops::flip_vertical(&img)
+}
+
+//Matrix applied after the projection so tile (x, y) of a tiles by tiles grid fills the viewport
+pub fn tile_projection(x: u32, y: u32, tiles: u32) -> Mat4 {
+    let n = tiles as f32;
+    let offset = Vec3::new(n - 1.0 - 2.0 * x as f32, n - 1.0 - 2.0 * y as f32, 0.0);
+    glm::translation(&offset) * glm::scaling(&Vec3::new(n, n, 1.0))
+}
+
+fn next_path(dir: &str, suffix: &str) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut i = 0;
+    loop {
+        let path = Path::new(dir).join(format!("screenshot_{}_{}{}.png", secs, i, suffix));
+        if !path.exists() {
+            return Ok(path);
+        }
+        i += 1;
+    }
+}
+
+//Writes the current frame, and the linearized depth buffer when the framebuffer holding it and
+//the near and far planes are given
+pub fn screenshot(
+    dir: &str,
+    width: u32,
+    height: u32,
+    depth: Option<(u32, f32, f32)>,
+) -> ImageResult<PathBuf> {
+    let path = next_path(dir, "")?;
+    unsafe { read_pixels(width, height) }.save(&path)?;
+    if let Some((framebuffer, near, far)) = depth {
+        let depth_path = path.with_file_name(format!(
+            "{}_depth.png",
+            path.file_stem().unwrap().to_string_lossy()
+        ));
+        unsafe { read_depth(framebuffer, width, height, near, far) }.save(depth_path)?;
+    }
+    Ok(path)
+}
+
+//Renders the scene once per tile through draw, which gets the tile matrix to put in front of
+//the projection, and stitches the tiles into one image tiles times the window size
+pub fn screenshot_tiled(
+    dir: &str,
+    tiles: u32,
+    width: u32,
+    height: u32,
+    mut draw: impl FnMut(&Mat4),
+) -> ImageResult<PathBuf> {
+    let path = next_path(dir, &format!("_x{}", tiles))?;
+    let mut out = RgbaImage::new(width * tiles, height * tiles);
+    for y in 0..tiles {
+        for x in 0..tiles {
+            draw(&tile_projection(x, y, tiles));
+            let tile = unsafe { read_pixels(width, height) };
+            image::imageops::replace(&mut out, &tile, x * width, (tiles - 1 - y) * height);
+        }
+    }
+    out.save(&path)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_linearize_depth_range() {
+        assert!((linearize_depth(0.0, 0.1, 100.0) - 0.001).abs() < 1e-6);
+        assert!((linearize_depth(1.0, 0.1, 100.0) - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_tile_projection_covers_tile() {
+        let m = tile_projection(1, 0, 2);
+        let low = m * glm::vec4(0.0, -1.0, 0.5, 1.0);
+        let high = m * glm::vec4(1.0, 0.0, 0.5, 1.0);
+        assert_eq!((low.x, low.y, low.z), (-1.0, -1.0, 0.5));
+        assert_eq!((high.x, high.y), (1.0, 1.0));
+    }
+}
@@ -1,56 +1,106 @@
 use super::Camera;
 use crate::geom::ChunkKey;
+use crate::metrics::Counter;
+use crate::span;
 use crate::SharedState;
 
 use gl::types::*;
 
-use flamer::flame;
 use glm::Vec3;
+use log::trace;
 
 use std::collections::HashMap;
 use std::mem;
 use std::os::raw::c_void;
 use std::ptr;
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 
-#[derive(Copy, Clone, Debug)]
+//Floats per voxel in the render data: position, top, side and bottom texture layers,
+//opacity, the bitmask of hidden faces and two floats of packed ambient occlusion
+const VOXEL_STRIDE: usize = 10;
+//How far in voxels the camera moves before translucent voxels are sorted again, the order
+//barely changes over shorter moves
+const RESORT_DISTANCE: f32 = 1.0;
+
+unsafe fn upload(vbo: u32, data: &[f32]) {
+    gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+    gl::BufferData(
+        gl::ARRAY_BUFFER,
+        (data.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+        data.as_ptr() as *const c_void,
+        gl::DYNAMIC_DRAW,
+    );
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+}
+
+unsafe fn draw_points(vbo: u32, amount: i32) {
+    gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+    let stride = (VOXEL_STRIDE * mem::size_of::<GLfloat>()) as i32;
+    let offset = |floats: usize| (floats * mem::size_of::<GLfloat>()) as *const c_void;
+    gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, ptr::null());
+    gl::VertexAttribPointer(1, 3, gl::FLOAT, gl::FALSE, stride, offset(3));
+    gl::VertexAttribPointer(2, 1, gl::FLOAT, gl::FALSE, stride, offset(6));
+    gl::VertexAttribPointer(3, 1, gl::FLOAT, gl::FALSE, stride, offset(7));
+    gl::VertexAttribPointer(4, 2, gl::FLOAT, gl::FALSE, stride, offset(8));
+    gl::DrawArrays(gl::POINTS, 0, amount / VOXEL_STRIDE as i32);
+    gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+}
+
+//Chunk counts from the last frame drawn
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RenderStats {
+    pub meshed: usize,
+    pub visible: usize,
+    pub drawn: usize,
+}
+
+#[derive(Clone, Debug)]
 struct ChunkData {
     rendered: bool,
     amount: i32,
+    //Size of the data in both buffers
+    bytes: usize,
     vbo: u32,
+    translucent: Vec<f32>,
+    translucent_vbo: u32,
+    sorted_from: Option<Vec3>,
+    //Render version of the chunk the uploaded data came from
+    version: u64,
 }
 
 impl ChunkData {
-    #[flame("ChunkData")]
-    unsafe fn load_data(&mut self, data: &Vec<f32>) {
+    unsafe fn load_data(&mut self, data: &Vec<f32>, translucent: Vec<f32>, version: u64) {
+        self.rendered = true;
+        self.version = version;
         self.amount = data.len() as i32;
+        self.bytes = (data.len() + translucent.len()) * mem::size_of::<GLfloat>();
         if self.amount > 0 {
-            self.rendered = true;
-            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-            gl::BufferData(
-                gl::ARRAY_BUFFER,
-                (self.amount as usize * mem::size_of::<GLfloat>()) as GLsizeiptr,
-                &data[0] as *const f32 as *const c_void,
-                gl::DYNAMIC_DRAW,
-            );
-            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            upload(self.vbo, data);
         }
+        self.translucent = translucent;
+        self.sorted_from = None;
     }
 
-    #[flame("ChunkData")]
     unsafe fn draw(&self) {
-        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
-        let count = self.amount;
-        gl::VertexAttribPointer(
-            0,
-            3,
-            gl::FLOAT,
-            gl::FALSE,
-            3 * mem::size_of::<GLfloat>() as i32,
-            ptr::null(),
-        );
-        gl::DrawArrays(gl::POINTS, 0, count / 3);
-        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        if self.amount > 0 {
+            draw_points(self.vbo, self.amount);
+        }
+    }
+
+    //Translucent voxels are sorted back to front again once the camera has moved far enough
+    unsafe fn draw_translucent(&mut self, cam_pos: &Vec3) {
+        let moved = self
+            .sorted_from
+            .is_none_or(|from| glm::distance(&from, cam_pos) > RESORT_DISTANCE);
+        if moved {
+            let distance = |v: &[f32]| glm::distance2(&Vec3::new(v[0], v[1], v[2]), cam_pos);
+            let mut voxels: Vec<&[f32]> = self.translucent.chunks(VOXEL_STRIDE).collect();
+            voxels.sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap());
+            upload(self.translucent_vbo, &voxels.concat());
+            self.sorted_from = Some(*cam_pos);
+        }
+        draw_points(self.translucent_vbo, self.translucent.len() as i32);
     }
 }
 
@@ -63,17 +113,22 @@ pub struct ChunkRender {
     state: SharedState,
     chunk_update_rx: Receiver<ChunkKey>,
     last_clear_render: bool,
+    pub stats: RenderStats,
+    draws: Arc<Counter>,
+    uploads: Arc<Counter>,
+    upload_bytes: Arc<Counter>,
 }
 
 impl ChunkRender {
-    #[flame("ChunkRender")]
     pub unsafe fn new(state: &SharedState, chunk_update_rx: Receiver<ChunkKey>) -> Self {
         let mut vao = 0 as u32;
 
         gl::GenVertexArrays(1, &mut vao);
         gl::BindVertexArray(vao);
 
-        gl::EnableVertexAttribArray(0);
+        for attrib in 0..5 {
+            gl::EnableVertexAttribArray(attrib);
+        }
         gl::BindVertexArray(0);
 
         ChunkRender {
@@ -85,10 +140,20 @@ impl ChunkRender {
             state: state.clone(),
             chunk_update_rx,
             last_clear_render: false,
+            stats: RenderStats::default(),
+            draws: state
+                .metrics
+                .counter("chunk_draws_total", "Chunk draw calls"),
+            uploads: state
+                .metrics
+                .counter("chunk_uploads_total", "Chunk render data uploads"),
+            upload_bytes: state.metrics.counter(
+                "chunk_upload_bytes_total",
+                "Bytes of chunk render data uploaded",
+            ),
         }
     }
 
-    #[flame("ChunkRender")]
     fn clear_queue(&mut self, clear_render: bool) {
         if clear_render && !self.last_clear_render {
             self.old_queue = self.queue.clone();
@@ -96,31 +161,52 @@ impl ChunkRender {
         }
     }
 
-    #[flame("ChunkRender")]
+    //Keys come again after their chunk was meshed again, chunks whose render data changed since
+    //it was uploaded are uploaded again
     fn insert_keys(&mut self) {
-        for key in self.chunk_update_rx.try_iter() {
-            println!("Rendering: {:?}", key);
-            self.render_map.entry(key).or_insert({
-                if self.vbo_stack.is_empty() {
-                    unsafe {
-                        let mut vbo = 0;
-                        gl::GenBuffers(1, &mut vbo);
-                        self.vbo_stack.push(vbo);
-                    }
+        let keys: Vec<ChunkKey> = self.chunk_update_rx.try_iter().collect();
+        for key in keys {
+            trace!("Rendering {:?}", key);
+            let version = self.render_version(&key);
+            if let Some(cd) = self.render_map.get_mut(&key) {
+                if cd.rendered && version.is_some_and(|v| v != cd.version) {
+                    cd.rendered = false;
                 }
-                ChunkData {
+            } else {
+                let data = ChunkData {
                     rendered: false,
                     amount: 0,
-                    vbo: self.vbo_stack.pop().unwrap(),
-                }
-            });
+                    bytes: 0,
+                    vbo: self.take_vbo(),
+                    translucent: Vec::new(),
+                    translucent_vbo: self.take_vbo(),
+                    sorted_from: None,
+                    version: 0,
+                };
+                self.render_map.insert(key, data);
+            }
             if !self.queue.contains(&key) {
                 self.queue.push(key);
             }
         }
     }
 
-    #[flame("ChunkRender")]
+    fn take_vbo(&mut self) -> u32 {
+        self.vbo_stack.pop().unwrap_or_else(|| unsafe {
+            let mut vbo = 0;
+            gl::GenBuffers(1, &mut vbo);
+            vbo
+        })
+    }
+
+    fn release(&mut self, key: &ChunkKey) {
+        if let Some(cd) = self.render_map.remove(key) {
+            self.vbo_stack.push(cd.vbo);
+            self.vbo_stack.push(cd.translucent_vbo);
+        }
+    }
+
+    //Draws the opaque and cutout voxels of every chunk in view
     pub fn process(&mut self, cam: &Camera) {
         let clear_render = *self.state.clear_render.read().unwrap();
 
@@ -130,57 +216,98 @@ impl ChunkRender {
 
         self.clear_old(clear_render);
 
+        self.stats = RenderStats::default();
         unsafe {
             self.process_queue(cam);
         }
 
         self.last_clear_render = clear_render;
+        self.stats.meshed = self.queue.len();
+    }
+
+    //Drops every chunk, for switching worlds
+    pub fn reset(&mut self) {
+        let keys: Vec<ChunkKey> = self.render_map.keys().copied().collect();
+        for key in keys.iter() {
+            self.release(key);
+        }
+        self.queue.clear();
+        self.old_queue.clear();
+    }
+
+    //Bytes of voxel data uploaded to the chunk buffers
+    pub fn vbo_bytes(&self) -> usize {
+        self.render_map.values().map(|cd| cd.bytes).sum()
     }
 
-    #[flame("ChunkRender")]
     fn clear_old(&mut self, clear_render: bool) {
         if !clear_render && self.last_clear_render {
             for i in 0..self.old_queue.len() {
                 let key = self.old_queue[i];
                 if !self.queue.contains(&key) {
-                    self.vbo_stack.push(self.render_map[&key].vbo);
-                    self.render_map.remove(&key);
+                    self.release(&key);
                 }
             }
         }
     }
 
-    #[flame("ChunkRender")]
-    fn chunk_render_data(&mut self, key: &ChunkKey) -> Vec<f32> {
+    fn render_version(&self, key: &ChunkKey) -> Option<u64> {
+        let world_id = *self.state.active_world.read().unwrap();
+        self.state
+            .world_registry
+            .get(&world_id)?
+            .pc
+            .chunk_render_version(key)
+    }
+
+    //Keys sent for a world that was switched away from may not exist in the active world, they
+    //get no data and are released, as do all keys once the active world is removed. The version
+    //is read first, so data meshed in between is uploaded again rather than missed
+    fn chunk_render_data(&mut self, key: &ChunkKey) -> (Vec<f32>, Vec<f32>, u64) {
         let world_id = *self.state.active_world.read().unwrap();
-        let active_world = self.state.world_registry.world(&world_id);
-        active_world.pc.chunk_render(&key).clone()
+        let active_world = match self.state.world_registry.get(&world_id) {
+            Some(world) if world.pc.chunk_exists(key) => world,
+            _ => return (Vec::new(), Vec::new(), 0),
+        };
+        let version = active_world.pc.chunk_render_version(key).unwrap_or(0);
+        (
+            active_world.pc.chunk_render(&key),
+            active_world.pc.chunk_translucent(&key),
+            version,
+        )
     }
 
-    #[flame("ChunkRender")]
     unsafe fn load_data(&mut self, entry: usize) -> bool {
+        let _span = span!("upload_chunk");
         let key = self.queue[entry];
-        let d = self.chunk_render_data(&key);
+        let (d, translucent, version) = self.chunk_render_data(&key);
         let cd = self.render_map.get_mut(&key).unwrap();
 
-        if d.len() > 0 {
-            cd.load_data(&d);
+        if d.len() > 0 || translucent.len() > 0 {
+            cd.load_data(&d, translucent, version);
+            self.uploads.inc();
+            self.upload_bytes.add(cd.bytes as u64);
         } else {
             self.queue.remove(entry);
-            self.vbo_stack.push(self.render_map[&key].vbo);
-            self.render_map.remove(&key);
+            self.release(&key);
             return false;
         }
 
         true
     }
 
-    #[flame("ChunkRender")]
-    unsafe fn process_queue_entry(&mut self, entry: usize, cam: &Camera) -> bool {
+    fn chunk_center(&self, key: &ChunkKey) -> Vec3 {
         let chunk_size = *self.state.chunk_size as i32;
         let half_size = chunk_size as f32 / 2.0;
-        let half_size_vec = Vec3::new(half_size, half_size, half_size);
+        Vec3::new(
+            (key.x * chunk_size) as f32,
+            (key.y * chunk_size) as f32,
+            (key.z * chunk_size) as f32,
+        ) + Vec3::new(half_size, half_size, half_size)
+    }
 
+    unsafe fn process_queue_entry(&mut self, entry: usize, cam: &Camera) -> bool {
+        let chunk_size = *self.state.chunk_size as f32;
         let key = self.queue[entry];
 
         if !self.render_map[&key].rendered {
@@ -189,21 +316,55 @@ impl ChunkRender {
             }
         }
 
-        let cd = self.render_map.get_mut(&key).unwrap();
-
-        let chunk_world_pos = Vec3::new(
-            (key.x * chunk_size) as f32,
-            (key.y * chunk_size) as f32,
-            (key.z * chunk_size) as f32,
-        ) + half_size_vec;
-        if cam.cube_in_view(chunk_world_pos, chunk_size as f32) {
+        if cam.cube_in_view(self.chunk_center(&key), chunk_size) {
+            let cd = &self.render_map[&key];
+            self.stats.visible += 1;
+            if cd.amount > 0 {
+                self.stats.drawn += 1;
+                self.draws.inc();
+            }
             cd.draw();
         }
 
         true
     }
 
-    #[flame("ChunkRender")]
+    //Draws the opaque and cutout voxels of every loaded chunk, for shadow maps where chunks
+    //outside the camera's view still cast shadows into it
+    pub unsafe fn draw_shadow_casters(&self) {
+        for key in self.queue.iter() {
+            let cd = &self.render_map[key];
+            if cd.rendered {
+                cd.draw();
+            }
+        }
+    }
+
+    //Draws translucent voxels chunk by chunk from the furthest chunk in view to the nearest,
+    //call after process with blending enabled
+    pub unsafe fn draw_translucent(&mut self, cam: &Camera) {
+        let chunk_size = *self.state.chunk_size as f32;
+        let mut chunks: Vec<(f32, ChunkKey)> = self
+            .queue
+            .iter()
+            .filter(|key| {
+                let cd = &self.render_map[key];
+                cd.rendered && !cd.translucent.is_empty()
+            })
+            .map(|key| (self.chunk_center(key), *key))
+            .filter(|(center, _)| cam.cube_in_view(*center, chunk_size))
+            .map(|(center, key)| (glm::distance2(&center, &cam.pos), key))
+            .collect();
+        chunks.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+        for (_, key) in chunks {
+            self.render_map
+                .get_mut(&key)
+                .unwrap()
+                .draw_translucent(&cam.pos);
+        }
+    }
+
     unsafe fn process_queue(&mut self, cam: &Camera) {
         let mut i = 0;
         while i < self.queue.len() {
@@ -0,0 +1,295 @@
+use std::mem;
+use std::os::raw::c_void;
+use std::ptr;
+
+use gl::types::*;
+use glm::{Vec2, Vec3};
+
+use super::font::{glyph_index, glyph_pixel, GLYPHS, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::geom::ChunkKey;
+use crate::shader::{Shader, ShaderError};
+
+//Size in font pixels of the cell each character takes, leaving a gap after and below it
+const CELL_WIDTH: usize = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT + 2;
+//Screen pixels per font pixel
+const SCALE: f32 = 2.0;
+//Space between the edge of the screen, the background and the text, in screen pixels
+const MARGIN: f32 = 8.0;
+//Floats per vertex: screen position and atlas coordinates, a negative u marks the background
+const VERTEX_FLOATS: usize = 4;
+//How often the frame rate shown is updated, in seconds
+const FPS_INTERVAL: f64 = 0.5;
+
+//Averages frame times over a short interval so the numbers shown are readable
+pub struct FrameTimer {
+    interval_start: f64,
+    frames: u32,
+    pub fps: f32,
+    pub frame_ms: f32,
+}
+
+impl FrameTimer {
+    pub fn new(now: f64) -> FrameTimer {
+        FrameTimer {
+            interval_start: now,
+            frames: 0,
+            fps: 0.0,
+            frame_ms: 0.0,
+        }
+    }
+
+    pub fn frame(&mut self, now: f64) {
+        self.frames += 1;
+        let elapsed = now - self.interval_start;
+        if elapsed >= FPS_INTERVAL {
+            self.fps = (self.frames as f64 / elapsed) as f32;
+            self.frame_ms = (elapsed * 1000.0 / self.frames as f64) as f32;
+            self.interval_start = now;
+            self.frames = 0;
+        }
+    }
+}
+
+//Everything the overlay shows, gathered once per frame
+#[derive(Debug, Clone)]
+pub struct HudStats {
+    pub fps: f32,
+    pub frame_ms: f32,
+    pub cam_pos: Vec3,
+    pub chunk: ChunkKey,
+    pub loaded_chunks: usize,
+    pub meshed_chunks: usize,
+    pub visible_chunks: usize,
+    pub drawn_chunks: usize,
+    pub ticket_queue: usize,
+    pub gen_queue: usize,
+    pub vbo_bytes: usize,
+}
+
+impl HudStats {
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("FPS: {:.0} ({:.2} ms)", self.fps, self.frame_ms),
+            format!(
+                "Position: {:.1} {:.1} {:.1}",
+                self.cam_pos.x, self.cam_pos.y, self.cam_pos.z
+            ),
+            format!("Chunk: {} {} {}", self.chunk.x, self.chunk.y, self.chunk.z),
+            format!(
+                "Chunks: {} loaded, {} meshed, {} visible, {} drawn",
+                self.loaded_chunks, self.meshed_chunks, self.visible_chunks, self.drawn_chunks
+            ),
+            format!(
+                "Queues: {} tickets, {} to generate",
+                self.ticket_queue, self.gen_queue
+            ),
+            format!(
+                "VBO memory: {:.1} MiB",
+                self.vbo_bytes as f32 / (1024.0 * 1024.0)
+            ),
+        ]
+    }
+}
+
+//Two triangles covering (x0, y0) to (x1, y1) on screen and (u0, v0) to (u1, v1) in the atlas
+fn push_quad(out: &mut Vec<f32>, (x0, y0, x1, y1): (f32, f32, f32, f32), uv: [f32; 4]) {
+    let [u0, v0, u1, v1] = uv;
+    out.extend_from_slice(&[
+        x0, y0, u0, v0, x1, y0, u1, v0, x1, y1, u1, v1, x0, y0, u0, v0, x1, y1, u1, v1, x0, y1, u0,
+        v1,
+    ]);
+}
+
+//Vertices for a background box and the text of lines in the top left corner of the screen,
+//in screen pixels with y growing downwards
+pub fn text_vertices(lines: &[String]) -> Vec<f32> {
+    let mut out = Vec::new();
+    let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+    if columns == 0 {
+        return out;
+    }
+    let cell_w = CELL_WIDTH as f32 * SCALE;
+    let cell_h = CELL_HEIGHT as f32 * SCALE;
+    let background = (
+        MARGIN,
+        MARGIN,
+        2.0 * MARGIN + columns as f32 * cell_w,
+        2.0 * MARGIN + lines.len() as f32 * cell_h,
+    );
+    push_quad(&mut out, background, [-1.0; 4]);
+
+    let atlas_width = (GLYPHS.len() * CELL_WIDTH) as f32;
+    for (row, line) in lines.iter().enumerate() {
+        let y = 1.5 * MARGIN + row as f32 * cell_h;
+        for (column, c) in line.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            let x = 1.5 * MARGIN + column as f32 * cell_w;
+            let u = (glyph_index(c) * CELL_WIDTH) as f32 / atlas_width;
+            push_quad(
+                &mut out,
+                (x, y, x + cell_w, y + cell_h),
+                [u, 0.0, u + CELL_WIDTH as f32 / atlas_width, 1.0],
+            );
+        }
+    }
+    out
+}
+
+//One cell per glyph side by side, one byte per pixel
+fn font_atlas() -> (usize, usize, Vec<u8>) {
+    let width = GLYPHS.len() * CELL_WIDTH;
+    let mut pixels = vec![0u8; width * CELL_HEIGHT];
+    for glyph in 0..GLYPHS.len() {
+        for x in 0..GLYPH_WIDTH {
+            for y in 0..GLYPH_HEIGHT {
+                if glyph_pixel(glyph, x, y) {
+                    pixels[y * width + glyph * CELL_WIDTH + x] = 255;
+                }
+            }
+        }
+    }
+    (width, CELL_HEIGHT, pixels)
+}
+
+//Text overlay drawn over the finished frame
+pub struct Hud {
+    program: Shader,
+    vao: u32,
+    vbo: u32,
+    font: u32,
+    pub visible: bool,
+}
+
+impl Hud {
+    pub unsafe fn new() -> Result<Hud, ShaderError> {
+        let program = Shader::new("src/shaders/hud.vert", "src/shaders/hud.frag")?;
+
+        let (width, height, pixels) = font_atlas();
+        let mut font = 0;
+        gl::GenTextures(1, &mut font);
+        gl::BindTexture(gl::TEXTURE_2D, font);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::R8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RED,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const c_void,
+        );
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        for name in [gl::TEXTURE_MIN_FILTER, gl::TEXTURE_MAG_FILTER].iter() {
+            gl::TexParameteri(gl::TEXTURE_2D, *name, gl::NEAREST as i32);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        let (mut vao, mut vbo) = (0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let stride = (VERTEX_FLOATS * mem::size_of::<GLfloat>()) as i32;
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(0, 2, gl::FLOAT, gl::FALSE, stride, ptr::null());
+        gl::EnableVertexAttribArray(1);
+        gl::VertexAttribPointer(
+            1,
+            2,
+            gl::FLOAT,
+            gl::FALSE,
+            stride,
+            (2 * mem::size_of::<GLfloat>()) as *const c_void,
+        );
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        gl::BindVertexArray(0);
+
+        Ok(Hud {
+            program,
+            vao,
+            vbo,
+            font,
+            visible: false,
+        })
+    }
+
+    pub fn reload_if_changed(&mut self) {
+        self.program.reload_if_changed();
+    }
+
+    //Draws lines over whatever framebuffer is bound, which is screen_size pixels large
+    pub unsafe fn draw(&self, lines: &[String], screen_size: &Vec2) {
+        if !self.visible {
+            return;
+        }
+        let vertices = text_vertices(lines);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            (vertices.len() * mem::size_of::<GLfloat>()) as GLsizeiptr,
+            vertices.as_ptr() as *const c_void,
+            gl::STREAM_DRAW,
+        );
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+        gl::Disable(gl::DEPTH_TEST);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        self.program.use_program();
+        self.program.set_vec2("screenSize", screen_size);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.font);
+        gl::BindVertexArray(self.vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, (vertices.len() / VERTEX_FLOATS) as i32);
+        gl::BindVertexArray(0);
+        gl::Disable(gl::BLEND);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_vertices() {
+        let lines = vec!["ab c".to_string(), "d".to_string()];
+        let vertices = text_vertices(&lines);
+        //A background quad and a quad per character that isn't a space
+        assert_eq!(vertices.len(), 5 * 6 * VERTEX_FLOATS);
+        let background_right = vertices[4];
+        assert_eq!(
+            background_right,
+            2.0 * MARGIN + 4.0 * CELL_WIDTH as f32 * SCALE
+        );
+        assert!(vertices[..6 * VERTEX_FLOATS]
+            .chunks(VERTEX_FLOATS)
+            .all(|v| v[2] < 0.0));
+        assert!(text_vertices(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_glyphs() {
+        assert_eq!(glyph_index('!'), 1);
+        assert_eq!(glyph_index('\n'), glyph_index('?'));
+        //The bar of 'T' runs along the top row
+        let t = glyph_index('T');
+        assert!((0..GLYPH_WIDTH).all(|x| glyph_pixel(t, x, 0)));
+        assert!(!glyph_pixel(t, 0, 1));
+    }
+
+    #[test]
+    fn test_frame_timer() {
+        let mut timer = FrameTimer::new(0.0);
+        for i in 1..=10 {
+            timer.frame(i as f64 * 0.05);
+        }
+        assert!((timer.fps - 20.0).abs() < 1e-3);
+        assert!((timer.frame_ms - 50.0).abs() < 1e-3);
+    }
+}
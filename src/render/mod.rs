@@ -1,5 +1,15 @@
 mod camera;
+pub mod camera_path;
+pub mod capture;
 mod chunk_render;
+mod font;
+pub mod hud;
+pub mod post;
+pub mod scene;
+pub mod shadow;
+pub mod sky;
+pub mod sun;
+pub mod uniforms;
 
 pub use self::camera::Camera;
 pub use self::chunk_render::ChunkRender;
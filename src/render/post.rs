@@ -0,0 +1,566 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ptr;
+
+use gl::types::*;
+use glm::Vec2;
+use log::{error, warn};
+
+use super::uniforms::{CAMERA_BLOCK_BINDING, CAMERA_BLOCK_NAME};
+use crate::shader::{Shader, ShaderError};
+use crate::span;
+
+const VERTEX_SHADER: &str = "src/shaders/post/fullscreen.vert";
+const SHADER_DIR: &str = "src/shaders/post";
+//Passes that read the Camera uniform block
+const CAMERA_PASSES: &[&str] = &["ssao"];
+//Horizontal and vertical blur pairs run over the bloom texture
+const BLOOM_BLUR_PASSES: usize = 3;
+
+//Every pass in the order it runs with its default parameters. Input passes render a texture
+//that is combined with the scene before the colour passes, which each read the previous
+//pass's output and the last writes to the window
+const PASSES: &[(&str, bool, &[(&str, f32)])] = &[
+    (
+        "ssao",
+        false,
+        &[("radius", 0.75), ("bias", 0.03), ("strength", 1.0)],
+    ),
+    ("bloom", false, &[("threshold", 1.0), ("intensity", 0.3)]),
+    ("tonemap", true, &[("exposure", 1.0)]),
+    ("gamma", true, &[("gamma", 2.2)]),
+    ("fxaa", true, &[("span", 8.0), ("reduce", 0.125)]),
+];
+
+#[derive(Debug)]
+pub enum PostError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    UnknownPass(String),
+    UnknownParam { pass: String, param: String },
+    InvalidValue { pass: String, param: String },
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PostError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            PostError::Parse(err) => write!(f, "invalid post config: {}", err),
+            PostError::UnknownPass(pass) => write!(f, "unknown post pass \"{}\"", pass),
+            PostError::UnknownParam { pass, param } => {
+                write!(f, "post pass \"{}\" has no parameter \"{}\"", pass, param)
+            }
+            PostError::InvalidValue { pass, param } => write!(
+                f,
+                "\"{}.{}\" must be a number, or a bool for enabled",
+                pass, param
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PostError {}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PassConfig {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub params: BTreeMap<String, f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PostConfig {
+    pub passes: Vec<PassConfig>,
+}
+
+impl Default for PostConfig {
+    fn default() -> Self {
+        PostConfig {
+            passes: PASSES
+                .iter()
+                .map(|(name, _, params)| PassConfig {
+                    name,
+                    enabled: true,
+                    params: params.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+//Names of every pass in the order they run
+pub fn pass_names() -> impl Iterator<Item = &'static str> {
+    PASSES.iter().map(|(name, _, _)| *name)
+}
+
+impl PostConfig {
+    //A table per pass holding enabled and any parameters to override, missing passes and
+    //parameters keep their defaults
+    pub fn parse(src: &str) -> Result<PostConfig, PostError> {
+        let tables: HashMap<String, BTreeMap<String, toml::Value>> =
+            toml::from_str(src).map_err(PostError::Parse)?;
+        let mut config = PostConfig::default();
+        for (name, table) in tables {
+            let pass = config
+                .passes
+                .iter_mut()
+                .find(|p| p.name == name)
+                .ok_or_else(|| PostError::UnknownPass(name.clone()))?;
+            for (param, value) in table {
+                let invalid = || PostError::InvalidValue {
+                    pass: name.clone(),
+                    param: param.clone(),
+                };
+                if param == "enabled" {
+                    pass.enabled = value.as_bool().ok_or_else(invalid)?;
+                    continue;
+                }
+                let value = match value {
+                    toml::Value::Float(v) => v as f32,
+                    toml::Value::Integer(v) => v as f32,
+                    _ => return Err(invalid()),
+                };
+                match pass.params.get_mut(&param) {
+                    Some(current) => *current = value,
+                    None => {
+                        return Err(PostError::UnknownParam {
+                            pass: name.clone(),
+                            param,
+                        })
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<PostConfig, PostError> {
+        let src = fs::read_to_string(path).map_err(|e| PostError::Io(path.to_string(), e))?;
+        PostConfig::parse(&src)
+    }
+
+    pub fn load_or_default(path: &str) -> PostConfig {
+        match PostConfig::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Using the default post passes, {}", err);
+                PostConfig::default()
+            }
+        }
+    }
+}
+
+//A fullscreen pass, its parameters are set as float uniforms of the same name when the
+//shader has one
+pub struct PostPass {
+    pub name: &'static str,
+    pub enabled: bool,
+    colour: bool,
+    shader: Shader,
+    params: BTreeMap<String, f32>,
+}
+
+impl PostPass {
+    fn new(config: &PassConfig) -> Result<PostPass, ShaderError> {
+        let colour = PASSES
+            .iter()
+            .any(|(name, colour, _)| *name == config.name && *colour);
+        Ok(PostPass {
+            name: config.name,
+            enabled: config.enabled,
+            colour,
+            shader: post_shader(config.name)?,
+            params: config.params.clone(),
+        })
+    }
+
+    fn param(&self, name: &str) -> f32 {
+        self.params[name]
+    }
+
+    unsafe fn use_program(&self) {
+        self.shader.use_program();
+        for (name, value) in self.params.iter() {
+            if self.shader.has_uniform(name) {
+                self.shader.set_float(name, *value);
+            }
+        }
+    }
+}
+
+fn post_shader(name: &str) -> Result<Shader, ShaderError> {
+    let mut shader = Shader::new(VERTEX_SHADER, &format!("{}/{}.frag", SHADER_DIR, name))?;
+    if CAMERA_PASSES.contains(&name) {
+        shader.bind_uniform_block(CAMERA_BLOCK_NAME, CAMERA_BLOCK_BINDING);
+    }
+    Ok(shader)
+}
+
+//A framebuffer with one colour texture and optionally a depth texture
+struct RenderTarget {
+    fbo: u32,
+    colour: u32,
+    depth: u32,
+    width: i32,
+    height: i32,
+}
+
+unsafe fn new_texture(width: i32, height: i32, format: GLenum, filter: GLenum) -> u32 {
+    let (external, kind) = match format {
+        gl::DEPTH_COMPONENT32F => (gl::DEPTH_COMPONENT, gl::FLOAT),
+        _ => (gl::RGBA, gl::FLOAT),
+    };
+    let mut texture = 0;
+    gl::GenTextures(1, &mut texture);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexImage2D(
+        gl::TEXTURE_2D,
+        0,
+        format as i32,
+        width,
+        height,
+        0,
+        external,
+        kind,
+        ptr::null(),
+    );
+    let params = [
+        (gl::TEXTURE_MIN_FILTER, filter),
+        (gl::TEXTURE_MAG_FILTER, filter),
+        (gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE),
+        (gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE),
+    ];
+    for (name, value) in params.iter() {
+        gl::TexParameteri(gl::TEXTURE_2D, *name, *value as i32);
+    }
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    texture
+}
+
+impl RenderTarget {
+    unsafe fn new(width: i32, height: i32, format: GLenum, with_depth: bool) -> RenderTarget {
+        let colour = new_texture(width, height, format, gl::LINEAR);
+        let depth = if with_depth {
+            new_texture(width, height, gl::DEPTH_COMPONENT32F, gl::NEAREST)
+        } else {
+            0
+        };
+
+        let mut fbo = 0;
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::FramebufferTexture2D(
+            gl::FRAMEBUFFER,
+            gl::COLOR_ATTACHMENT0,
+            gl::TEXTURE_2D,
+            colour,
+            0,
+        );
+        if with_depth {
+            gl::FramebufferTexture2D(
+                gl::FRAMEBUFFER,
+                gl::DEPTH_ATTACHMENT,
+                gl::TEXTURE_2D,
+                depth,
+                0,
+            );
+        }
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            error!("Post framebuffer {}x{} is incomplete", width, height);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        RenderTarget {
+            fbo,
+            colour,
+            depth,
+            width,
+            height,
+        }
+    }
+
+    unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+
+    unsafe fn delete(&self) {
+        gl::DeleteFramebuffers(1, &self.fbo);
+        gl::DeleteTextures(1, &self.colour);
+        if self.depth != 0 {
+            gl::DeleteTextures(1, &self.depth);
+        }
+    }
+}
+
+//Everything sized to the window, rebuilt when it changes
+struct Targets {
+    scene: RenderTarget,
+    ao: RenderTarget,
+    bloom: [RenderTarget; 2],
+    ping: [RenderTarget; 2],
+}
+
+impl Targets {
+    unsafe fn new(width: i32, height: i32) -> Targets {
+        let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+        Targets {
+            scene: RenderTarget::new(width, height, gl::RGBA16F, true),
+            ao: RenderTarget::new(width, height, gl::R8, false),
+            bloom: [
+                RenderTarget::new(half_width, half_height, gl::RGBA16F, false),
+                RenderTarget::new(half_width, half_height, gl::RGBA16F, false),
+            ],
+            ping: [
+                RenderTarget::new(width, height, gl::RGBA16F, false),
+                RenderTarget::new(width, height, gl::RGBA16F, false),
+            ],
+        }
+    }
+
+    unsafe fn delete(&self) {
+        self.scene.delete();
+        self.ao.delete();
+        for target in self.bloom.iter().chain(self.ping.iter()) {
+            target.delete();
+        }
+    }
+}
+
+//1x1 texture used in place of a disabled input pass
+unsafe fn constant_texture(value: f32) -> u32 {
+    let texture = new_texture(1, 1, gl::RGBA16F, gl::NEAREST);
+    let pixel = [value; 4];
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+    gl::TexSubImage2D(
+        gl::TEXTURE_2D,
+        0,
+        0,
+        0,
+        1,
+        1,
+        gl::RGBA,
+        gl::FLOAT,
+        pixel.as_ptr() as *const _,
+    );
+    gl::BindTexture(gl::TEXTURE_2D, 0);
+    texture
+}
+
+unsafe fn bind_texture(unit: u32, texture: u32) {
+    gl::ActiveTexture(gl::TEXTURE0 + unit);
+    gl::BindTexture(gl::TEXTURE_2D, texture);
+}
+
+//The scene is drawn into an HDR framebuffer between begin and finish, finish runs the passes
+//and writes the result to the window. Pass shaders sample the input colour on texture unit 0,
+//scene depth on 1, ambient occlusion on 2 and bloom on 3, see shaders/post
+pub struct PostChain {
+    passes: Vec<PostPass>,
+    composite: Shader,
+    blur: Shader,
+    vao: u32,
+    white: u32,
+    black: u32,
+    targets: Option<Targets>,
+}
+
+impl PostChain {
+    pub unsafe fn new(config: &PostConfig) -> Result<PostChain, ShaderError> {
+        let passes = config
+            .passes
+            .iter()
+            .map(PostPass::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        Ok(PostChain {
+            passes,
+            composite: post_shader("composite")?,
+            blur: post_shader("blur")?,
+            vao,
+            white: constant_texture(1.0),
+            black: constant_texture(0.0),
+            targets: None,
+        })
+    }
+
+    pub fn reload_if_changed(&mut self) {
+        for pass in self.passes.iter_mut() {
+            pass.shader.reload_if_changed();
+        }
+        self.composite.reload_if_changed();
+        self.blur.reload_if_changed();
+    }
+
+    //Flips a pass on or off, returns whether it is now enabled or None for unknown passes
+    pub fn toggle(&mut self, name: &str) -> Option<bool> {
+        let pass = self.passes.iter_mut().find(|p| p.name == name)?;
+        pass.enabled = !pass.enabled;
+        Some(pass.enabled)
+    }
+
+    fn pass(&self, name: &str) -> &PostPass {
+        self.passes.iter().find(|p| p.name == name).unwrap()
+    }
+
+    //Framebuffer holding the depth of the last frame drawn
+    pub fn scene_framebuffer(&self) -> u32 {
+        self.targets.as_ref().map(|t| t.scene.fbo).unwrap_or(0)
+    }
+
+    //Binds the HDR framebuffer, resized to the current viewport
+    pub unsafe fn begin(&mut self) {
+        let mut viewport = [0 as GLint; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        let (width, height) = (viewport[2].max(1), viewport[3].max(1));
+        let resized = match self.targets.as_ref() {
+            Some(t) => t.scene.width != width || t.scene.height != height,
+            None => true,
+        };
+        if resized {
+            if let Some(targets) = self.targets.take() {
+                targets.delete();
+            }
+            self.targets = Some(Targets::new(width, height));
+        }
+        self.targets.as_ref().unwrap().scene.bind();
+    }
+
+    //Runs the enabled passes and leaves the window framebuffer bound
+    pub unsafe fn finish(&mut self) {
+        let _span = span!("post");
+        let targets = self.targets.as_ref().unwrap();
+        gl::Disable(gl::DEPTH_TEST);
+        gl::BindVertexArray(self.vao);
+        bind_texture(0, targets.scene.colour);
+        bind_texture(1, targets.scene.depth);
+
+        let ssao = self.pass("ssao");
+        let ao = if ssao.enabled {
+            targets.ao.bind();
+            ssao.use_program();
+            draw_fullscreen();
+            targets.ao.colour
+        } else {
+            self.white
+        };
+
+        let bloom = self.pass("bloom");
+        let bloom_texture = if bloom.enabled {
+            targets.bloom[0].bind();
+            bloom.use_program();
+            draw_fullscreen();
+            self.blur.use_program();
+            for i in 0..BLOOM_BLUR_PASSES * 2 {
+                let direction = if i % 2 == 0 {
+                    Vec2::new(1.0, 0.0)
+                } else {
+                    Vec2::new(0.0, 1.0)
+                };
+                targets.bloom[(i + 1) % 2].bind();
+                bind_texture(0, targets.bloom[i % 2].colour);
+                self.blur.set_vec2("direction", &direction);
+                draw_fullscreen();
+            }
+            targets.bloom[0].colour
+        } else {
+            self.black
+        };
+
+        bind_texture(2, ao);
+        bind_texture(3, bloom_texture);
+        let colour_passes: Vec<&PostPass> = self
+            .passes
+            .iter()
+            .filter(|p| p.colour && p.enabled)
+            .collect();
+
+        //The composite adds the input passes to the scene, then each colour pass reads the
+        //previous output and the last one draws to the window
+        let (width, height) = (targets.scene.width, targets.scene.height);
+        let output = |i: usize| {
+            if i == colour_passes.len() {
+                gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                gl::Viewport(0, 0, width, height);
+            } else {
+                targets.ping[i % 2].bind();
+            }
+        };
+        output(0);
+        bind_texture(0, targets.scene.colour);
+        self.composite.use_program();
+        self.composite.set_float(
+            "aoStrength",
+            if ssao.enabled {
+                ssao.param("strength")
+            } else {
+                0.0
+            },
+        );
+        self.composite
+            .set_float("bloomIntensity", bloom.param("intensity"));
+        draw_fullscreen();
+        for (i, pass) in colour_passes.iter().enumerate() {
+            bind_texture(0, targets.ping[i % 2].colour);
+            output(i + 1);
+            pass.use_program();
+            draw_fullscreen();
+        }
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
+
+unsafe fn draw_fullscreen() {
+    gl::DrawArrays(gl::TRIANGLES, 0, 3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_overrides_defaults() {
+        let config = PostConfig::parse(
+            r#"
+            [ssao]
+            enabled = false
+            radius = 2
+
+            [gamma]
+            gamma = 2.4
+            "#,
+        )
+        .unwrap();
+        let ssao = &config.passes[0];
+        assert_eq!(ssao.name, "ssao");
+        assert!(!ssao.enabled);
+        assert_eq!(ssao.params["radius"], 2.0);
+        assert_eq!(ssao.params["bias"], 0.03);
+        assert_eq!(config.passes[3].params["gamma"], 2.4);
+        assert!(config.passes[4].enabled);
+        assert_eq!(
+            config.passes.iter().map(|p| p.name).collect::<Vec<_>>(),
+            pass_names().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown() {
+        match PostConfig::parse("[dof]\nenabled = true") {
+            Err(PostError::UnknownPass(pass)) => assert_eq!(pass, "dof"),
+            other => panic!("expected unknown pass, got {:?}", other),
+        }
+        match PostConfig::parse("[fxaa]\nquality = 3") {
+            Err(PostError::UnknownParam { pass, param }) => {
+                assert_eq!((pass.as_str(), param.as_str()), ("fxaa", "quality"))
+            }
+            other => panic!("expected unknown parameter, got {:?}", other),
+        }
+        assert!(PostConfig::parse("[bloom]\nenabled = 1").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use gl::types::*;
+use glm::{Mat4, Vec2};
+
+use super::post::{PostChain, PostConfig};
+use super::shadow::{cascade_matrices, cascade_splits, ShadowMaps};
+use super::sky::{SkyPass, FOG_START};
+use super::sun::{ShadowConfig, Sun};
+use super::uniforms::{
+    CameraBlock, LightingBlock, UniformBuffer, CAMERA_BLOCK_BINDING, CAMERA_BLOCK_NAME,
+    LIGHTING_BLOCK_BINDING, LIGHTING_BLOCK_NAME,
+};
+use super::{Camera, ChunkRender};
+use crate::shader::{Shader, ShaderError};
+use crate::span;
+
+//The programs, buffers and textures used to draw a frame of the world
+pub struct Scene {
+    program: Shader,
+    shadow_program: Shader,
+    sky_program: Shader,
+    sky: SkyPass,
+    post: PostChain,
+    camera_ubo: UniformBuffer<CameraBlock>,
+    lighting_ubo: UniformBuffer<LightingBlock>,
+    shadows: ShadowMaps,
+    shadow_config: ShadowConfig,
+    voxel_texture: u32,
+    voxel_size: f32,
+    fog_distance: f32,
+}
+
+impl Scene {
+    pub unsafe fn new(
+        shadow_config: ShadowConfig,
+        post_config: &PostConfig,
+        voxel_texture: u32,
+        voxel_size: f32,
+        fog_distance: f32,
+    ) -> Result<Scene, ShaderError> {
+        let mut program = Shader::new("src/shaders/raybox.vert", "src/shaders/colored.frag")?;
+        let mut shadow_program = Shader::new("src/shaders/raybox.vert", "src/shaders/shadow.frag")?;
+        let mut sky_program = Shader::new("src/shaders/sky.vert", "src/shaders/sky.frag")?;
+        for shader in [&mut program, &mut shadow_program, &mut sky_program].iter_mut() {
+            shader.bind_uniform_block(CAMERA_BLOCK_NAME, CAMERA_BLOCK_BINDING);
+        }
+        for shader in [&mut program, &mut sky_program].iter_mut() {
+            shader.bind_uniform_block(LIGHTING_BLOCK_NAME, LIGHTING_BLOCK_BINDING);
+        }
+
+        Ok(Scene {
+            program,
+            shadow_program,
+            sky_program,
+            sky: SkyPass::new(),
+            post: PostChain::new(post_config)?,
+            camera_ubo: UniformBuffer::new(CAMERA_BLOCK_BINDING),
+            lighting_ubo: UniformBuffer::new(LIGHTING_BLOCK_BINDING),
+            shadows: ShadowMaps::new(shadow_config.resolution, shadow_config.cascades),
+            shadow_config,
+            voxel_texture,
+            voxel_size,
+            fog_distance,
+        })
+    }
+
+    pub fn reload_if_changed(&mut self) {
+        self.program.reload_if_changed();
+        self.shadow_program.reload_if_changed();
+        self.sky_program.reload_if_changed();
+        self.post.reload_if_changed();
+    }
+
+    //Flips a post pass on or off, returns whether it is now enabled or None for unknown passes
+    pub fn toggle_post_pass(&mut self, name: &str) -> Option<bool> {
+        self.post.toggle(name)
+    }
+
+    //Framebuffer whose depth attachment holds the depth of the last frame drawn
+    pub fn depth_framebuffer(&self) -> u32 {
+        self.post.scene_framebuffer()
+    }
+
+    //Draws the world seen by cam through the projection p, which may differ from the
+    //camera's own when rendering screenshot tiles. Shadow cascades always follow the camera.
+    //The frame goes through the post passes and ends up in the window framebuffer
+    pub unsafe fn draw(
+        &mut self,
+        renderer: &mut ChunkRender,
+        cam: &Camera,
+        p: &Mat4,
+        screen_size: &Vec2,
+        sun: &Sun,
+    ) {
+        let _span = span!("draw_scene");
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.voxel_texture);
+        gl::BindVertexArray(renderer.vao);
+
+        let mut lighting =
+            LightingBlock::new(sun, self.fog_distance * FOG_START, self.fog_distance);
+        if self.shadow_config.enabled && sun.daylight > 0.0 {
+            self.draw_shadows(renderer, cam, sun, &mut lighting);
+        }
+        self.lighting_ubo.update(&lighting);
+
+        self.post.begin();
+        gl::ClearColor(sun.horizon.x, sun.horizon.y, sun.horizon.z, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        self.camera_ubo.update(&CameraBlock::new(
+            &cam.view(),
+            p,
+            screen_size,
+            self.voxel_size,
+        ));
+        self.sky_program.use_program();
+        self.sky.draw();
+
+        gl::BindVertexArray(renderer.vao);
+        self.program.use_program();
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.shadows.texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+        self.program.set_bool("translucentPass", false);
+        renderer.process(cam);
+
+        //Translucent voxels are blended over the rest without writing depth, so ones behind
+        //each other all show
+        self.program.set_bool("translucentPass", true);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+        renderer.draw_translucent(cam);
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+
+        self.post.finish();
+    }
+
+    unsafe fn draw_shadows(
+        &mut self,
+        renderer: &mut ChunkRender,
+        cam: &Camera,
+        sun: &Sun,
+        lighting: &mut LightingBlock,
+    ) {
+        let _span = span!("draw_shadows");
+        let mut viewport = [0 as GLint; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        let config = &self.shadow_config;
+        //Nothing past the fog needs shadows
+        let far = config.distance.min(cam.far_plane).min(self.fog_distance);
+        let splits = cascade_splits(cam.near_plane, far, config.cascades, config.split_lambda);
+        let size = Vec2::new(config.resolution as f32, config.resolution as f32);
+
+        self.shadow_program.use_program();
+        let mut near = cam.near_plane;
+        for (cascade, end) in splits.iter().enumerate() {
+            let (view, projection) = cascade_matrices(
+                &cam.view(),
+                cam.fov.to_radians(),
+                cam.aspect_ratio,
+                near,
+                *end,
+                &sun.direction,
+                config.resolution,
+            );
+            lighting.set_cascade(cascade, &(projection * view), *end);
+
+            self.shadows.bind_cascade(cascade);
+            self.camera_ubo.update(&CameraBlock::new(
+                &view,
+                &projection,
+                &size,
+                self.voxel_size,
+            ));
+            renderer.draw_shadow_casters();
+            near = *end;
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+}
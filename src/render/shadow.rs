@@ -0,0 +1,184 @@
+use std::ptr;
+
+use gl::types::*;
+use glm::{Mat4, Vec3, Vec4};
+
+//Has to match MAX_CASCADES in shaders/lighting.glsl
+pub const MAX_CASCADES: usize = 4;
+
+//How far behind a cascade, towards the sun, voxels still cast shadows into it
+const CASTER_MARGIN: f32 = 64.0;
+
+//Distances from the camera where each cascade ends, blending evenly spaced and logarithmic
+//splits by lambda (0 is even, 1 logarithmic)
+pub fn cascade_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let log = near * (far / near).powf(t);
+            let even = near + (far - near) * t;
+            lambda * log + (1.0 - lambda) * even
+        })
+        .collect()
+}
+
+//Light view and orthographic projection covering the camera frustum between near and far.
+//The box is sized from a bounding sphere and moved in whole shadow map texels so shadows
+//don't shimmer as the camera turns and moves
+pub fn cascade_matrices(
+    cam_view: &Mat4,
+    fov_y: f32,
+    aspect_ratio: f32,
+    near: f32,
+    far: f32,
+    sun_direction: &Vec3,
+    resolution: u32,
+) -> (Mat4, Mat4) {
+    let inv_view = glm::inverse(cam_view);
+    let tan_y = (fov_y / 2.0).tan();
+    let tan_x = tan_y * aspect_ratio;
+    let mut corners = Vec::with_capacity(8);
+    for d in [near, far].iter() {
+        for (x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)].iter() {
+            let corner = inv_view * Vec4::new(x * tan_x * d, y * tan_y * d, -d, 1.0);
+            corners.push(corner.xyz());
+        }
+    }
+    let center = corners.iter().fold(Vec3::zeros(), |acc, c| acc + c) / corners.len() as f32;
+    let radius = corners
+        .iter()
+        .map(|c| glm::distance(c, &center))
+        .fold(0.0, f32::max)
+        .ceil();
+
+    let up = if sun_direction.y.abs() > 0.99 {
+        Vec3::new(0.0, 0.0, 1.0)
+    } else {
+        Vec3::new(0.0, 1.0, 0.0)
+    };
+    let eye = center + sun_direction * (radius + CASTER_MARGIN);
+    let view = glm::look_at(&eye, &center, &up);
+    let mut projection = glm::ortho(
+        -radius,
+        radius,
+        -radius,
+        radius,
+        0.0,
+        2.0 * radius + CASTER_MARGIN,
+    );
+
+    let texels = resolution as f32 / 2.0;
+    let origin = projection * view * Vec4::new(0.0, 0.0, 0.0, 1.0) * texels;
+    projection[(0, 3)] += (origin.x.round() - origin.x) / texels;
+    projection[(1, 3)] += (origin.y.round() - origin.y) / texels;
+    (view, projection)
+}
+
+//Depth texture array with a layer per cascade, filtered with PCF in shaders/colored.frag
+pub struct ShadowMaps {
+    fbo: u32,
+    pub texture: u32,
+    pub resolution: u32,
+}
+
+impl ShadowMaps {
+    pub unsafe fn new(resolution: u32, cascades: usize) -> ShadowMaps {
+        let mut texture = 0;
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY,
+            0,
+            gl::DEPTH_COMPONENT32F as i32,
+            resolution as i32,
+            resolution as i32,
+            cascades as i32,
+            0,
+            gl::DEPTH_COMPONENT,
+            gl::FLOAT,
+            ptr::null(),
+        );
+        let params = [
+            (gl::TEXTURE_MIN_FILTER, gl::NEAREST),
+            (gl::TEXTURE_MAG_FILTER, gl::NEAREST),
+            (gl::TEXTURE_WRAP_S, gl::CLAMP_TO_BORDER),
+            (gl::TEXTURE_WRAP_T, gl::CLAMP_TO_BORDER),
+        ];
+        for (name, value) in params.iter() {
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, *name, *value as i32);
+        }
+        //Everything outside the map is lit
+        let border = [1.0f32; 4];
+        gl::TexParameterfv(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_BORDER_COLOR,
+            border.as_ptr(),
+        );
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+
+        let mut fbo = 0;
+        gl::GenFramebuffers(1, &mut fbo);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo);
+        gl::DrawBuffer(gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        ShadowMaps {
+            fbo,
+            texture,
+            resolution,
+        }
+    }
+
+    //Renders into cascade from here on, bind framebuffer 0 and reset the viewport afterwards
+    pub unsafe fn bind_cascade(&self, cascade: usize) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+        gl::FramebufferTextureLayer(
+            gl::FRAMEBUFFER,
+            gl::DEPTH_ATTACHMENT,
+            self.texture,
+            0,
+            cascade as GLint,
+        );
+        gl::Viewport(0, 0, self.resolution as i32, self.resolution as i32);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cascade_splits() {
+        let even = cascade_splits(1.0, 100.0, 4, 0.0);
+        assert_eq!(even, vec![25.75, 50.5, 75.25, 100.0]);
+        let log = cascade_splits(1.0, 100.0, 2, 1.0);
+        assert!((log[0] - 10.0).abs() < 1e-4);
+        assert!((log[1] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_cascade_covers_frustum_slice() {
+        let cam_view = glm::look_at(
+            &Vec3::new(3.0, 10.0, -4.0),
+            &Vec3::new(20.0, 2.0, 7.0),
+            &Vec3::new(0.0, 1.0, 0.0),
+        );
+        let sun = Vec3::new(0.4, 1.0, 0.3).normalize();
+        let (near, far) = (5.0, 40.0);
+        let (view, projection) =
+            cascade_matrices(&cam_view, 1.2, 16.0 / 9.0, near, far, &sun, 1024);
+
+        let inv_view = glm::inverse(&cam_view);
+        for d in [near, far].iter() {
+            for (x, y) in [(-1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].iter() {
+                let tan_y = 0.6f32.tan();
+                let corner =
+                    inv_view * Vec4::new(x * tan_y * 16.0 / 9.0 * d, y * tan_y * d, -d, 1.0);
+                let ndc = projection * view * corner;
+                assert!(ndc.x.abs() <= 1.0 && ndc.y.abs() <= 1.0 && ndc.z.abs() <= 1.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//Where fog starts as a fraction of the fog distance
+pub const FOG_START: f32 = 0.6;
+
+//Distance from the camera to the closest voxel outside the loaded world, when chunks up to
+//load_radius - 1 chunks away (counting steps along the axes) from the camera's chunk are loaded.
+//The camera can be anywhere in its chunk, so half a chunk diagonal is taken off
+pub fn fog_distance(load_radius: u32, chunk_size: usize) -> f32 {
+    let r = load_radius as i32;
+    let mut closest = f32::MAX;
+    for x in 0..=r {
+        for y in 0..=r - x {
+            let z = r - x - y;
+            //Distance from the centre of the camera's chunk to the nearest point of the
+            //unloaded chunk at (x, y, z), in chunks
+            let near = |c: i32| (c as f32 - 0.5).max(0.0);
+            let d = (near(x).powi(2) + near(y).powi(2) + near(z).powi(2)).sqrt();
+            closest = closest.min(d);
+        }
+    }
+    ((closest - 3f32.sqrt() / 2.0) * chunk_size as f32).max(0.0)
+}
+
+//Draws the sky gradient over the whole screen, see shaders/sky.vert
+pub struct SkyPass {
+    vao: u32,
+}
+
+impl SkyPass {
+    pub unsafe fn new() -> SkyPass {
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        SkyPass { vao }
+    }
+
+    //Draws behind everything else, call with the sky program in use
+    pub unsafe fn draw(&self) {
+        gl::DepthMask(gl::FALSE);
+        gl::Disable(gl::DEPTH_TEST);
+        gl::BindVertexArray(self.vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::TRUE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fog_distance() {
+        //The nearest unloaded chunks are ones like (2, 2, 1) when radius is 5
+        let expected = (1.5f32 * 1.5 * 2.0 + 0.25).sqrt() - 3f32.sqrt() / 2.0;
+        assert!((fog_distance(5, 16) - expected * 16.0).abs() < 1e-4);
+        assert!(fog_distance(6, 16) > fog_distance(5, 16));
+        assert_eq!(fog_distance(1, 16), 0.0);
+    }
+}
@@ -0,0 +1,222 @@
+use std::f32::consts::PI;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use glm::Vec3;
+use log::warn;
+use serde::Deserialize;
+
+use super::shadow::MAX_CASCADES;
+
+//How far above or below the horizon the sun tints the sky with the dusk colour
+const DUSK_HEIGHT: f32 = 0.3;
+
+#[derive(Debug)]
+pub enum SunError {
+    Io(String, io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl fmt::Display for SunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SunError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            SunError::Parse(err) => write!(f, "invalid sun config: {}", err),
+            SunError::Invalid(msg) => write!(f, "invalid sun config: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SunError {}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    pub cascades: usize,
+    pub resolution: u32,
+    pub distance: f32,
+    pub split_lambda: f32,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        ShadowConfig {
+            enabled: true,
+            cascades: 3,
+            resolution: 2048,
+            distance: 150.0,
+            split_lambda: 0.7,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SunConfig {
+    pub colour: [f32; 3],
+    pub intensity: f32,
+    pub ambient: f32,
+    pub night_ambient: f32,
+    pub day_length: u32,
+    pub start_time: f32,
+    pub direction: [f32; 3],
+    pub day_sky: [f32; 3],
+    pub night_sky: [f32; 3],
+    pub day_horizon: [f32; 3],
+    pub night_horizon: [f32; 3],
+    pub dusk_sky: [f32; 3],
+    pub shadows: ShadowConfig,
+}
+
+impl Default for SunConfig {
+    fn default() -> Self {
+        SunConfig {
+            colour: [1.0, 0.95, 0.85],
+            intensity: 1.0,
+            ambient: 0.3,
+            night_ambient: 0.05,
+            day_length: 24000,
+            start_time: 0.3,
+            direction: [0.3, 1.0, 0.2],
+            day_sky: [0.35, 0.6, 1.0],
+            night_sky: [0.01, 0.015, 0.05],
+            day_horizon: [0.75, 0.85, 0.95],
+            night_horizon: [0.05, 0.06, 0.12],
+            dusk_sky: [0.95, 0.55, 0.3],
+            shadows: ShadowConfig::default(),
+        }
+    }
+}
+
+//The sun at one moment of the day
+#[derive(Debug, Copy, Clone)]
+pub struct Sun {
+    //Unit vector pointing towards the sun
+    pub direction: Vec3,
+    //Light colour scaled by intensity, black while the sun is down
+    pub colour: Vec3,
+    pub ambient: Vec3,
+    //Sky colour straight up and at the horizon, fog fades to the horizon colour
+    pub zenith: Vec3,
+    pub horizon: Vec3,
+    //0 at night to 1 during the day
+    pub daylight: f32,
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+impl SunConfig {
+    pub fn parse(src: &str) -> Result<SunConfig, SunError> {
+        let config: SunConfig = toml::from_str(src).map_err(SunError::Parse)?;
+        if config.intensity < 0.0 || config.ambient < 0.0 || config.night_ambient < 0.0 {
+            return Err(SunError::Invalid(
+                "intensity and ambient can't be negative".to_string(),
+            ));
+        }
+        if config.shadows.cascades == 0 || config.shadows.cascades > MAX_CASCADES {
+            return Err(SunError::Invalid(format!(
+                "shadows.cascades must be between 1 and {}",
+                MAX_CASCADES
+            )));
+        }
+        if config.shadows.distance <= 0.0 || config.shadows.resolution == 0 {
+            return Err(SunError::Invalid(
+                "shadows.distance and shadows.resolution must be positive".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<SunConfig, SunError> {
+        let src = fs::read_to_string(path).map_err(|e| SunError::Io(path.to_string(), e))?;
+        SunConfig::parse(&src)
+    }
+
+    pub fn load_or_default(path: &str) -> SunConfig {
+        match SunConfig::load(path) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Using the default sun, {}", err);
+                SunConfig::default()
+            }
+        }
+    }
+
+    //Time of day from 0 to 1 at tick, 0.25 is sunrise and 0.5 noon
+    pub fn time_of_day(&self, tick: u32) -> f32 {
+        if self.day_length == 0 {
+            return self.start_time;
+        }
+        let day = (tick % self.day_length) as f32 / self.day_length as f32;
+        (self.start_time + day).fract()
+    }
+
+    //With a day length of 0 the sun stays at direction, otherwise it rises in +x and sets in
+    //-x, leaning towards +z so it never passes straight overhead
+    pub fn sun(&self, tick: u32) -> Sun {
+        let direction = if self.day_length == 0 {
+            vec3(self.direction)
+        } else {
+            let angle = 2.0 * PI * (self.time_of_day(tick) - 0.25);
+            Vec3::new(angle.cos(), angle.sin(), 0.3)
+        }
+        .normalize();
+
+        let daylight = smoothstep(-0.1, 0.15, direction.y);
+        let dusk = (1.0 - direction.y.abs() / DUSK_HEIGHT).max(0.0);
+        let dusk_sky = vec3(self.dusk_sky);
+        let zenith = glm::lerp(&vec3(self.night_sky), &vec3(self.day_sky), daylight);
+        let zenith = glm::lerp(&zenith, &dusk_sky, dusk * 0.3);
+        let horizon = glm::lerp(&vec3(self.night_horizon), &vec3(self.day_horizon), daylight);
+        let horizon = glm::lerp(&horizon, &dusk_sky, dusk * 0.8);
+        let ambient = self.night_ambient + (self.ambient - self.night_ambient) * daylight;
+
+        Sun {
+            direction,
+            colour: vec3(self.colour) * self.intensity * daylight,
+            ambient: zenith * ambient,
+            zenith,
+            horizon,
+            daylight,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_day_night_cycle() {
+        let config = SunConfig {
+            day_length: 1000,
+            start_time: 0.0,
+            ..SunConfig::default()
+        };
+        let noon = config.sun(500);
+        assert!(noon.direction.y > 0.9);
+        assert_eq!(noon.daylight, 1.0);
+
+        let midnight = config.sun(0);
+        assert!(midnight.direction.y < -0.9);
+        assert_eq!(midnight.daylight, 0.0);
+        assert_eq!(midnight.colour, Vec3::zeros());
+        assert_eq!(config.time_of_day(1250), 0.25);
+    }
+
+    #[test]
+    fn test_invalid_cascades() {
+        assert!(SunConfig::parse("[shadows]\ncascades = 9").is_err());
+        assert_eq!(SunConfig::parse("day_length = 0").unwrap().day_length, 0);
+    }
+}
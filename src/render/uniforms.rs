@@ -0,0 +1,147 @@
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::c_void;
+
+use gl::types::*;
+use glm::{Mat4, Vec2};
+
+use super::shadow::MAX_CASCADES;
+use super::sun::Sun;
+
+pub const CAMERA_BLOCK_BINDING: u32 = 0;
+pub const CAMERA_BLOCK_NAME: &str = "Camera";
+pub const LIGHTING_BLOCK_BINDING: u32 = 1;
+pub const LIGHTING_BLOCK_NAME: &str = "Lighting";
+
+//Matches the std140 layout of the Camera block in shaders/camera.glsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct CameraBlock {
+    pub mvp: [f32; 16],
+    pub p: [f32; 16],
+    pub inv_p: [f32; 16],
+    pub inv_mv: [f32; 16],
+    pub screen_size: [f32; 2],
+    pub voxel_size: f32,
+    _pad: f32,
+}
+
+impl CameraBlock {
+    pub fn new(mv: &Mat4, p: &Mat4, screen_size: &Vec2, voxel_size: f32) -> CameraBlock {
+        let mvp = p * mv;
+        let mut block = CameraBlock {
+            mvp: [0.0; 16],
+            p: [0.0; 16],
+            inv_p: [0.0; 16],
+            inv_mv: [0.0; 16],
+            screen_size: [screen_size.x, screen_size.y],
+            voxel_size,
+            _pad: 0.0,
+        };
+        block.mvp.copy_from_slice(mvp.as_slice());
+        block.p.copy_from_slice(p.as_slice());
+        block.inv_p.copy_from_slice(glm::inverse(p).as_slice());
+        block.inv_mv.copy_from_slice(glm::inverse(mv).as_slice());
+        block
+    }
+}
+
+//The cascade ends share a single vec4
+const _: () = assert!(MAX_CASCADES <= 4);
+
+//Matches the std140 layout of the Lighting block in shaders/lighting.glsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct LightingBlock {
+    pub light_space: [[f32; 16]; MAX_CASCADES],
+    //A vec4, entries past the cascades in use are unused
+    pub cascade_ends: [f32; 4],
+    //xyz towards the sun, w the number of shadow cascades in use
+    pub sun_direction: [f32; 4],
+    pub sun_colour: [f32; 4],
+    pub ambient: [f32; 4],
+    pub zenith: [f32; 4],
+    pub horizon: [f32; 4],
+    //Distances where fog starts and where it fully hides the world
+    pub fog: [f32; 4],
+}
+
+impl LightingBlock {
+    pub fn new(sun: &Sun, fog_start: f32, fog_end: f32) -> LightingBlock {
+        let d = sun.direction;
+        let (z, h) = (sun.zenith, sun.horizon);
+        LightingBlock {
+            light_space: [[0.0; 16]; MAX_CASCADES],
+            cascade_ends: [0.0; 4],
+            sun_direction: [d.x, d.y, d.z, 0.0],
+            sun_colour: [sun.colour.x, sun.colour.y, sun.colour.z, 1.0],
+            ambient: [sun.ambient.x, sun.ambient.y, sun.ambient.z, 1.0],
+            zenith: [z.x, z.y, z.z, 1.0],
+            horizon: [h.x, h.y, h.z, 1.0],
+            fog: [fog_start, fog_end, 0.0, 0.0],
+        }
+    }
+
+    pub fn set_cascade(&mut self, cascade: usize, light_space: &Mat4, end: f32) {
+        self.light_space[cascade].copy_from_slice(light_space.as_slice());
+        self.cascade_ends[cascade] = end;
+        self.sun_direction[3] = self.sun_direction[3].max(cascade as f32 + 1.0);
+    }
+}
+
+//A uniform buffer holding one T, bound to a fixed binding point shared by every program
+pub struct UniformBuffer<T> {
+    id: u32,
+    binding: u32,
+    _data: PhantomData<T>,
+}
+
+impl<T: Copy> UniformBuffer<T> {
+    pub unsafe fn new(binding: u32) -> UniformBuffer<T> {
+        let mut id = 0;
+        gl::GenBuffers(1, &mut id);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, id);
+        gl::BufferData(
+            gl::UNIFORM_BUFFER,
+            mem::size_of::<T>() as GLsizeiptr,
+            std::ptr::null(),
+            gl::DYNAMIC_DRAW,
+        );
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, binding, id);
+        UniformBuffer {
+            id,
+            binding,
+            _data: PhantomData,
+        }
+    }
+
+    pub unsafe fn update(&self, data: &T) {
+        gl::BindBuffer(gl::UNIFORM_BUFFER, self.id);
+        gl::BufferSubData(
+            gl::UNIFORM_BUFFER,
+            0,
+            mem::size_of::<T>() as GLsizeiptr,
+            data as *const T as *const c_void,
+        );
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gl::BindBufferBase(gl::UNIFORM_BUFFER, self.binding, self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_block_is_std140_sized() {
+        //Four mat4, a vec2 and a float, rounded up to a multiple of a vec4
+        assert_eq!(mem::size_of::<CameraBlock>(), 4 * 64 + 16);
+    }
+
+    #[test]
+    fn test_lighting_block_is_std140_sized() {
+        //A mat4 per cascade followed by seven vec4
+        assert_eq!(mem::size_of::<LightingBlock>(), MAX_CASCADES * 64 + 7 * 16);
+    }
+}
@@ -1,147 +1,554 @@
-use std::ffi::{CStr, CString};
-use std::fs::File;
-use std::io::Read;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
 use std::ptr;
-use std::str;
+use std::time::{Duration, Instant, SystemTime};
 
 use gl::types::*;
 
 use glm::{Mat4, Vec2, Vec3};
 
-pub struct Shader {
-    pub id: u32,
+use log::{error, info, warn};
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Io {
+        path: String,
+        err: io::Error,
+    },
+    Include {
+        path: String,
+        line: usize,
+        msg: String,
+    },
+    Compile {
+        path: String,
+        stage: &'static str,
+        log: String,
+        sources: Vec<PathBuf>,
+    },
+    Link {
+        vertex: String,
+        fragment: String,
+        log: String,
+    },
 }
 
-impl Shader {
-    pub fn new(vertex_path: &str, fragment_path: &str) -> Shader {
-        let mut shader = Shader { id: 0 };
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderError::Io { path, err } => write!(f, "failed to read {}: {}", path, err),
+            ShaderError::Include { path, line, msg } => write!(f, "{}:{}: {}", path, line, msg),
+            ShaderError::Compile {
+                path,
+                stage,
+                log,
+                sources,
+            } => {
+                writeln!(f, "failed to compile {} shader {}:", stage, path)?;
+                //Log lines are prefixed with the source string number set by #line
+                for (i, source) in sources.iter().enumerate() {
+                    writeln!(f, "  source {}: {}", i, source.display())?;
+                }
+                write!(f, "{}", log)
+            }
+            ShaderError::Link {
+                vertex,
+                fragment,
+                log,
+            } => write!(f, "failed to link {} and {}:\n{}", vertex, fragment, log),
+        }
+    }
+}
 
-        let mut v_shader_file =
-            File::open(vertex_path).unwrap_or_else(|_| panic!("Failed to open {}", vertex_path));
-        let mut f_shader_file = File::open(fragment_path)
-            .unwrap_or_else(|_| panic!("Failed to open {}", fragment_path));
+impl std::error::Error for ShaderError {}
 
-        let mut vertex_code = String::new();
-        let mut fragment_code = String::new();
+//How often the render loop's reload checks look at the source files
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
 
-        v_shader_file
-            .read_to_string(&mut vertex_code)
-            .expect("Failed to read vertex shader");
-        f_shader_file
-            .read_to_string(&mut fragment_code)
-            .expect("Failed to read fragment shader");
+//Paths are compared to include files once, so different ways of naming a file have to become
+//the same path. Ones that aren't on disk are only cleaned of . and ..
+fn normalize(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(out.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                out.pop();
+            }
+            component => out.push(component),
+        }
+    }
+    out
+}
 
-        let v_shader_code = CString::new(vertex_code.as_bytes()).unwrap();
-        let f_shader_code = CString::new(fragment_code.as_bytes()).unwrap();
+//Expands #include "file" lines, paths are relative to the including file and every file is
+//included at most once. Each file gets its own #line source number, indexing into sources.
+pub fn preprocess(
+    path: &Path,
+    load: &dyn Fn(&Path) -> io::Result<String>,
+    sources: &mut Vec<PathBuf>,
+) -> Result<String, ShaderError> {
+    let path = &normalize(path);
+    let source_idx = sources.len();
+    sources.push(path.to_path_buf());
+    let src = load(path).map_err(|err| ShaderError::Io {
+        path: path.display().to_string(),
+        err,
+    })?;
 
-        unsafe {
-            let vertex = gl::CreateShader(gl::VERTEX_SHADER);
-            gl::ShaderSource(vertex, 1, &v_shader_code.as_ptr(), ptr::null());
-            gl::CompileShader(vertex);
-            shader.check_compile_errors(vertex, "VERTEX");
+    let mut out = String::new();
+    for (i, line) in src.lines().enumerate() {
+        let trimmed = line.trim();
+        if !trimmed.starts_with("#include") {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+
+        let include_err = |msg: &str| ShaderError::Include {
+            path: path.display().to_string(),
+            line: i + 1,
+            msg: msg.to_string(),
+        };
+        let name = trimmed["#include".len()..].trim();
+        if name.len() < 2 || !name.starts_with('"') || !name.ends_with('"') {
+            return Err(include_err("expected #include \"file\""));
+        }
+        let include_path = normalize(
+            &path
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(&name[1..name.len() - 1]),
+        );
+        if sources.contains(&include_path) {
+            //An empty line keeps the line numbers after it right
+            out.push('\n');
+            continue;
+        }
+
+        let include_idx = sources.len();
+        let included = preprocess(&include_path, load, sources)?;
+        out.push_str(&format!("#line 1 {}\n", include_idx));
+        out.push_str(&included);
+        out.push_str(&format!("#line {} {}\n", i + 2, source_idx));
+    }
+    Ok(out)
+}
 
-            let fragment = gl::CreateShader(gl::FRAGMENT_SHADER);
-            gl::ShaderSource(fragment, 1, &f_shader_code.as_ptr(), ptr::null());
-            gl::CompileShader(fragment);
-            shader.check_compile_errors(fragment, "FRAGMENT");
+pub struct Shader {
+    pub id: u32,
+    vertex_path: String,
+    fragment_path: String,
+    sources: Vec<(PathBuf, Option<SystemTime>)>,
+    last_poll: Cell<Instant>,
+    uniforms: HashMap<String, i32>,
+    blocks: Vec<(String, u32)>,
+    warned: RefCell<HashSet<String>>,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl Shader {
+    pub fn new(vertex_path: &str, fragment_path: &str) -> Result<Shader, ShaderError> {
+        let mut shader = Shader {
+            id: 0,
+            vertex_path: vertex_path.to_string(),
+            fragment_path: fragment_path.to_string(),
+            sources: Vec::new(),
+            last_poll: Cell::new(Instant::now()),
+            uniforms: HashMap::new(),
+            blocks: Vec::new(),
+            warned: RefCell::new(HashSet::new()),
+        };
+        shader.reload()?;
+        Ok(shader)
+    }
+
+    //Rebuilds the program from disk, the current program is kept if anything fails
+    pub fn reload(&mut self) -> Result<(), ShaderError> {
+        let load = |p: &Path| fs::read_to_string(p);
+        let mut vertex_sources = Vec::new();
+        let vertex_code = preprocess(Path::new(&self.vertex_path), &load, &mut vertex_sources)?;
+        let mut fragment_sources = Vec::new();
+        let fragment_code =
+            preprocess(Path::new(&self.fragment_path), &load, &mut fragment_sources)?;
+
+        let id = unsafe {
+            let vertex = compile(
+                gl::VERTEX_SHADER,
+                "VERTEX",
+                &self.vertex_path,
+                &vertex_code,
+                vertex_sources.clone(),
+            )?;
+            let fragment = match compile(
+                gl::FRAGMENT_SHADER,
+                "FRAGMENT",
+                &self.fragment_path,
+                &fragment_code,
+                fragment_sources.clone(),
+            ) {
+                Ok(fragment) => fragment,
+                Err(err) => {
+                    gl::DeleteShader(vertex);
+                    return Err(err);
+                }
+            };
 
             let id = gl::CreateProgram();
             gl::AttachShader(id, vertex);
             gl::AttachShader(id, fragment);
             gl::LinkProgram(id);
-            shader.check_compile_errors(id, "PROGRAM");
-
             gl::DeleteShader(vertex);
             gl::DeleteShader(fragment);
-            shader.id = id;
+
+            let mut success = gl::FALSE as GLint;
+            gl::GetProgramiv(id, gl::LINK_STATUS, &mut success);
+            if success != gl::TRUE as GLint {
+                let log = program_log(id);
+                gl::DeleteProgram(id);
+                return Err(ShaderError::Link {
+                    vertex: self.vertex_path.clone(),
+                    fragment: self.fragment_path.clone(),
+                    log,
+                });
+            }
+            id
+        };
+
+        if self.id != 0 {
+            unsafe { gl::DeleteProgram(self.id) };
         }
+        self.id = id;
+        self.uniforms = unsafe { active_uniforms(id) };
+        self.warned.borrow_mut().clear();
+        for (name, binding) in self.blocks.clone() {
+            self.bind_uniform_block(&name, binding);
+        }
+        self.sources = vertex_sources
+            .into_iter()
+            .chain(fragment_sources.into_iter())
+            .map(|p| {
+                let time = modified(&p);
+                (p, time)
+            })
+            .collect();
+        Ok(())
+    }
 
-        shader
+    //Only looks at the files every POLL_INTERVAL, false in between
+    pub fn changed(&self) -> bool {
+        if self.last_poll.get().elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll.set(Instant::now());
+        self.sources
+            .iter()
+            .any(|(path, time)| modified(path) != *time)
+    }
+
+    //Polled from the render loop, returns true when a new program was swapped in
+    pub fn reload_if_changed(&mut self) -> bool {
+        if !self.changed() {
+            return false;
+        }
+        match self.reload() {
+            Ok(()) => {
+                info!(
+                    "Reloaded shader {} {}",
+                    self.vertex_path, self.fragment_path
+                );
+                true
+            }
+            Err(err) => {
+                error!("{}", err);
+                //Remember the new timestamps so a broken file is reported once per save
+                for (path, time) in self.sources.iter_mut() {
+                    *time = modified(path);
+                }
+                false
+            }
+        }
     }
 
     pub unsafe fn use_program(&self) {
         gl::UseProgram(self.id)
     }
 
+    //Looks up a cached location, unknown names are reported once and return None
+    fn location(&self, name: &str) -> Option<i32> {
+        match self.uniforms.get(name) {
+            Some(location) => Some(*location),
+            None => {
+                if self.warned.borrow_mut().insert(name.to_string()) {
+                    warn!(
+                        "Shader {} {} has no active uniform {}",
+                        self.vertex_path, self.fragment_path, name
+                    );
+                }
+                None
+            }
+        }
+    }
+
+    pub fn has_uniform(&self, name: &str) -> bool {
+        self.uniforms.contains_key(name)
+    }
+
+    //Binds a uniform block to a binding point, remembered so reloads keep the binding
+    pub fn bind_uniform_block(&mut self, name: &str, binding: u32) {
+        if !self.blocks.iter().any(|(n, _)| n == name) {
+            self.blocks.push((name.to_string(), binding));
+        }
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            let index = gl::GetUniformBlockIndex(self.id, c_name.as_ptr());
+            if index == gl::INVALID_INDEX {
+                warn!(
+                    "Shader {} {} has no uniform block {}",
+                    self.vertex_path, self.fragment_path, name
+                );
+                return;
+            }
+            gl::UniformBlockBinding(self.id, index, binding);
+        }
+    }
+
     #[allow(dead_code)]
-    pub unsafe fn set_bool(&self, name: &CStr, value: bool) {
-        gl::Uniform1i(gl::GetUniformLocation(self.id, name.as_ptr()), value as i32);
+    pub unsafe fn set_bool(&self, name: &str, value: bool) {
+        if let Some(location) = self.location(name) {
+            gl::Uniform1i(location, value as i32);
+        }
     }
 
     #[allow(dead_code)]
-    pub unsafe fn set_int(&self, name: &CStr, value: i32) {
-        gl::Uniform1i(gl::GetUniformLocation(self.id, name.as_ptr()), value);
+    pub unsafe fn set_int(&self, name: &str, value: i32) {
+        if let Some(location) = self.location(name) {
+            gl::Uniform1i(location, value);
+        }
     }
 
     #[allow(dead_code)]
-    pub unsafe fn set_float(&self, name: &CStr, value: f32) {
-        gl::Uniform1f(gl::GetUniformLocation(self.id, name.as_ptr()), value);
+    pub unsafe fn set_float(&self, name: &str, value: f32) {
+        if let Some(location) = self.location(name) {
+            gl::Uniform1f(location, value);
+        }
     }
 
     #[allow(dead_code)]
-    pub unsafe fn set_vec3(&self, name: &CStr, value: &Vec3) {
-        gl::Uniform3fv(
-            gl::GetUniformLocation(self.id, name.as_ptr()),
-            1,
-            value.as_ptr(),
-        );
+    pub unsafe fn set_vec3(&self, name: &str, value: &Vec3) {
+        if let Some(location) = self.location(name) {
+            gl::Uniform3fv(location, 1, value.as_ptr());
+        }
     }
 
     #[allow(dead_code)]
-    pub unsafe fn set_vec2(&self, name: &CStr, value: &Vec2) {
-        gl::Uniform2fv(
-            gl::GetUniformLocation(self.id, name.as_ptr()),
-            1,
-            value.as_ptr(),
-        );
+    pub unsafe fn set_vec2(&self, name: &str, value: &Vec2) {
+        if let Some(location) = self.location(name) {
+            gl::Uniform2fv(location, 1, value.as_ptr());
+        }
     }
 
     #[allow(dead_code)]
-    pub unsafe fn set_mat4(&self, name: &CStr, mat: &Mat4) {
-        gl::UniformMatrix4fv(
-            gl::GetUniformLocation(self.id, name.as_ptr()),
-            1,
-            gl::FALSE,
-            mat.as_ptr(),
+    pub unsafe fn set_mat4(&self, name: &str, mat: &Mat4) {
+        if let Some(location) = self.location(name) {
+            gl::UniformMatrix4fv(location, 1, gl::FALSE, mat.as_ptr());
+        }
+    }
+}
+
+//Uniforms inside blocks have no location and are skipped, arrays are stored both as
+//"name[0]" as reported by GL and as "name"
+unsafe fn active_uniforms(program: u32) -> HashMap<String, i32> {
+    let mut uniforms = HashMap::new();
+    let mut count = 0;
+    gl::GetProgramiv(program, gl::ACTIVE_UNIFORMS, &mut count);
+    let mut max_len = 0;
+    gl::GetProgramiv(program, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+    for i in 0..count as u32 {
+        let mut name = vec![0u8; max_len.max(1) as usize];
+        let mut len = 0;
+        let mut size = 0;
+        let mut kind = 0;
+        gl::GetActiveUniform(
+            program,
+            i,
+            max_len,
+            &mut len,
+            &mut size,
+            &mut kind,
+            name.as_mut_ptr() as *mut GLchar,
         );
+        name.truncate(len as usize);
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let c_name = CString::new(name.as_str()).unwrap();
+        let location = gl::GetUniformLocation(program, c_name.as_ptr());
+        if location < 0 {
+            continue;
+        }
+        if name.ends_with("[0]") {
+            uniforms.insert(name[..name.len() - 3].to_string(), location);
+        }
+        uniforms.insert(name, location);
     }
+    uniforms
+}
 
-    unsafe fn check_compile_errors(&self, shader: u32, type_: &str) {
-        let mut success = gl::FALSE as GLint;
-        let mut info_log = Vec::with_capacity(1024);
-        info_log.set_len(1024 - 1); // subtract 1 to skip the trailing null character
-        if type_ != "PROGRAM" {
-            gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetShaderInfoLog(
-                    shader,
-                    1024,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                println!(
-                    "ERROR::SHADER_COMPILATION_ERROR of type: {}\n{}\n \
-                         -- --------------------------------------------------- -- ",
-                    type_,
-                    str::from_utf8(&info_log).unwrap()
-                );
-            }
-        } else {
-            gl::GetProgramiv(shader, gl::LINK_STATUS, &mut success);
-            if success != gl::TRUE as GLint {
-                gl::GetProgramInfoLog(
-                    shader,
-                    1024,
-                    ptr::null_mut(),
-                    info_log.as_mut_ptr() as *mut GLchar,
-                );
-                println!(
-                    "ERROR::PROGRAM_LINKING_ERROR of type: {}\n{}\n \
-                         -- --------------------------------------------------- -- ",
-                    type_,
-                    str::from_utf8(&info_log).unwrap()
-                );
-            }
+unsafe fn compile(
+    kind: GLenum,
+    stage: &'static str,
+    path: &str,
+    code: &str,
+    sources: Vec<PathBuf>,
+) -> Result<u32, ShaderError> {
+    let c_code = CString::new(code.as_bytes()).map_err(|_| ShaderError::Compile {
+        path: path.to_string(),
+        stage,
+        log: "source contains a nul byte".to_string(),
+        sources: sources.clone(),
+    })?;
+    let shader = gl::CreateShader(kind);
+    gl::ShaderSource(shader, 1, &c_code.as_ptr(), ptr::null());
+    gl::CompileShader(shader);
+
+    let mut success = gl::FALSE as GLint;
+    gl::GetShaderiv(shader, gl::COMPILE_STATUS, &mut success);
+    if success != gl::TRUE as GLint {
+        let mut len = 0;
+        gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
+        let mut log = vec![0u8; len.max(1) as usize];
+        gl::GetShaderInfoLog(
+            shader,
+            len,
+            ptr::null_mut(),
+            log.as_mut_ptr() as *mut GLchar,
+        );
+        gl::DeleteShader(shader);
+        return Err(ShaderError::Compile {
+            path: path.to_string(),
+            stage,
+            log: log_to_string(log),
+            sources,
+        });
+    }
+    Ok(shader)
+}
+
+unsafe fn program_log(program: u32) -> String {
+    let mut len = 0;
+    gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
+    let mut log = vec![0u8; len.max(1) as usize];
+    gl::GetProgramInfoLog(
+        program,
+        len,
+        ptr::null_mut(),
+        log.as_mut_ptr() as *mut GLchar,
+    );
+    log_to_string(log)
+}
+
+fn log_to_string(mut log: Vec<u8>) -> String {
+    while log.last() == Some(&0) {
+        log.pop();
+    }
+    String::from_utf8_lossy(&log).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn loader(files: HashMap<&'static str, &'static str>) -> impl Fn(&Path) -> io::Result<String> {
+        move |p: &Path| {
+            files
+                .get(p.to_str().unwrap())
+                .map(|s| s.to_string())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "missing"))
+        }
+    }
+
+    #[test]
+    fn test_preprocess_include() {
+        let mut files = HashMap::new();
+        files.insert(
+            "shaders/main.frag",
+            "#version 440\n#include \"lib.glsl\"\nvoid main() {}\n",
+        );
+        files.insert("shaders/lib.glsl", "float f() { return 1.0; }\n");
+        let mut sources = Vec::new();
+        let out = preprocess(Path::new("shaders/main.frag"), &loader(files), &mut sources).unwrap();
+        assert_eq!(
+            out,
+            "#version 440\n#line 1 1\nfloat f() { return 1.0; }\n#line 3 0\nvoid main() {}\n"
+        );
+        assert_eq!(sources[1], PathBuf::from("shaders/lib.glsl"));
+    }
+
+    #[test]
+    fn test_preprocess_includes_once() {
+        let mut files = HashMap::new();
+        files.insert("a.glsl", "#include \"b.glsl\"\n#include \"b.glsl\"\n");
+        files.insert("b.glsl", "#include \"a.glsl\"\nint b;\n");
+        let mut sources = Vec::new();
+        let out = preprocess(Path::new("a.glsl"), &loader(files), &mut sources).unwrap();
+        assert_eq!(out.matches("int b;").count(), 1);
+        assert_eq!(sources.len(), 2);
+    }
+
+    #[test]
+    fn test_preprocess_duplicate_include_keeps_lines() {
+        let mut files = HashMap::new();
+        files.insert(
+            "main.frag",
+            "#include \"lib.glsl\"\n#include \"lib.glsl\"\nvoid main() {}\n",
+        );
+        files.insert("lib.glsl", "int b;\n");
+        let mut sources = Vec::new();
+        let out = preprocess(Path::new("main.frag"), &loader(files), &mut sources).unwrap();
+        //void main() is the third line of main.frag, two lines after the #line 2 0 directive
+        let lines: Vec<&str> = out.lines().collect();
+        let directive = lines.iter().position(|l| *l == "#line 2 0").unwrap();
+        assert_eq!(lines[directive + 2], "void main() {}");
+    }
+
+    #[test]
+    fn test_preprocess_normalizes_includes() {
+        let mut files = HashMap::new();
+        files.insert(
+            "a.glsl",
+            "#include \"lib/b.glsl\"\n#include \"./lib/../lib/b.glsl\"\n",
+        );
+        files.insert("lib/b.glsl", "#include \"../a.glsl\"\nint b;\n");
+        let mut sources = Vec::new();
+        let out = preprocess(Path::new("./a.glsl"), &loader(files), &mut sources).unwrap();
+        assert_eq!(out.matches("int b;").count(), 1);
+        assert_eq!(
+            sources,
+            vec![PathBuf::from("a.glsl"), PathBuf::from("lib/b.glsl")]
+        );
+    }
+
+    #[test]
+    fn test_preprocess_missing_include() {
+        let mut files = HashMap::new();
+        files.insert("a.glsl", "#include \"nope.glsl\"\n");
+        let mut sources = Vec::new();
+        match preprocess(Path::new("a.glsl"), &loader(files), &mut sources) {
+            Err(ShaderError::Io { path, .. }) => assert_eq!(path, "nope.glsl"),
+            _ => panic!("expected io error"),
         }
     }
 }
@@ -0,0 +1,10 @@
+// Per frame camera data, shared by every program through one uniform buffer
+
+layout (std140) uniform Camera {
+    mat4 mvp;
+    mat4 p;
+    mat4 invP;
+    mat4 invMv;
+    vec2 screenSize;
+    float voxelSize;
+};
@@ -2,92 +2,81 @@
 
 in vec3 posBox;
 in vec3 radiusBox;
+// Texture array layers of the top, side and bottom faces
+flat in vec3 texLayers;
+flat in float opacity;
+// Bit i is set when the face along normal i (see geom::normals) is hidden
+flat in int hiddenFaces;
+// 2 bits of occlusion per face corner, faces 0-2 in x and 3-5 in y (see geom::ao)
+flat in ivec2 ao;
 
-uniform mat4 mvp;
-uniform mat4 invP;
-uniform mat4 invMv;
-uniform vec2 screenSize;
-uniform float voxelSize;
+#include "camera.glsl"
+#include "lighting.glsl"
+#include "sky.glsl"
 
-uniform sampler2D voxelTexture;
+uniform sampler2DArray voxelTexture;
+layout (binding = 1) uniform sampler2DArray shadowMap;
+uniform bool translucentPass;
 
 
 out vec4 fragColor;
 
-struct Ray {
-    vec3 direction;
-    vec3 origin;
-};
-
-struct Box {
-    vec3     center;
-    vec3     radius;
-    vec3     invRadius;
-    mat3     rotation;
-};
-
-float maxComponent(vec3 v) { return max (max(v.x, v.y), v.z); }
-
-float safeInverse(float x) { return (x == 0.0) ? 1e12 : (1.0 / x); }
-vec3 safeInverse(vec3 v) { return vec3(safeInverse(v.x), safeInverse(v.y), safeInverse(v.z)); }
-
-bool ourHitAABox(vec3 boxCenter, vec3 boxRadius, vec3 rayOrigin, vec3 rayDirection, vec3 invRayDirection) {
-    rayOrigin -= boxCenter;
-    vec3 distanceToPlane = (-boxRadius * sign(rayDirection) - rayOrigin) * invRayDirection;
-
-#   define TEST(U, V,W)\
-         (float(distanceToPlane.U >= 0.0) * \
-          float(abs(rayOrigin.V + rayDirection.V * distanceToPlane.U) < boxRadius.V) *\
-          float(abs(rayOrigin.W + rayDirection.W * distanceToPlane.U) < boxRadius.W))
-
-    // If the ray is in the box or there is a hit along any axis, then there is a hit
-    return bool(float(abs(rayOrigin.x) < boxRadius.x) *
-                float(abs(rayOrigin.y) < boxRadius.y) *
-                float(abs(rayOrigin.z) < boxRadius.z) +
-                TEST(x, y, z) +
-                TEST(y, z, x) +
-                TEST(z, x, y));
-#   undef TEST
-}
-
-bool rayBoxIntersect(Box box, Ray ray, out float dist, out vec3 normal,
-    const bool canStartInBox, const in bool oriented, in vec3 _invRayDir) {
-    ray.origin = (ray.origin - box.center);
-    if (oriented) {
-        ray.origin *= box.rotation;
-        ray.direction *= box.rotation;
-    }
-
-    float winding = canStartInBox && (maxComponent(abs(ray.origin) * box.invRadius)
-        < 1.0) ? -1 : 1;
-
-    vec3 sgn = -sign(ray.direction);
-
-    vec3 d = box.radius * winding * sgn - ray.origin;
-    if (oriented) d /= ray.direction; else d *= _invRayDir;
+#include "raybox.glsl"
 
-    # define TEST(U, VW)\
-        (d.U >= 0.0) && \
-        all(lessThan(abs(ray.origin.VW + ray.direction.VW * d.U), box.radius.VW))
+// Fraction of the sun reaching p, from the cascade covering it with 3x3 PCF
+float sunVisibility(vec3 p, vec3 normal) {
+    int cascades = int(sunDirection.w);
+    vec3 camPos = (invMv * vec4(0, 0, 0, 1)).xyz;
+    vec3 forward = normalize((invMv * vec4(0, 0, -1, 0)).xyz);
+    float depth = dot(p - camPos, forward);
 
-    bvec3 test = bvec3(TEST(x, yz), TEST(y, zx), TEST(z, xy));
-    sgn = test.x ? vec3(sgn.x, 0.0, 0.0) : (test.y ? vec3(0.0, sgn.y , 0.0) :
-        vec3(0.0, 0.0 , test.z ? sgn.z : 0.0));
-    # undef TEST
+    int cascade = 0;
+    while (cascade < cascades && depth > cascadeEnds[cascade]) {
+        cascade++;
+    }
+    if (cascade == cascades) {
+        return 1.0;
+    }
 
-    dist = (sgn.x != 0) ? d.x : ((sgn.y != 0) ? d.y : d.z);
-    normal = oriented ? (box.rotation * sgn) : sgn;
+    // Offsetting along the normal keeps faces from shadowing themselves
+    vec4 lightPos = lightSpace[cascade] * vec4(p + normal * 0.05, 1.0);
+    vec3 coord = (lightPos.xyz / lightPos.w) * 0.5 + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(shadowMap, 0).xy);
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            float closest = texture(shadowMap, vec3(coord.xy + vec2(x, y) * texel, cascade)).r;
+            lit += coord.z - 0.0005 <= closest ? 1.0 : 0.0;
+        }
+    }
+    return lit / 9.0;
+}
 
-    return (sgn.x != 0) || (sgn.y != 0) || (sgn.z != 0);
+float GetLight(vec3 p, vec3 normal) {
+    float dif = max(dot(normal, sunDirection.xyz), 0.0);
+    return dif > 0.0 ? dif * sunVisibility(p, normal) : 0.0;
 }
 
+int faceIndex(vec3 normal) {
+    if (abs(normal.x) > 0.5) {
+        return normal.x > 0.0 ? 0 : 1;
+    }
+    if (abs(normal.y) > 0.5) {
+        return normal.y > 0.0 ? 2 : 3;
+    }
+    return normal.z > 0.0 ? 4 : 5;
+}
 
-float GetLight(vec3 p, vec3 normal) {
-    vec3 lightPos = vec3(8, 16, 8);
-    vec3 l = normalize(lightPos-p);
+// Interpolates the occlusion of the face's corners over the hit point, 0 is fully occluded
+float ambientOcclusion(int face, vec3 pos) {
+    vec3 local = (pos - posBox) / (2.0 * radiusBox) + 0.5;
+    int axis = face / 2;
+    vec2 uv = clamp(vec2(local[(axis + 1) % 3], local[(axis + 2) % 3]), 0.0, 1.0);
 
-    float dif = max((dot(normal, l)), 0.0);
-    return dif;
+    int bits = (face < 3 ? ao.x : ao.y) >> ((face % 3) * 8);
+    vec4 corners = vec4(bits & 3, (bits >> 2) & 3, (bits >> 4) & 3, (bits >> 6) & 3) / 3.0;
+    return mix(mix(corners.x, corners.y, uv.x), mix(corners.z, corners.w, uv.x), uv.y);
 }
 
 float LinearizeDepth(float depth) {
@@ -125,23 +114,31 @@ void main() {
         discard;
     }
 
+    // Faces between translucent voxels of the same type are left out so they read as one volume
+    if (translucentPass && (hiddenFaces & (1 << faceIndex(normal))) != 0) {
+        discard;
+    }
+
     vec4 PClip = mvp * vec4(pos, 1.0);
     float ndc_depth = PClip.z / PClip.w;
 
     gl_FragDepth = (ndc_depth - gl_DepthRange.near) / (gl_DepthRange.far - gl_DepthRange.near);
 
-    vec3 lightColor = vec3(1.0);
-    float ambientStrength = 0.1;
-
     vec2 tileUV = (vec2(dot(normal.zxy, pos),
         dot(normal.yzx, pos)));
 
-    vec4 texture = texture(voxelTexture, tileUV);
-    vec3 ambient = (ambientStrength * lightColor) * texture.xyz;
+    float layer = normal.y > 0.5 ? texLayers.x : (normal.y < -0.5 ? texLayers.z : texLayers.y);
+    vec4 texture = texture(voxelTexture, vec3(tileUV, layer));
+    // Cutout voxels drop the transparent parts of their texture
+    if (!translucentPass && texture.a < 0.5) {
+        discard;
+    }
+    vec3 ambient = ambientColour.rgb * texture.xyz;
 
     float diff = GetLight(pos, normal) ;
-    vec3 diffuse = diff * lightColor * texture.xyz;
-    vec3 col = (ambient + diffuse);
+    vec3 diffuse = diff * sunColour.rgb * texture.xyz;
+    vec3 col = (ambient + diffuse) * mix(0.35, 1.0, ambientOcclusion(faceIndex(normal), pos));
 
-    fragColor = texture * vec4(col, 1);
+    col = applyFog(texture.xyz * col, osCamPos.xyz, pos);
+    fragColor = vec4(col, translucentPass ? texture.a * opacity : 1.0);
 }
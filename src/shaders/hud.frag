@@ -0,0 +1,18 @@
+#version 440
+
+layout(binding = 0) uniform sampler2D font;
+
+in vec2 uv;
+out vec4 fragColor;
+
+const vec4 TEXT = vec4(1.0, 1.0, 1.0, 1.0);
+const vec4 BACKGROUND = vec4(0.0, 0.0, 0.0, 0.55);
+
+void main() {
+    // The background quad has negative atlas coordinates
+    if (uv.x < 0.0) {
+        fragColor = BACKGROUND;
+        return;
+    }
+    fragColor = TEXT * texture(font, uv).r;
+}
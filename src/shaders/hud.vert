@@ -0,0 +1,15 @@
+#version 440
+
+layout (location = 0) in vec2 pos;
+layout (location = 1) in vec2 atlasPos;
+
+uniform vec2 screenSize;
+
+out vec2 uv;
+
+// pos is in pixels from the top left corner of the screen
+void main() {
+    uv = atlasPos;
+    vec2 ndc = pos / screenSize * 2.0 - 1.0;
+    gl_Position = vec4(ndc.x, -ndc.y, 0.0, 1.0);
+}
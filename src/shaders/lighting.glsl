@@ -0,0 +1,17 @@
+// Sun and shadow cascades, shared through one uniform buffer (see render::uniforms)
+
+#define MAX_CASCADES 4
+
+layout (std140) uniform Lighting {
+    mat4 lightSpace[MAX_CASCADES];
+    // Distance from the camera where each cascade ends
+    vec4 cascadeEnds;
+    // xyz towards the sun, w the number of cascades in use
+    vec4 sunDirection;
+    vec4 sunColour;
+    vec4 ambientColour;
+    vec4 skyZenith;
+    vec4 skyHorizon;
+    // x where fog starts, y where it hides everything
+    vec4 fog;
+};
@@ -0,0 +1,24 @@
+#version 440
+
+// Keeps the parts of the scene brighter than threshold, drawn at half size
+
+layout(binding = 0) uniform sampler2D colour;
+
+uniform float threshold;
+
+in vec2 uv;
+out vec4 fragColor;
+
+void main() {
+    // Four bilinear taps average a 4x4 block of the full size image
+    vec2 texel = 1.0 / vec2(textureSize(colour, 0));
+    vec3 c = texture(colour, uv + texel * vec2(-1.0, -1.0)).rgb;
+    c += texture(colour, uv + texel * vec2(1.0, -1.0)).rgb;
+    c += texture(colour, uv + texel * vec2(-1.0, 1.0)).rgb;
+    c += texture(colour, uv + texel * vec2(1.0, 1.0)).rgb;
+    c *= 0.25;
+
+    float brightness = max(c.r, max(c.g, c.b));
+    float contribution = max(brightness - threshold, 0.0) / max(brightness, 0.0001);
+    fragColor = vec4(c * contribution, 1.0);
+}
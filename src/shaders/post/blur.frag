@@ -0,0 +1,23 @@
+#version 440
+
+// Separable 9 tap gaussian blur along direction, using bilinear filtering to halve the taps
+
+layout(binding = 0) uniform sampler2D colour;
+
+uniform vec2 direction;
+
+in vec2 uv;
+out vec4 fragColor;
+
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec2 stride = direction / vec2(textureSize(colour, 0));
+    vec3 c = texture(colour, uv).rgb * WEIGHTS[0];
+    for (int i = 1; i < 3; i++) {
+        c += texture(colour, uv + stride * OFFSETS[i]).rgb * WEIGHTS[i];
+        c += texture(colour, uv - stride * OFFSETS[i]).rgb * WEIGHTS[i];
+    }
+    fragColor = vec4(c, 1.0);
+}
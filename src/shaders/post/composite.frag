@@ -0,0 +1,31 @@
+#version 440
+
+// Darkens the scene by ambient occlusion and adds bloom, disabled passes bind a texture that
+// leaves the scene unchanged
+
+layout(binding = 0) uniform sampler2D colour;
+layout(binding = 2) uniform sampler2D aoMap;
+layout(binding = 3) uniform sampler2D bloomMap;
+
+uniform float aoStrength;
+uniform float bloomIntensity;
+
+in vec2 uv;
+out vec4 fragColor;
+
+void main() {
+    // Average the 4x4 block ssao.frag rotates its samples over
+    ivec2 size = textureSize(aoMap, 0);
+    ivec2 base = ivec2(uv * vec2(size)) - 2;
+    float ao = 0.0;
+    for (int x = 0; x < 4; x++) {
+        for (int y = 0; y < 4; y++) {
+            ao += texelFetch(aoMap, clamp(base + ivec2(x, y), ivec2(0), size - 1), 0).r;
+        }
+    }
+    ao /= 16.0;
+
+    vec3 c = texture(colour, uv).rgb * mix(1.0, ao, aoStrength);
+    c += texture(bloomMap, uv).rgb * bloomIntensity;
+    fragColor = vec4(c, 1.0);
+}
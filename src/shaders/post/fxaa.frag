@@ -0,0 +1,39 @@
+#version 440
+
+// Fast approximate anti-aliasing, blurs along edges found from luma. span limits how far in
+// pixels it reaches and reduce how much flat areas are left alone
+
+layout(binding = 0) uniform sampler2D colour;
+
+uniform float span;
+uniform float reduce;
+
+in vec2 uv;
+out vec4 fragColor;
+
+const vec3 LUMA = vec3(0.299, 0.587, 0.114);
+const float REDUCE_MIN = 1.0 / 128.0;
+
+void main() {
+    vec2 texel = 1.0 / vec2(textureSize(colour, 0));
+    float lumaNW = dot(texture(colour, uv + vec2(-1.0, -1.0) * texel).rgb, LUMA);
+    float lumaNE = dot(texture(colour, uv + vec2(1.0, -1.0) * texel).rgb, LUMA);
+    float lumaSW = dot(texture(colour, uv + vec2(-1.0, 1.0) * texel).rgb, LUMA);
+    float lumaSE = dot(texture(colour, uv + vec2(1.0, 1.0) * texel).rgb, LUMA);
+    vec3 rgbM = texture(colour, uv).rgb;
+    float lumaM = dot(rgbM, LUMA);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * reduce, REDUCE_MIN);
+    float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+    dir = clamp(dir * rcpDirMin, vec2(-span), vec2(span)) * texel;
+
+    vec3 rgbA = 0.5 * (texture(colour, uv + dir * (1.0 / 3.0 - 0.5)).rgb
+        + texture(colour, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgbB = rgbA * 0.5 + 0.25 * (texture(colour, uv - dir * 0.5).rgb
+        + texture(colour, uv + dir * 0.5).rgb);
+    float lumaB = dot(rgbB, LUMA);
+    fragColor = vec4((lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 440
+
+// Converts linear colour for display
+
+layout(binding = 0) uniform sampler2D colour;
+
+uniform float gamma;
+
+in vec2 uv;
+out vec4 fragColor;
+
+void main() {
+    vec3 c = max(texture(colour, uv).rgb, vec3(0.0));
+    fragColor = vec4(pow(c, vec3(1.0 / gamma)), 1.0);
+}
@@ -0,0 +1,69 @@
+#version 440
+
+// Screen space ambient occlusion from the scene depth, the result is noisy in a 4x4 pattern
+// that composite.frag averages away
+
+#include "../camera.glsl"
+
+layout(binding = 1) uniform sampler2D depth;
+
+uniform float radius;
+uniform float bias;
+
+in vec2 uv;
+out float ao;
+
+const int SAMPLES = 16;
+const float GOLDEN_ANGLE = 2.3999632;
+
+vec3 viewPos(vec2 coord) {
+    float d = texture(depth, coord).r;
+    vec4 pos = invP * vec4(vec3(coord, d) * 2.0 - 1.0, 1.0);
+    return pos.xyz / pos.w;
+}
+
+// Uses the neighbours with the smaller depth difference so edges don't bleed
+vec3 viewNormal(vec3 pos) {
+    vec2 texel = 1.0 / vec2(textureSize(depth, 0));
+    vec3 right = viewPos(uv + vec2(texel.x, 0.0)) - pos;
+    vec3 left = pos - viewPos(uv - vec2(texel.x, 0.0));
+    vec3 up = viewPos(uv + vec2(0.0, texel.y)) - pos;
+    vec3 down = pos - viewPos(uv - vec2(0.0, texel.y));
+    vec3 dx = abs(right.z) < abs(left.z) ? right : left;
+    vec3 dy = abs(up.z) < abs(down.z) ? up : down;
+    return normalize(cross(dx, dy));
+}
+
+void main() {
+    if (texture(depth, uv).r >= 1.0) {
+        ao = 1.0;
+        return;
+    }
+    vec3 pos = viewPos(uv);
+    vec3 normal = viewNormal(pos);
+
+    // Rotate the sample kernel per pixel in a 4x4 pattern
+    ivec2 cell = ivec2(gl_FragCoord.xy) & 3;
+    float angle = float(cell.x * 4 + cell.y) * (6.2831853 / 16.0);
+    vec3 random = vec3(cos(angle), sin(angle), 0.0);
+    vec3 tangent = normalize(random - normal * dot(random, normal));
+    mat3 tbn = mat3(tangent, cross(normal, tangent), normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < SAMPLES; i++) {
+        // Points spiral over the hemisphere, packed closer to the centre
+        float t = (float(i) + 0.5) / float(SAMPLES);
+        float z = 1.0 - t;
+        float r = sqrt(1.0 - z * z);
+        float phi = float(i) * GOLDEN_ANGLE;
+        vec3 offset = vec3(r * cos(phi), r * sin(phi), z) * mix(0.1, 1.0, t * t);
+        vec3 samplePos = pos + tbn * offset * radius;
+
+        vec4 clip = p * vec4(samplePos, 1.0);
+        float sceneZ = viewPos(clip.xy / clip.w * 0.5 + 0.5).z;
+        // Geometry far in front of the sample doesn't occlude it
+        float range = smoothstep(0.0, 1.0, radius / abs(pos.z - sceneZ));
+        occlusion += (sceneZ >= samplePos.z + bias ? 1.0 : 0.0) * range;
+    }
+    ao = 1.0 - occlusion / float(SAMPLES);
+}
@@ -0,0 +1,18 @@
+#version 440
+
+// Maps HDR colour into [0, 1] with a fitted ACES curve
+
+layout(binding = 0) uniform sampler2D colour;
+
+uniform float exposure;
+
+in vec2 uv;
+out vec4 fragColor;
+
+vec3 aces(vec3 x) {
+    return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+}
+
+void main() {
+    fragColor = vec4(aces(texture(colour, uv).rgb * exposure), 1.0);
+}
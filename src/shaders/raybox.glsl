@@ -0,0 +1,68 @@
+// Ray and axis aligned box intersection shared by the voxel shaders
+
+struct Ray {
+    vec3 direction;
+    vec3 origin;
+};
+
+struct Box {
+    vec3     center;
+    vec3     radius;
+    vec3     invRadius;
+    mat3     rotation;
+};
+
+float maxComponent(vec3 v) { return max (max(v.x, v.y), v.z); }
+
+float safeInverse(float x) { return (x == 0.0) ? 1e12 : (1.0 / x); }
+vec3 safeInverse(vec3 v) { return vec3(safeInverse(v.x), safeInverse(v.y), safeInverse(v.z)); }
+
+bool ourHitAABox(vec3 boxCenter, vec3 boxRadius, vec3 rayOrigin, vec3 rayDirection, vec3 invRayDirection) {
+    rayOrigin -= boxCenter;
+    vec3 distanceToPlane = (-boxRadius * sign(rayDirection) - rayOrigin) * invRayDirection;
+
+#   define TEST(U, V,W)\
+         (float(distanceToPlane.U >= 0.0) * \
+          float(abs(rayOrigin.V + rayDirection.V * distanceToPlane.U) < boxRadius.V) *\
+          float(abs(rayOrigin.W + rayDirection.W * distanceToPlane.U) < boxRadius.W))
+
+    // If the ray is in the box or there is a hit along any axis, then there is a hit
+    return bool(float(abs(rayOrigin.x) < boxRadius.x) *
+                float(abs(rayOrigin.y) < boxRadius.y) *
+                float(abs(rayOrigin.z) < boxRadius.z) +
+                TEST(x, y, z) +
+                TEST(y, z, x) +
+                TEST(z, x, y));
+#   undef TEST
+}
+
+bool rayBoxIntersect(Box box, Ray ray, out float dist, out vec3 normal,
+    const bool canStartInBox, const in bool oriented, in vec3 _invRayDir) {
+    ray.origin = (ray.origin - box.center);
+    if (oriented) {
+        ray.origin *= box.rotation;
+        ray.direction *= box.rotation;
+    }
+
+    float winding = canStartInBox && (maxComponent(abs(ray.origin) * box.invRadius)
+        < 1.0) ? -1 : 1;
+
+    vec3 sgn = -sign(ray.direction);
+
+    vec3 d = box.radius * winding * sgn - ray.origin;
+    if (oriented) d /= ray.direction; else d *= _invRayDir;
+
+    # define TEST(U, VW)\
+        (d.U >= 0.0) && \
+        all(lessThan(abs(ray.origin.VW + ray.direction.VW * d.U), box.radius.VW))
+
+    bvec3 test = bvec3(TEST(x, yz), TEST(y, zx), TEST(z, xy));
+    sgn = test.x ? vec3(sgn.x, 0.0, 0.0) : (test.y ? vec3(0.0, sgn.y , 0.0) :
+        vec3(0.0, 0.0 , test.z ? sgn.z : 0.0));
+    # undef TEST
+
+    dist = (sgn.x != 0) ? d.x : ((sgn.y != 0) ? d.y : d.z);
+    normal = oriented ? (box.rotation * sgn) : sgn;
+
+    return (sgn.x != 0) || (sgn.y != 0) || (sgn.z != 0);
+}
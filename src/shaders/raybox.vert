@@ -1,14 +1,20 @@
 #version 440
 
 layout (location = 0) in vec3 vPos;
+layout (location = 1) in vec3 vTexLayers;
+layout (location = 2) in float vOpacity;
+layout (location = 3) in float vHiddenFaces;
+layout (location = 4) in vec2 vAo;
 
 
-uniform mat4 mvp;
-uniform vec2 screenSize;
-uniform float voxelSize;
+#include "camera.glsl"
 
 out vec3 posBox;
 out vec3 radiusBox;
+flat out vec3 texLayers;
+flat out float opacity;
+flat out int hiddenFaces;
+flat out ivec2 ao;
 
 
 void quadricProj(in vec3 osPosition, in float voxelSize,
@@ -61,4 +67,8 @@ void main() {
 
     posBox = vertex;
     radiusBox = vec3(voxelSize/2);
+    texLayers = vTexLayers;
+    opacity = vOpacity;
+    hiddenFaces = int(vHiddenFaces);
+    ao = ivec2(vAo);
 }
@@ -0,0 +1,43 @@
+#version 440
+
+in vec3 posBox;
+in vec3 radiusBox;
+flat in vec3 texLayers;
+
+#include "camera.glsl"
+
+uniform sampler2DArray voxelTexture;
+
+#include "raybox.glsl"
+
+// Depth of the voxels seen from the sun, the camera block holds the cascade's light matrices.
+// Rays are built from both clip planes since the projection is orthographic
+void main() {
+    vec2 uv = 2.0*((gl_FragCoord.xy + 0.5) / screenSize.xy)-1.0;
+    mat4 invMvp = invMv * invP;
+    vec4 nearPoint = invMvp * vec4(uv, -1.0, 1.0);
+    vec4 farPoint = invMvp * vec4(uv, 1.0, 1.0);
+    vec3 ro = nearPoint.xyz / nearPoint.w;
+    vec3 rd = normalize(farPoint.xyz / farPoint.w - ro);
+
+    Ray r = Ray(rd, ro);
+    Box b = Box(posBox, radiusBox, safeInverse(radiusBox), mat3(1.0f));
+
+    float dist;
+    vec3 normal;
+    if (!rayBoxIntersect(b, r, dist, normal, true, false, safeInverse(r.direction))) {
+        discard;
+    }
+
+    vec3 pos = r.origin + (dist * r.direction);
+
+    // Cutout voxels only cast shadows where their texture is solid
+    vec2 tileUV = vec2(dot(normal.zxy, pos), dot(normal.yzx, pos));
+    float layer = normal.y > 0.5 ? texLayers.x : (normal.y < -0.5 ? texLayers.z : texLayers.y);
+    if (texture(voxelTexture, vec3(tileUV, layer)).a < 0.5) {
+        discard;
+    }
+
+    vec4 clip = mvp * vec4(pos, 1.0);
+    gl_FragDepth = (clip.z / clip.w) * 0.5 + 0.5;
+}
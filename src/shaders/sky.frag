@@ -0,0 +1,16 @@
+#version 440
+
+#include "camera.glsl"
+#include "lighting.glsl"
+#include "sky.glsl"
+
+out vec4 fragColor;
+
+void main() {
+    vec2 uv = 2.0*((gl_FragCoord.xy + 0.5) / screenSize.xy)-1.0;
+    vec3 ro = (invMv * vec4(0, 0, 0, 1)).xyz;
+    vec4 rdh = (invMv * invP) * vec4(uv, -1.0, 1.0);
+    vec3 rd = normalize((rdh.xyz / rdh.w) - ro);
+
+    fragColor = vec4(skyColour(rd), 1.0);
+}
@@ -0,0 +1,17 @@
+// Sky gradient and distance fog, needs lighting.glsl
+
+vec3 skyColour(vec3 dir) {
+    float height = dir.y;
+    vec3 sky = mix(skyHorizon.rgb, skyZenith.rgb, smoothstep(0.0, 0.5, height));
+    // Below the horizon the sky darkens slightly towards the ground
+    sky = mix(sky, skyHorizon.rgb * 0.6, smoothstep(0.0, -0.4, height));
+    float sun = max(dot(dir, sunDirection.xyz), 0.0);
+    return sky + sunColour.rgb * (pow(sun, 2000.0) * 4.0 + pow(sun, 16.0) * 0.15);
+}
+
+// Fades colour into the sky behind it with distance, the loaded world ends at fog.y
+vec3 applyFog(vec3 colour, vec3 camPos, vec3 pos) {
+    vec3 offset = pos - camPos;
+    float amount = smoothstep(fog.x, fog.y, length(offset));
+    return mix(colour, skyColour(normalize(offset)), amount);
+}
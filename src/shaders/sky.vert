@@ -0,0 +1,7 @@
+#version 440
+
+// One triangle covering the screen, drawn without vertex buffers
+void main() {
+    vec2 pos = vec2((gl_VertexID << 1) & 2, gl_VertexID & 2) * 2.0 - 1.0;
+    gl_Position = vec4(pos, 1.0, 1.0);
+}
@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use super::config::StructureConfig;
+use super::consts::{OPAQUE_VOXEL, TRANSPARENT_VOXEL};
+use super::geom::{calc_idx, ChunkKey};
+use super::region::{Region, RegionError};
+use super::voxel_registry::VoxelReg;
+use super::world::{World, WorldType};
+
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(String, io::Error),
+    Region(RegionError),
+    Vox(String),
+    UnknownVoxel(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            TemplateError::Region(err) => write!(f, "{}", err),
+            TemplateError::Vox(msg) => write!(f, "invalid .vox file: {}", msg),
+            TemplateError::UnknownVoxel(id) => write!(f, "unknown voxel type {}", id),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+impl From<io::Error> for TemplateError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => TemplateError::Vox("file is truncated".to_string()),
+            _ => TemplateError::Io("template".to_string(), err),
+        }
+    }
+}
+
+//A box of voxels stamped into worlds with the middle of its bottom layer at the placement
+//position. Empty cells leave the world's voxels as they are
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub size: [u32; 3],
+    //x changing fastest, then y, then z
+    voxels: Vec<Option<u64>>,
+}
+
+impl Template {
+    //Transparent voxels and ones the registry doesn't know are left empty
+    pub fn from_region(region: &Region, reg: &VoxelReg) -> Template {
+        let transparent = reg.key_from_string_id(TRANSPARENT_VOXEL);
+        let palette: Vec<Option<u64>> = region
+            .palette()
+            .iter()
+            .map(|entry| entry.to_key(reg).filter(|key| Some(*key) != transparent))
+            .collect();
+        let [sx, sy, sz] = region.size;
+        let mut voxels = Vec::with_capacity((sx * sy * sz) as usize);
+        for z in 0..sz {
+            for y in 0..sy {
+                for x in 0..sx {
+                    voxels.push(palette[region.palette_index([x, y, z])]);
+                }
+            }
+        }
+        Template {
+            size: region.size,
+            voxels,
+        }
+    }
+
+    //A MagicaVoxel model, the first one in the file. Colour indices map to voxel keys through
+    //colours, ones missing from it are fallback. MagicaVoxel's z axis is up, it becomes y
+    pub fn read_vox<R: Read>(
+        input: &mut R,
+        colours: &HashMap<u8, u64>,
+        fallback: u64,
+    ) -> Result<Template, TemplateError> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != b"VOX " {
+            return Err(TemplateError::Vox("not a .vox file".to_string()));
+        }
+        read_i32(input)?;
+
+        let mut size = None;
+        let mut voxels = None;
+        let mut id = [0u8; 4];
+        loop {
+            match input.read_exact(&mut id) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e.into()),
+            }
+            let content = read_i32(input)?;
+            read_i32(input)?;
+            if content < 0 {
+                return Err(TemplateError::Vox("negative chunk size".to_string()));
+            }
+            match &id {
+                b"SIZE" if size.is_none() => {
+                    let [x, y, z] = [read_i32(input)?, read_i32(input)?, read_i32(input)?];
+                    if x <= 0 || y <= 0 || z <= 0 || x > 256 || y > 256 || z > 256 {
+                        return Err(TemplateError::Vox(format!("model size {}x{}x{}", x, y, z)));
+                    }
+                    size = Some([x as u32, z as u32, y as u32]);
+                }
+                b"XYZI" if voxels.is_none() => {
+                    //The count is checked against the chunk's size before anything is allocated
+                    let count = read_i32(input)? as i64;
+                    if count < 0 || 4 + count * 4 > content as i64 {
+                        return Err(TemplateError::Vox(format!("{} voxels don't fit", count)));
+                    }
+                    let mut xyzi = vec![0u8; count as usize * 4];
+                    input.read_exact(&mut xyzi)?;
+                    let rest = content as u64 - 4 - count as u64 * 4;
+                    io::copy(&mut input.take(rest), &mut io::sink())?;
+                    voxels = Some(xyzi);
+                }
+                //Child chunks follow MAIN directly, so its content is all there is to skip
+                _ => {
+                    io::copy(&mut input.take(content as u64), &mut io::sink())?;
+                }
+            }
+        }
+
+        let (size, xyzi) = match (size, voxels) {
+            (Some(size), Some(xyzi)) => (size, xyzi),
+            _ => return Err(TemplateError::Vox("no model".to_string())),
+        };
+        let [sx, sy, sz] = size.map(|s| s as usize);
+        let mut template = Template {
+            size,
+            voxels: vec![None; sx * sy * sz],
+        };
+        for v in xyzi.chunks(4) {
+            let (x, y, z) = (v[0] as usize, v[2] as usize, v[1] as usize);
+            if x >= sx || y >= sy || z >= sz {
+                return Err(TemplateError::Vox("voxel outside the model".to_string()));
+            }
+            let voxel = colours.get(&v[3]).copied().unwrap_or(fallback);
+            template.voxels[x + sx * (y + sy * z)] = Some(voxel);
+        }
+        Ok(template)
+    }
+
+    //.vox files are read as MagicaVoxel models, anything else as a region file
+    pub fn load(config: &StructureConfig, reg: &VoxelReg) -> Result<Template, TemplateError> {
+        let key = |id: &str| {
+            reg.key_from_string_id(id)
+                .ok_or_else(|| TemplateError::UnknownVoxel(id.to_string()))
+        };
+        let path = config.template.as_str();
+        if Path::new(path).extension().is_some_and(|e| e == "vox") {
+            let mut colours = HashMap::new();
+            for (index, id) in config.colours.iter() {
+                let index = index
+                    .parse()
+                    .map_err(|_| TemplateError::Vox(format!("{} isn't a colour index", index)))?;
+                colours.insert(index, key(id)?);
+            }
+            let fallback = key(config.voxel.as_deref().unwrap_or(OPAQUE_VOXEL))?;
+            let file = File::open(path).map_err(|e| TemplateError::Io(path.to_string(), e))?;
+            Template::read_vox(&mut BufReader::new(file), &colours, fallback).map_err(|e| match e {
+                TemplateError::Io(_, err) => TemplateError::Io(path.to_string(), err),
+                e => e,
+            })
+        } else {
+            let region = Region::load(path).map_err(TemplateError::Region)?;
+            Ok(Template::from_region(&region, reg))
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, pos: [u32; 3]) -> Option<u64> {
+        let [x, y, z] = pos.map(|p| p as usize);
+        let [sx, sy, _] = self.size.map(|s| s as usize);
+        self.voxels[x + sx * (y + sy * z)]
+    }
+
+    //Places the template with the middle of its bottom layer at pos
+    pub fn place(&self, world: &World, pos: [i32; 3], priority: u64, reg: &VoxelReg) {
+        let [sx, sy, sz] = self.size.map(|s| s as i32);
+        let origin = [pos[0] - sx / 2, pos[1], pos[2] - sz / 2];
+        let mut voxels = self.voxels.iter();
+        for z in 0..sz {
+            for y in 0..sy {
+                for x in 0..sx {
+                    if let Some(Some(voxel)) = voxels.next() {
+                        let at = [origin[0] + x, origin[1] + y, origin[2] + z];
+                        //Only fails once the world is removed, nothing more can be placed then
+                        if world
+                            .place_structure_voxel(at, *voxel, priority, reg)
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn read_i32<R: Read>(input: &mut R) -> io::Result<i32> {
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+//SplitMix64, placements only need to be cheap and the same for the same seed
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+//Columns in a chunk to try placing a structure in and the priority of each placement, from the
+//world seed, the chunk and which structure it is. per_chunk is the expected count, its fraction
+//is the chance of one more
+pub fn columns(
+    seed: u64,
+    key: &ChunkKey,
+    index: usize,
+    per_chunk: f64,
+    size: usize,
+) -> Vec<([usize; 2], u64)> {
+    let mut state = [key.x as u64, key.y as u64, key.z as u64, index as u64]
+        .iter()
+        .fold(mix(seed), |h, v| mix(h ^ v));
+    let mut next = || {
+        state = mix(state);
+        state
+    };
+    let fraction = (next() >> 11) as f64 / (1u64 << 53) as f64;
+    let count = per_chunk.floor() as usize + (fraction < per_chunk.fract()) as usize;
+    (0..count)
+        .map(|_| {
+            let v = next();
+            let column = [
+                (v % size as u64) as usize,
+                ((v >> 32) % size as u64) as usize,
+            ];
+            (column, next())
+        })
+        .collect()
+}
+
+pub struct Placement {
+    pub template: Arc<Template>,
+    pub per_chunk: f64,
+    //The voxel type the structure stands on, None for any that isn't transparent
+    pub on: Option<u64>,
+}
+
+//The structures a world places while decorating its chunks
+pub struct Structures {
+    pub seed: u64,
+    pub placements: Vec<Placement>,
+}
+
+impl Structures {
+    //Places structures on surfaces in the chunk, parts of them reach into the chunks around it.
+    //Surfaces are found in the terrain world_type generates rather than the world, which may
+    //hold structures of chunks decorated before this one
+    pub fn decorate(
+        &self,
+        world: &World,
+        key: &ChunkKey,
+        world_type: &dyn WorldType,
+        reg: &VoxelReg,
+    ) {
+        let size = world.chunk_size();
+        let base = [key.x, key.y, key.z].map(|k| k * size as i32);
+        let mut terrain = None;
+        for (index, placement) in self.placements.iter().enumerate() {
+            for ([x, z], priority) in columns(self.seed, key, index, placement.per_chunk, size) {
+                let (chunk, above) = terrain.get_or_insert_with(|| {
+                    let above = ChunkKey {
+                        y: key.y + 1,
+                        ..*key
+                    };
+                    (
+                        world_type.gen_chunk(key, reg),
+                        world_type.gen_chunk(&above, reg),
+                    )
+                });
+                let surface = (0..size).rev().find(|y| {
+                    let ground = chunk[calc_idx(x, *y, z, size)];
+                    let above = if y + 1 < size {
+                        chunk[calc_idx(x, y + 1, z, size)]
+                    } else {
+                        above[calc_idx(x, 0, z, size)]
+                    };
+                    let stands = match placement.on {
+                        Some(on) => ground == on,
+                        None => !reg.is_transparent(&ground),
+                    };
+                    stands && reg.is_transparent(&above)
+                });
+                if let Some(y) = surface {
+                    let at = [
+                        base[0] + x as i32,
+                        base[1] + y as i32 + 1,
+                        base[2] + z as i32,
+                    ];
+                    placement.template.place(world, at, priority, reg);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend_from_slice(&(content.len() as i32).to_le_bytes());
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(content);
+        bytes
+    }
+
+    #[test]
+    fn test_read_vox() {
+        let size: Vec<u8> = [2i32, 3, 4].iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut xyzi = 2i32.to_le_bytes().to_vec();
+        xyzi.extend_from_slice(&[1, 2, 3, 7, 0, 0, 0, 9]);
+        let mut children = chunk(b"SIZE", &size);
+        children.extend(chunk(b"XYZI", &xyzi));
+        children.extend(chunk(b"RGBA", &[0; 1024]));
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&150i32.to_le_bytes());
+        bytes.extend_from_slice(b"MAIN");
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(children.len() as i32).to_le_bytes());
+        bytes.extend(children);
+
+        let colours: HashMap<u8, u64> = [(7, 70)].iter().copied().collect();
+        let template = Template::read_vox(&mut bytes.as_slice(), &colours, 5).unwrap();
+        assert_eq!(template.size, [2, 4, 3]);
+        assert_eq!(template.get([1, 3, 2]), Some(70));
+        assert_eq!(template.get([0, 0, 0]), Some(5));
+        assert_eq!(template.get([1, 0, 0]), None);
+
+        assert!(Template::read_vox(&mut &bytes[..bytes.len() - 1030], &colours, 5).is_err());
+        assert!(Template::read_vox(&mut &b"VOX \x96\0\0\0"[..], &colours, 5).is_err());
+
+        //A count bigger than its chunk
+        let mut huge = b"VOX ".to_vec();
+        huge.extend_from_slice(&150i32.to_le_bytes());
+        huge.extend(chunk(b"XYZI", &i32::MAX.to_le_bytes()));
+        assert!(matches!(
+            Template::read_vox(&mut huge.as_slice(), &colours, 5),
+            Err(TemplateError::Vox(_))
+        ));
+    }
+
+    #[test]
+    fn test_columns() {
+        let key = ChunkKey { x: 3, y: 0, z: -2 };
+        let a = columns(42, &key, 0, 2.5, 16);
+        assert_eq!(a, columns(42, &key, 0, 2.5, 16));
+        assert!(a.len() == 2 || a.len() == 3);
+        assert!(a.iter().all(|([x, z], _)| *x < 16 && *z < 16));
+        assert!(columns(42, &key, 0, 0.0, 16).is_empty());
+
+        //About a quarter of chunks get a structure at 0.25
+        let placed: usize = (0..1000)
+            .map(|x| columns(1, &ChunkKey { x, y: 0, z: 0 }, 1, 0.25, 16).len())
+            .sum();
+        assert!(placed > 150 && placed < 350, "{}", placed);
+    }
+}
@@ -1,41 +1,133 @@
-use image::GenericImageView;
+use image::imageops::FilterType;
+use image::{ImageError, RgbaImage};
 
+use std::fmt;
 use std::os::raw::c_void;
 
-use flamer::flame;
+const MISSING_TEXTURE_SIZE: u32 = 64;
 
-#[flame]
-pub fn read_texture(path: String) -> (Vec<u8>, u32, u32) {
-    let img = image::open(path).unwrap();
-    let (width, height) = img.dimensions();
-    (img.to_bytes(), width, height)
+#[derive(Debug)]
+pub struct TextureError {
+    pub path: String,
+    pub err: ImageError,
 }
 
-#[flame]
-pub fn generate_texture(path: String) -> u32 {
-    let (img, width, height) = read_texture(path);
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to load texture {}: {}", self.path, self.err)
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+pub fn read_texture(path: &str) -> Result<RgbaImage, TextureError> {
+    let img = image::open(path).map_err(|err| TextureError {
+        path: path.to_string(),
+        err,
+    })?;
+    Ok(img.to_rgba8())
+}
+
+//Magenta and black checkerboard standing in for textures that failed to load
+pub fn missing_texture(width: u32, height: u32) -> RgbaImage {
+    RgbaImage::from_fn(width, height, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 {
+            image::Rgba([255, 0, 255, 255])
+        } else {
+            image::Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+//Loads one layer per path, all scaled to the size of the first texture that loads. Failed
+//textures are replaced by missing_texture and their errors returned alongside the layers.
+pub fn load_layers(paths: &[String]) -> (Vec<RgbaImage>, Vec<TextureError>) {
+    let mut errors = Vec::new();
+    let loaded: Vec<Option<RgbaImage>> = paths
+        .iter()
+        .map(|path| match read_texture(path) {
+            Ok(img) => Some(img),
+            Err(err) => {
+                errors.push(err);
+                None
+            }
+        })
+        .collect();
+
+    let (width, height) = loaded
+        .iter()
+        .flatten()
+        .next()
+        .map(|img| img.dimensions())
+        .unwrap_or((MISSING_TEXTURE_SIZE, MISSING_TEXTURE_SIZE));
+
+    let mut layers: Vec<RgbaImage> = loaded
+        .into_iter()
+        .map(|img| match img {
+            Some(img) if img.dimensions() == (width, height) => img,
+            Some(img) => image::imageops::resize(&img, width, height, FilterType::Triangle),
+            None => missing_texture(width, height),
+        })
+        .collect();
+    if layers.is_empty() {
+        layers.push(missing_texture(width, height));
+    }
+    (layers, errors)
+}
+
+//Uploads equally sized layers into a GL_TEXTURE_2D_ARRAY, each layer repeats on its own so
+//neighbouring textures can't bleed into each other like they would in an atlas
+pub fn generate_texture_array(layers: &[RgbaImage]) -> u32 {
+    let (width, height) = layers[0].dimensions();
     let mut texture: u32 = 0;
     unsafe {
         gl::GenTextures(1, &mut texture);
-        gl::BindTexture(gl::TEXTURE_2D, texture);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, texture);
 
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+        gl::TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_MIN_FILTER,
+            gl::LINEAR_MIPMAP_LINEAR as i32,
+        );
+        gl::TexParameteri(
+            gl::TEXTURE_2D_ARRAY,
+            gl::TEXTURE_MAG_FILTER,
+            gl::LINEAR as i32,
+        );
 
-        gl::TexImage2D(
-            gl::TEXTURE_2D,
+        //Images are stored in sRGB, sampling converts them to linear colour for lighting and
+        //the gamma post pass converts back
+        gl::TexImage3D(
+            gl::TEXTURE_2D_ARRAY,
             0,
-            gl::RGB as i32,
+            gl::SRGB8_ALPHA8 as i32,
             width as i32,
             height as i32,
+            layers.len() as i32,
             0,
-            gl::RGB,
+            gl::RGBA,
             gl::UNSIGNED_BYTE,
-            &img[0] as *const u8 as *const c_void,
+            std::ptr::null(),
         );
-        gl::GenerateMipmap(gl::TEXTURE_2D);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        for (i, layer) in layers.iter().enumerate() {
+            gl::TexSubImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                0,
+                0,
+                i as i32,
+                width as i32,
+                height as i32,
+                1,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                layer.as_ptr() as *const c_void,
+            );
+        }
+        gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
     }
 
     texture
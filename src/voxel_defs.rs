@@ -0,0 +1,409 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+
+use glm::Vec3;
+use serde::Deserialize;
+
+use super::consts::MAX_VOXEL_STATES;
+use super::voxel_registry::{
+    self, FaceTextures, Material, PropertyKind, PropertyValue, StateProperty, VoxelAttributes,
+    VoxelKind, VoxelReg,
+};
+
+#[derive(Debug)]
+pub enum DefinitionError {
+    Io(String, io::Error),
+    Parse(String, toml::de::Error),
+    Invalid {
+        index: usize,
+        id: String,
+        msg: String,
+    },
+}
+
+impl fmt::Display for DefinitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DefinitionError::Io(path, err) => write!(f, "failed to read {}: {}", path, err),
+            DefinitionError::Parse(path, err) => write!(f, "invalid voxel file {}: {}", path, err),
+            DefinitionError::Invalid { index, id, msg } => {
+                write!(f, "voxel #{} \"{}\": {}", index, id, msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DefinitionError {}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionFile {
+    #[serde(default)]
+    voxel: Vec<VoxelDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VoxelDef {
+    id: String,
+    #[serde(default)]
+    kind: VoxelKind,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default = "default_solid")]
+    solid: bool,
+    #[serde(default)]
+    material: MaterialDef,
+    textures: Option<TexturesDef>,
+    #[serde(default)]
+    emission: [f32; 3],
+    #[serde(default)]
+    properties: HashMap<String, toml::Value>,
+    #[serde(default)]
+    states: BTreeMap<String, StateDef>,
+}
+
+//Defaults are false, min and the first value when not given
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum StateDef {
+    Bool {
+        #[serde(default)]
+        default: bool,
+    },
+    Int {
+        min: i64,
+        max: i64,
+        default: Option<i64>,
+    },
+    Enum {
+        values: Vec<String>,
+        default: Option<String>,
+    },
+}
+
+impl StateDef {
+    fn property(&self, name: &str) -> StateProperty {
+        let (kind, default) = match self {
+            StateDef::Bool { default } => (PropertyKind::Bool, PropertyValue::Bool(*default)),
+            StateDef::Int { min, max, default } => (
+                PropertyKind::Int {
+                    min: *min,
+                    max: *max,
+                },
+                PropertyValue::Int(default.unwrap_or(*min)),
+            ),
+            StateDef::Enum { values, default } => (
+                PropertyKind::Enum(values.clone()),
+                PropertyValue::Enum(
+                    default
+                        .clone()
+                        .or_else(|| values.first().cloned())
+                        .unwrap_or_default(),
+                ),
+            ),
+        };
+        StateProperty {
+            name: name.to_string(),
+            kind,
+            default,
+        }
+    }
+}
+
+fn default_solid() -> bool {
+    true
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct MaterialDef {
+    #[serde(default)]
+    ambient: [f32; 3],
+    #[serde(default)]
+    diffuse: [f32; 3],
+    #[serde(default)]
+    specular: [f32; 3],
+    #[serde(default)]
+    shininess: f32,
+}
+
+//Either all for every face, or any of top, side and bottom with all as the fallback
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TexturesDef {
+    all: Option<String>,
+    top: Option<String>,
+    side: Option<String>,
+    bottom: Option<String>,
+}
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3::new(v[0], v[1], v[2])
+}
+
+fn check_colour(name: &str, c: [f32; 3]) -> Result<(), String> {
+    if c.iter().all(|v| *v >= 0.0 && *v <= 1.0) {
+        Ok(())
+    } else {
+        Err(format!("{} components must be between 0 and 1", name))
+    }
+}
+
+impl VoxelDef {
+    fn validate(&self, reg: &VoxelReg) -> Result<(), String> {
+        if self.id.is_empty() {
+            return Err("id can't be empty".to_string());
+        }
+        if reg.key_from_string_id(&self.id).is_some() {
+            return Err("id is already registered".to_string());
+        }
+        check_colour("material.ambient", self.material.ambient)?;
+        check_colour("material.diffuse", self.material.diffuse)?;
+        check_colour("material.specular", self.material.specular)?;
+        if self.material.shininess < 0.0 {
+            return Err("material.shininess can't be negative".to_string());
+        }
+        if self.opacity < 0.0 || self.opacity > 1.0 {
+            return Err("opacity must be between 0 and 1".to_string());
+        }
+        if self.emission.iter().any(|v| *v < 0.0) {
+            return Err("emission can't be negative".to_string());
+        }
+        for (name, state) in self.states.iter() {
+            if let StateDef::Enum { values, .. } = state {
+                if (1..values.len()).any(|i| values[..i].contains(&values[i])) {
+                    return Err(format!("state {} has duplicate values", name));
+                }
+            }
+            if !state.property(name).is_valid() {
+                return Err(format!(
+                    "state {} has no values or an invalid default",
+                    name
+                ));
+            }
+        }
+        if voxel_registry::state_count(&self.state_properties()).is_none() {
+            return Err(format!(
+                "states have more than {} combinations",
+                MAX_VOXEL_STATES
+            ));
+        }
+        match &self.textures {
+            None if self.kind != VoxelKind::Empty => {
+                Err("only empty voxels can leave out textures".to_string())
+            }
+            Some(t)
+                if t.all.is_none()
+                    && (t.top.is_none() || t.side.is_none() || t.bottom.is_none()) =>
+            {
+                Err("textures needs all, or each of top, side and bottom".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn state_properties(&self) -> Vec<StateProperty> {
+        self.states
+            .iter()
+            .map(|(name, state)| state.property(name))
+            .collect()
+    }
+
+    fn face_textures(&self, reg: &mut VoxelReg) -> FaceTextures {
+        match &self.textures {
+            None => FaceTextures::default(),
+            Some(t) => {
+                let mut layer = |face: &Option<String>| {
+                    let path = face.as_ref().or_else(|| t.all.as_ref()).unwrap();
+                    reg.register_texture(path)
+                };
+                FaceTextures {
+                    top: layer(&t.top),
+                    side: layer(&t.side),
+                    bottom: layer(&t.bottom),
+                }
+            }
+        }
+    }
+}
+
+//Registers every voxel in src, nothing is registered if any entry is invalid
+pub fn parse(src: &str, name: &str, reg: &mut VoxelReg) -> Result<Vec<u64>, DefinitionError> {
+    let file: DefinitionFile =
+        toml::from_str(src).map_err(|e| DefinitionError::Parse(name.to_string(), e))?;
+
+    for (index, def) in file.voxel.iter().enumerate() {
+        let duplicate = file.voxel[..index].iter().any(|d| d.id == def.id);
+        let result = if duplicate {
+            Err("id is defined more than once".to_string())
+        } else {
+            def.validate(reg)
+        };
+        result.map_err(|msg| DefinitionError::Invalid {
+            index,
+            id: def.id.clone(),
+            msg,
+        })?;
+    }
+
+    let mut keys = Vec::new();
+    for def in file.voxel {
+        let textures = def.face_textures(reg);
+        let states = def.state_properties();
+        let attributes = VoxelAttributes {
+            kind: def.kind,
+            opacity: def.opacity,
+            solid: def.solid,
+            mat: Material {
+                ambient: vec3(def.material.ambient),
+                diffuse: vec3(def.material.diffuse),
+                specular: vec3(def.material.specular),
+                shininess: def.material.shininess,
+            },
+            textures,
+            emission: vec3(def.emission),
+        };
+        keys.push(reg.register_voxel_type(&def.id, attributes, def.properties, states));
+    }
+    Ok(keys)
+}
+
+pub fn load(path: &str, reg: &mut VoxelReg) -> Result<Vec<u64>, DefinitionError> {
+    let src = fs::read_to_string(path).map_err(|e| DefinitionError::Io(path.to_string(), e))?;
+    parse(&src, path, reg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEFS: &str = r#"
+        [[voxel]]
+        id = "air"
+        kind = "empty"
+        solid = false
+
+        [[voxel]]
+        id = "grass"
+        textures = { top = "grass_top.png", side = "grass_side.png", bottom = "dirt.png" }
+        material = { diffuse = [0.8, 0.8, 0.8] }
+        properties = { hardness = 2 }
+
+        [[voxel]]
+        id = "dirt"
+        textures = { all = "dirt.png" }
+    "#;
+
+    const WATER: &str = r#"
+        [[voxel]]
+        id = "water"
+        kind = "translucent"
+        opacity = 0.6
+        solid = false
+        textures = { all = "water.png" }
+    "#;
+
+    #[test]
+    fn test_parse_definitions() {
+        let mut reg = VoxelReg::new();
+        let keys = parse(DEFS, "test", &mut reg).unwrap();
+        assert_eq!(keys.len(), 3);
+        assert!(reg.is_transparent(&keys[0]));
+
+        let grass = reg.voxel_attributes(&keys[1]);
+        assert!(grass.solid);
+        assert_eq!(
+            grass.textures.bottom,
+            reg.voxel_attributes(&keys[2]).textures.top
+        );
+        assert_eq!(reg.texture_paths().len(), 3);
+        assert_eq!(
+            reg.property(&keys[1], "hardness"),
+            Some(&toml::Value::Integer(2))
+        );
+    }
+
+    #[test]
+    fn test_parse_kinds() {
+        let mut reg = VoxelReg::new();
+        let keys = parse(&format!("{}{}", DEFS, WATER), "test", &mut reg).unwrap();
+        assert_eq!(reg.kind(&keys[0]), VoxelKind::Empty);
+        assert_eq!(reg.kind(&keys[1]), VoxelKind::Opaque);
+        let water = reg.voxel_attributes(&keys[3]);
+        assert_eq!(water.kind, VoxelKind::Translucent);
+        assert_eq!(water.opacity, 0.6);
+
+        let src = format!("{}{}", DEFS, WATER.replace("0.6", "1.6"));
+        assert!(parse(&src, "test", &mut VoxelReg::new()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_entry_is_reported() {
+        let mut reg = VoxelReg::new();
+        let src = DEFS.replace("[0.8, 0.8, 0.8]", "[0.8, 1.8, 0.8]");
+        match parse(&src, "test", &mut reg) {
+            Err(DefinitionError::Invalid { index, id, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(id, "grass");
+            }
+            _ => panic!("expected invalid entry"),
+        }
+        assert_eq!(reg.key_from_string_id("air"), None);
+    }
+
+    #[test]
+    fn test_parse_states() {
+        let mut reg = VoxelReg::new();
+        let src = format!(
+            "{}{}",
+            DEFS,
+            r#"
+            [voxel.states]
+            axis = { type = "enum", values = ["x", "y", "z"], default = "y" }
+            level = { type = "int", min = 0, max = 7 }
+            wet = { type = "bool" }
+            "#
+        );
+        let keys = parse(&src, "test", &mut reg).unwrap();
+        let dirt = keys[2];
+        assert_eq!(reg.state_properties(&dirt).len(), 3);
+        assert_eq!(
+            reg.get_state(&dirt, "axis"),
+            Some(PropertyValue::Enum("y".to_string()))
+        );
+        let wet = reg
+            .with_property(&dirt, "wet", &PropertyValue::Bool(true))
+            .unwrap();
+        assert_eq!(reg.type_of(&wet), Some(dirt));
+    }
+
+    #[test]
+    fn test_invalid_state_default() {
+        let mut reg = VoxelReg::new();
+        let src = format!(
+            "{}{}",
+            DEFS, "[voxel.states]\nlevel = { type = \"int\", min = 0, max = 7, default = 9 }\n"
+        );
+        match parse(&src, "test", &mut reg) {
+            Err(DefinitionError::Invalid { index, .. }) => assert_eq!(index, 2),
+            _ => panic!("expected invalid state"),
+        }
+    }
+
+    #[test]
+    fn test_duplicate_id() {
+        let mut reg = VoxelReg::new();
+        let src = DEFS.replace("id = \"dirt\"", "id = \"air\"");
+        match parse(&src, "test", &mut reg) {
+            Err(DefinitionError::Invalid { index, .. }) => assert_eq!(index, 2),
+            _ => panic!("expected duplicate id"),
+        }
+    }
+}
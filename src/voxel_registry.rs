@@ -1,16 +1,23 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::io;
 
-use crate::consts::INVALID_VOXEL_ID;
+use crate::consts::{INVALID_VOXEL_ID, MAX_VOXEL_STATES};
 
-use flamer::flame;
 use glm::Vec3;
+use serde::{Deserialize, Serialize};
 
 struct Entry {
-    string_id: &'static str,
+    string_id: String,
     attributes: VoxelAttributes,
+    properties: HashMap<String, toml::Value>,
+    #[cfg(test)]
+    states: Vec<StateProperty>,
+    state_count: u64,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Default)]
 pub struct Material {
     pub ambient: Vec3,
     pub diffuse: Vec3,
@@ -18,76 +25,510 @@ pub struct Material {
     pub shininess: f32,
 }
 
-#[derive(Debug, Copy, Clone)]
+//Texture array layers used for each face of a voxel
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct FaceTextures {
+    pub top: u32,
+    pub side: u32,
+    pub bottom: u32,
+}
+
+//Empty voxels aren't drawn, cutout voxels are drawn with the transparent parts of their
+//textures discarded and translucent voxels are blended over everything else
+#[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VoxelKind {
+    #[default]
+    Opaque,
+    Cutout,
+    Translucent,
+    Empty,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
 pub struct VoxelAttributes {
-    pub transparent: bool,
+    pub kind: VoxelKind,
+    pub opacity: f32,
+    pub solid: bool,
     pub mat: Material,
+    pub textures: FaceTextures,
+    pub emission: Vec3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyKind {
+    Bool,
+    Int { min: i64, max: i64 },
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Bool(bool),
+    Int(i64),
+    Enum(String),
+}
+
+//A typed property of a voxel type, every combination of property values is its own state id
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateProperty {
+    pub name: String,
+    pub kind: PropertyKind,
+    pub default: PropertyValue,
+}
+
+impl StateProperty {
+    pub fn value_count(&self) -> u64 {
+        match &self.kind {
+            PropertyKind::Bool => 2,
+            PropertyKind::Int { min, max } if max >= min => {
+                max.checked_sub(*min).map_or(0, |d| d as u64 + 1)
+            }
+            PropertyKind::Int { .. } => 0,
+            PropertyKind::Enum(values) => values.len() as u64,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.value_count() > 0 && self.natural_index(&self.default).is_some()
+    }
+
+    fn natural_index(&self, value: &PropertyValue) -> Option<u64> {
+        match (&self.kind, value) {
+            (PropertyKind::Bool, PropertyValue::Bool(b)) => Some(*b as u64),
+            (PropertyKind::Int { min, max }, PropertyValue::Int(v)) if v >= min && v <= max => {
+                Some((v - min) as u64)
+            }
+            (PropertyKind::Enum(values), PropertyValue::Enum(v)) => {
+                values.iter().position(|x| x == v).map(|i| i as u64)
+            }
+            _ => None,
+        }
+    }
+
+    //Indices are rotated so the default value is always 0, that way the first state of a
+    //voxel type is its default state and the type id can be used as a state id
+    #[cfg(test)]
+    fn index(&self, value: &PropertyValue) -> Option<u64> {
+        let count = self.value_count();
+        let default = self.natural_index(&self.default)?;
+        Some((self.natural_index(value)? + count - default) % count)
+    }
+
+    #[cfg(test)]
+    fn value(&self, index: u64) -> PropertyValue {
+        let count = self.value_count();
+        let natural = (index + self.natural_index(&self.default).unwrap_or(0)) % count;
+        match &self.kind {
+            PropertyKind::Bool => PropertyValue::Bool(natural == 1),
+            PropertyKind::Int { min, .. } => PropertyValue::Int(min + natural as i64),
+            PropertyKind::Enum(values) => PropertyValue::Enum(values[natural as usize].clone()),
+        }
+    }
+}
+
+//Number of state ids needed for states, None if a property is invalid or there are more
+//than MAX_VOXEL_STATES combinations
+pub fn state_count(states: &[StateProperty]) -> Option<u64> {
+    let mut count: u64 = 1;
+    for state in states {
+        if !state.is_valid() {
+            return None;
+        }
+        count = count.checked_mul(state.value_count())?;
+    }
+    if count <= MAX_VOXEL_STATES {
+        Some(count)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum IdTableError {
+    Io(String, io::Error),
+    Parse(String, toml::de::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for IdTableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdTableError::Io(path, err) => write!(f, "failed to access {}: {}", path, err),
+            IdTableError::Parse(path, err) => write!(f, "invalid id table {}: {}", path, err),
+            IdTableError::Serialize(err) => write!(f, "failed to write id table: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for IdTableError {}
+
+//String id to numeric id of every registered voxel type, saved next to voxel data so the
+//numbers in it can be mapped to the ids of a later session or another peer. States lists
+//how many state ids follow each type id, types missing from it have one
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct IdTable {
+    pub ids: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub states: BTreeMap<String, u64>,
+}
+
+impl IdTable {
+    pub fn state_count(&self, string_id: &str) -> u64 {
+        *self.states.get(string_id).unwrap_or(&1)
+    }
+
+    pub fn parse(src: &str, name: &str) -> Result<IdTable, IdTableError> {
+        toml::from_str(src).map_err(|e| IdTableError::Parse(name.to_string(), e))
+    }
+
+    pub fn load(path: &str) -> Result<IdTable, IdTableError> {
+        let src = fs::read_to_string(path).map_err(|e| IdTableError::Io(path.to_string(), e))?;
+        IdTable::parse(&src, path)
+    }
+
+    pub fn to_toml(&self) -> Result<String, IdTableError> {
+        toml::to_string(self).map_err(IdTableError::Serialize)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), IdTableError> {
+        fs::write(path, self.to_toml()?).map_err(|e| IdTableError::Io(path.to_string(), e))
+    }
 }
 
 pub struct VoxelReg {
     reg: HashMap<u64, Entry>,
+    ids: HashMap<String, u64>,
+    state_types: HashMap<u64, u64>,
+    reserved: HashMap<String, (u64, u64)>,
+    reserved_keys: HashSet<u64>,
     next_key: u64,
+    textures: Vec<String>,
 }
 
 impl VoxelReg {
-    #[flame]
     pub fn new() -> VoxelReg {
         VoxelReg {
             reg: HashMap::new(),
+            ids: HashMap::new(),
+            state_types: HashMap::new(),
+            reserved: HashMap::new(),
+            reserved_keys: HashSet::new(),
             next_key: 1,
+            textures: Vec::new(),
         }
     }
 
-    #[flame]
-    pub fn get_new_key(&mut self) -> u64 {
-        let key = self.next_key;
-        self.next_key += 1;
-        key
+    //Voxel types registered afterwards get the id they have in table, so ids stay stable
+    //between sessions as long as the table is kept
+    pub fn reserve_ids(&mut self, table: &IdTable) {
+        for (string_id, key) in table.ids.iter() {
+            if *key != INVALID_VOXEL_ID {
+                let count = table.state_count(string_id).min(MAX_VOXEL_STATES);
+                self.reserved.insert(string_id.clone(), (*key, count));
+                self.reserved_keys.extend(*key..*key + count);
+            }
+        }
+    }
+
+    //Returns the first of count consecutive unused ids
+    pub fn get_new_keys(&mut self, count: u64) -> u64 {
+        loop {
+            let start = self.next_key;
+            match (start..start + count).find(|k| self.key_taken(k)) {
+                Some(taken) => self.next_key = taken + 1,
+                None => {
+                    self.next_key = start + count;
+                    return start;
+                }
+            }
+        }
+    }
+
+    fn key_taken(&self, key: &u64) -> bool {
+        self.reserved_keys.contains(key) || self.state_types.contains_key(key)
     }
 
-    #[flame]
+    //Returns the type id, which is also the id of its default state. The other states of the
+    //type follow it, panics if states is invalid (see state_count)
     pub fn register_voxel_type(
         &mut self,
-        string_id: &'static str,
-        transparent: bool,
-        mat: Material,
+        string_id: &str,
+        attributes: VoxelAttributes,
+        properties: HashMap<String, toml::Value>,
+        states: Vec<StateProperty>,
     ) -> u64 {
-        let key = self.get_new_key();
+        let count = state_count(&states).expect("invalid voxel states");
+        let key = match self.reserved.get(string_id) {
+            Some((key, reserved)) if count <= *reserved && !self.state_types.contains_key(key) => {
+                *key
+            }
+            _ => self.get_new_keys(count),
+        };
+        for state in key..key + count {
+            self.state_types.insert(state, key);
+        }
         self.reg.entry(key).or_insert(Entry {
-            string_id,
-            attributes: VoxelAttributes { transparent, mat },
+            string_id: string_id.to_string(),
+            attributes,
+            properties,
+            #[cfg(test)]
+            states,
+            state_count: count,
         });
+        self.ids.insert(string_id.to_string(), key);
         key
     }
 
-    #[flame]
-    pub fn voxel_attributes(&self, key: &u64) -> VoxelAttributes {
-        if *key != INVALID_VOXEL_ID {
-            return self.reg.get(key).unwrap().attributes;
+    //Type id of a state id
+    pub fn type_of(&self, state: &u64) -> Option<u64> {
+        self.state_types.get(state).copied()
+    }
+
+    fn entry(&self, state: &u64) -> Option<(u64, &Entry)> {
+        let key = self.type_of(state)?;
+        Some((key, self.reg.get(&key)?))
+    }
+
+    pub fn property(&self, key: &u64, name: &str) -> Option<&toml::Value> {
+        self.entry(key)?.1.properties.get(name)
+    }
+
+    #[cfg(test)]
+    pub fn state_properties(&self, key: &u64) -> &[StateProperty] {
+        self.entry(key).map_or(&[], |(_, e)| &e.states)
+    }
+
+    //Value of property name in state, None if the state or property doesn't exist
+    #[cfg(test)]
+    pub fn get_state(&self, state: &u64, name: &str) -> Option<PropertyValue> {
+        let (key, entry) = self.entry(state)?;
+        let mut stride = 1;
+        for prop in entry.states.iter() {
+            let count = prop.value_count();
+            if prop.name == name {
+                return Some(prop.value((state - key) / stride % count));
+            }
+            stride *= count;
         }
-        VoxelAttributes {
-            transparent: true,
-            mat: Material {
-                ambient: Vec3::new(0.0, 0.0, 0.0),
-                diffuse: Vec3::new(0.0, 0.0, 0.0),
-                specular: Vec3::new(0.0, 0.0, 0.0),
-                shininess: 0.0,
-            },
+        None
+    }
+
+    //The state id of state with property name set to value, None if the state or property
+    //doesn't exist or value isn't allowed for it
+    #[cfg(test)]
+    pub fn with_property(&self, state: &u64, name: &str, value: &PropertyValue) -> Option<u64> {
+        let (key, entry) = self.entry(state)?;
+        let mut stride = 1;
+        for prop in entry.states.iter() {
+            let count = prop.value_count();
+            if prop.name == name {
+                let old = (state - key) / stride % count;
+                let new = prop.index(value)?;
+                return Some(state - old * stride + new * stride);
+            }
+            stride *= count;
+        }
+        None
+    }
+
+    //Returns the texture array layer for path, registering it if it's new
+    pub fn register_texture(&mut self, path: &str) -> u32 {
+        match self.textures.iter().position(|p| p == path) {
+            Some(layer) => layer as u32,
+            None => {
+                self.textures.push(path.to_string());
+                (self.textures.len() - 1) as u32
+            }
         }
     }
 
-    #[flame]
+    pub fn texture_paths(&self) -> &[String] {
+        &self.textures
+    }
+
+    //Unknown ids, including INVALID_VOXEL_ID, are empty
+    pub fn voxel_attributes(&self, key: &u64) -> VoxelAttributes {
+        self.entry(key).map_or(
+            VoxelAttributes {
+                kind: VoxelKind::Empty,
+                ..VoxelAttributes::default()
+            },
+            |(_, e)| e.attributes,
+        )
+    }
+
+    pub fn kind(&self, key: &u64) -> VoxelKind {
+        self.voxel_attributes(key).kind
+    }
+
+    //Whether voxels behind this one can be seen through it
     pub fn is_transparent(&self, key: &u64) -> bool {
-        self.voxel_attributes(key).transparent
+        self.kind(key) != VoxelKind::Opaque
     }
 
-    #[flame]
-    pub fn key_from_string_id(&self, string_id: &str) -> u64 {
-        for (key, val) in self.reg.iter() {
-            if val.string_id == string_id {
-                return *key;
+    //Whether the face of voxel towards neighbour is drawn. Faces between two translucent
+    //voxels of the same type are hidden so water or glass reads as one volume
+    pub fn face_visible(&self, voxel: &u64, neighbour: &u64) -> bool {
+        match self.kind(neighbour) {
+            VoxelKind::Opaque => false,
+            VoxelKind::Translucent => {
+                self.kind(voxel) != VoxelKind::Translucent
+                    || self.type_of(voxel) != self.type_of(neighbour)
             }
+            VoxelKind::Cutout | VoxelKind::Empty => true,
+        }
+    }
+
+    pub fn key_from_string_id(&self, string_id: &str) -> Option<u64> {
+        self.ids.get(string_id).copied()
+    }
+
+    pub fn string_id(&self, key: &u64) -> Option<&str> {
+        self.entry(key).map(|(_, e)| e.string_id.as_str())
+    }
+
+    pub fn id_table(&self) -> IdTable {
+        IdTable {
+            ids: self
+                .ids
+                .iter()
+                .map(|(string_id, key)| (string_id.clone(), *key))
+                .collect(),
+            states: self
+                .reg
+                .values()
+                .filter(|e| e.state_count > 1)
+                .map(|e| (e.string_id.clone(), e.state_count))
+                .collect(),
         }
-        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(reg: &mut VoxelReg, string_id: &str) -> u64 {
+        reg.register_voxel_type(
+            string_id,
+            VoxelAttributes::default(),
+            HashMap::new(),
+            Vec::new(),
+        )
+    }
+
+    fn register_log(reg: &mut VoxelReg) -> u64 {
+        let states = vec![
+            StateProperty {
+                name: "axis".to_string(),
+                kind: PropertyKind::Enum(vec!["x".to_string(), "y".to_string(), "z".to_string()]),
+                default: PropertyValue::Enum("y".to_string()),
+            },
+            StateProperty {
+                name: "age".to_string(),
+                kind: PropertyKind::Int { min: 1, max: 4 },
+                default: PropertyValue::Int(1),
+            },
+            StateProperty {
+                name: "burning".to_string(),
+                kind: PropertyKind::Bool,
+                default: PropertyValue::Bool(false),
+            },
+        ];
+        reg.register_voxel_type("log", VoxelAttributes::default(), HashMap::new(), states)
+    }
+
+    #[test]
+    fn test_string_id_lookup() {
+        let mut reg = VoxelReg::new();
+        let stone = register(&mut reg, "stone");
+        assert_eq!(reg.key_from_string_id("stone"), Some(stone));
+        assert_eq!(reg.string_id(&stone), Some("stone"));
+        assert_eq!(reg.key_from_string_id("missing"), None);
+        assert_eq!(reg.string_id(&(stone + 1)), None);
+        assert_eq!(reg.kind(&(stone + 1)), VoxelKind::Empty);
+    }
+
+    #[test]
+    fn test_block_states() {
+        let mut reg = VoxelReg::new();
+        let log = register_log(&mut reg);
+        let stone = register(&mut reg, "stone");
+        assert_eq!(stone, log + 24);
+        assert_eq!(
+            reg.get_state(&log, "axis"),
+            Some(PropertyValue::Enum("y".to_string()))
+        );
+
+        let state = reg
+            .with_property(&log, "axis", &PropertyValue::Enum("z".to_string()))
+            .and_then(|s| reg.with_property(&s, "age", &PropertyValue::Int(3)))
+            .unwrap();
+        assert_eq!(reg.type_of(&state), Some(log));
+        assert_eq!(reg.string_id(&state), Some("log"));
+        assert_eq!(reg.get_state(&state, "age"), Some(PropertyValue::Int(3)));
+        assert_eq!(
+            reg.get_state(&state, "axis"),
+            Some(PropertyValue::Enum("z".to_string()))
+        );
+        assert_eq!(
+            reg.get_state(&state, "burning"),
+            Some(PropertyValue::Bool(false))
+        );
+
+        assert_eq!(
+            reg.with_property(&state, "age", &PropertyValue::Int(5)),
+            None
+        );
+        assert_eq!(
+            reg.with_property(&state, "colour", &PropertyValue::Int(1)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_translucent_faces() {
+        let mut reg = VoxelReg::new();
+        let translucent = |reg: &mut VoxelReg, string_id: &str| {
+            let attributes = VoxelAttributes {
+                kind: VoxelKind::Translucent,
+                ..VoxelAttributes::default()
+            };
+            reg.register_voxel_type(string_id, attributes, HashMap::new(), Vec::new())
+        };
+        let water = translucent(&mut reg, "water");
+        let glass = translucent(&mut reg, "glass");
+        let stone = register(&mut reg, "stone");
+
+        assert!(!reg.face_visible(&water, &water));
+        assert!(reg.face_visible(&water, &glass));
+        assert!(reg.face_visible(&stone, &water));
+        assert!(!reg.face_visible(&water, &stone));
+        assert!(reg.face_visible(&stone, &INVALID_VOXEL_ID));
+    }
+
+    #[test]
+    fn test_id_table_round_trip() {
+        let mut reg = VoxelReg::new();
+        register(&mut reg, "air");
+        register_log(&mut reg);
+        let table = reg.id_table();
+        assert_eq!(table.state_count("log"), 24);
+        let parsed = IdTable::parse(&table.to_toml().unwrap(), "test").unwrap();
+        assert_eq!(parsed, table);
+    }
+
+    #[test]
+    fn test_reserved_ids_are_stable() {
+        let mut old = VoxelReg::new();
+        register(&mut old, "air");
+        let stone = register(&mut old, "stone");
+
+        let mut reg = VoxelReg::new();
+        reg.reserve_ids(&old.id_table());
+        let dirt = register(&mut reg, "dirt");
+        assert_eq!(register(&mut reg, "stone"), stone);
+        assert!(dirt != stone && dirt != old.key_from_string_id("air").unwrap());
     }
 }
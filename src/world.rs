@@ -1,41 +1,167 @@
-use super::consts::{OPAQUE_VOXEL, TRANSPARENT_VOXEL};
+use super::consts::{INVALID_VOXEL_ID, OPAQUE_VOXEL, TRANSPARENT_VOXEL};
+use super::geom::Chunk;
 use super::geom::ChunkKey;
 use super::geom::PointCloud;
+use super::heightmap::HeightmapWorldType;
+use super::region::Region;
+use super::structures::Structures;
+use super::world_factory::{Param, ParamSpec, WorldParams, WorldTypeFactory};
 use super::VoxelReg;
 
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+
 use std::collections::HashMap;
+use std::fmt;
 use std::marker::Send;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum WorldError {
+    UnknownWorld(u64),
+    UnknownWorldType(u64),
+    NameTaken(String),
+    UnknownFactory(String),
+    Param(String),
+    Image(String, image::ImageError),
+    //The world was removed from the registry while something still held it
+    Removed(String),
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorldError::UnknownWorld(id) => write!(f, "no world with id {}", id),
+            WorldError::UnknownWorldType(id) => write!(f, "no world type with id {}", id),
+            WorldError::NameTaken(name) => write!(f, "a world named {} already exists", name),
+            WorldError::UnknownFactory(name) => write!(f, "unknown world type {}", name),
+            WorldError::Param(msg) => write!(f, "invalid world parameter: {}", msg),
+            WorldError::Image(path, err) => write!(f, "failed to load {}: {}", path, err),
+            WorldError::Removed(name) => write!(f, "world {} was removed", name),
+        }
+    }
+}
 
-use flamer::flame;
+impl std::error::Error for WorldError {}
 
 pub trait WorldType: Send + Sync {
     fn gen_chunk(&self, key: &ChunkKey, reg: &VoxelReg) -> Vec<u64>;
     fn world_type(&self) -> &'static str;
+
+    //Runs once for a new world before any of its chunks are generated
+    fn init_world(&self, _world: &World, _reg: &VoxelReg) {}
+
+    //Runs once per chunk when it and the 26 chunks around it exist, may place voxels in any of
+    //them through World::place_voxel
+    fn decorate(&self, _world: &World, _key: &ChunkKey, _reg: &VoxelReg) {}
+
+    //Where the camera starts in a world of this type, None leaves it to the config
+    fn spawn_point(&self) -> Option<[f32; 3]> {
+        None
+    }
 }
 
+//Fills every voxel at or below height with one voxel type
 pub struct FlatWorldType {
     pub chunk_size: usize,
+    pub height: i32,
+    pub voxel: u64,
+}
+
+impl FlatWorldType {
+    pub const FACTORY: WorldTypeFactory = WorldTypeFactory {
+        name: "flat",
+        params: &[
+            ParamSpec {
+                name: "height",
+                default: Param::Int(0),
+            },
+            ParamSpec {
+                name: "voxel",
+                default: Param::Voxel(OPAQUE_VOXEL),
+            },
+        ],
+        build: FlatWorldType::build,
+    };
+
+    fn build(params: &WorldParams) -> Result<Box<dyn WorldType>, WorldError> {
+        let height = params.int("height");
+        if height < i32::MIN as i64 || height > i32::MAX as i64 {
+            return Err(WorldError::Param(format!(
+                "height {} is out of range",
+                height
+            )));
+        }
+        Ok(Box::new(FlatWorldType {
+            chunk_size: params.chunk_size,
+            height: height as i32,
+            voxel: params.voxel("voxel"),
+        }))
+    }
 }
 
 impl WorldType for FlatWorldType {
-    #[flame("FlatWorldType")]
     fn gen_chunk(&self, key: &ChunkKey, reg: &VoxelReg) -> Vec<u64> {
-        let transparent_voxel = reg.key_from_string_id(TRANSPARENT_VOXEL);
-        let mut c = vec![transparent_voxel; self.chunk_size * self.chunk_size * self.chunk_size];
-        let voxel_type = reg.key_from_string_id(OPAQUE_VOXEL);
-        if key.y == 0 {
-            for x in 0..self.chunk_size {
-                for z in 0..self.chunk_size {
-                    let idx = crate::geom::calc_idx(x, 0, z, self.chunk_size);
-                    c[idx] = voxel_type;
+        let transparent_voxel = reg
+            .key_from_string_id(TRANSPARENT_VOXEL)
+            .unwrap_or(INVALID_VOXEL_ID);
+        let size = self.chunk_size;
+        let mut c = vec![transparent_voxel; size * size * size];
+        let chunk_y = key.y as i64 * size as i64;
+        for y in 0..size {
+            if chunk_y + y as i64 > self.height as i64 {
+                break;
+            }
+            for x in 0..size {
+                for z in 0..size {
+                    c[crate::geom::calc_idx(x, y, z, size)] = self.voxel;
                 }
             }
-        } else if key.y < 0 {
-            for y in 0..self.chunk_size {
-                for x in 0..self.chunk_size {
-                    for z in 0..self.chunk_size {
-                        let idx = crate::geom::calc_idx(x, y, z, self.chunk_size);
-                        c[idx] = voxel_type;
+        }
+        c
+    }
+
+    fn world_type(&self) -> &'static str {
+        "FlatWorldType"
+    }
+
+    fn spawn_point(&self) -> Option<[f32; 3]> {
+        Some([0.5, self.height as f32 + 5.0, 0.5])
+    }
+}
+
+//Serves the voxels of an imported region, everything outside it is empty
+pub struct RegionWorldType {
+    pub chunk_size: usize,
+    pub region: Region,
+}
+
+impl WorldType for RegionWorldType {
+    fn gen_chunk(&self, key: &ChunkKey, reg: &VoxelReg) -> Vec<u64> {
+        let transparent_voxel = reg
+            .key_from_string_id(TRANSPARENT_VOXEL)
+            .unwrap_or(INVALID_VOXEL_ID);
+        let palette: Vec<u64> = self
+            .region
+            .palette()
+            .iter()
+            .map(|entry| entry.to_key(reg).unwrap_or(transparent_voxel))
+            .collect();
+        let size = self.chunk_size;
+        let mut c = vec![transparent_voxel; size * size * size];
+        let chunk_origin = [key.x, key.y, key.z].map(|k| k * size as i32);
+        for x in 0..size {
+            for y in 0..size {
+                for z in 0..size {
+                    let world_pos = [
+                        chunk_origin[0] + x as i32,
+                        chunk_origin[1] + y as i32,
+                        chunk_origin[2] + z as i32,
+                    ];
+                    if let Some(local) = self.region.local_pos(world_pos) {
+                        let idx = crate::geom::calc_idx(x, y, z, size);
+                        c[idx] = palette[self.region.palette_index(local)];
                     }
                 }
             }
@@ -43,95 +169,458 @@ impl WorldType for FlatWorldType {
         c
     }
 
-    #[flame("FlatWorldType")]
     fn world_type(&self) -> &'static str {
-        "FlatWorldType"
+        "RegionWorldType"
+    }
+
+    //Above the middle of the region
+    fn spawn_point(&self) -> Option<[f32; 3]> {
+        let [x, y, z] = [0, 1, 2].map(|i| self.region.origin[i] as f32);
+        let [sx, sy, sz] = self.region.size.map(|s| s as f32);
+        Some([x + sx / 2.0, y + sy + 2.0, z + sz / 2.0])
     }
 }
 
 pub struct World {
     pub pc: PointCloud,
     pub world_type: u64,
-    pub active: bool,
+    //Portals and the config refer to worlds by name
+    pub name: String,
+    //Portals map positions between worlds by the ratio of their scales
+    pub scale: f32,
     chunk_size: usize,
+    //Voxels placed in chunks that weren't generated yet
+    pending: DashMap<ChunkKey, PendingVoxels>,
+    //Placed on chunks after the world type decorated them
+    pub structures: Option<Structures>,
+    removed: AtomicBool,
+}
+
+//Voxels placed in a chunk before it's generated, by index in the chunk
+#[derive(Default)]
+struct PendingVoxels {
+    voxels: Vec<(usize, u64)>,
+    //The highest priority structure voxel of each cell and its priority
+    structures: HashMap<usize, (u64, u64)>,
+}
+
+impl PendingVoxels {
+    fn place_structure(&mut self, idx: usize, voxel: u64, priority: u64) {
+        if self
+            .structures
+            .get(&idx)
+            .is_none_or(|(_, placed)| *placed < priority)
+        {
+            self.structures.insert(idx, (voxel, priority));
+        }
+    }
 }
 
 impl World {
-    #[flame("World")]
-    pub fn new(active: bool, chunk_size: usize, world_type: u64) -> World {
+    pub fn new(name: &str, chunk_size: usize, world_type: u64, scale: f32) -> World {
         World {
             pc: PointCloud::new(chunk_size),
             world_type,
-            active,
+            name: name.to_string(),
+            scale,
             chunk_size,
+            pending: DashMap::new(),
+            structures: None,
+            removed: AtomicBool::new(false),
+        }
+    }
+
+    //Set once the world is removed from its registry
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::Acquire)
+    }
+
+    fn check_removed(&self) -> Result<(), WorldError> {
+        if self.is_removed() {
+            Err(WorldError::Removed(self.name.clone()))
+        } else {
+            Ok(())
         }
     }
 
-    #[flame("World")]
     pub fn chunk_size(&self) -> usize {
         self.chunk_size
     }
+
+    //Chunk holding a voxel position and the voxel's index in it
+    fn chunk_idx(&self, pos: [i32; 3]) -> (ChunkKey, usize) {
+        let size = self.chunk_size as i32;
+        let key = ChunkKey {
+            x: pos[0].div_euclid(size),
+            y: pos[1].div_euclid(size),
+            z: pos[2].div_euclid(size),
+        };
+        let [x, y, z] = pos.map(|p| p.rem_euclid(size) as usize);
+        (key, crate::geom::calc_idx(x, y, z, self.chunk_size))
+    }
+
+    //Voxel at a position, None if its chunk doesn't exist yet
+    pub fn voxel(&self, pos: [i32; 3]) -> Option<u64> {
+        let (key, idx) = self.chunk_idx(pos);
+        if self.pc.chunk_exists(&key) {
+            Some(self.pc.voxel_in_chunk_idx(&key, idx))
+        } else {
+            None
+        }
+    }
+
+    //Sets a voxel, in chunks that don't exist yet it's written when they are inserted. Meshed
+    //chunks are meshed and uploaded again when their next ticket is processed
+    pub fn place_voxel(&self, pos: [i32; 3], voxel: u64, reg: &VoxelReg) -> Result<(), WorldError> {
+        self.check_removed()?;
+        let (key, idx) = self.chunk_idx(pos);
+        self.place(key, idx, voxel, reg);
+        Ok(())
+    }
+
+    //Places a structure's voxel, it only replaces voxels that are transparent in the generated
+    //terrain and voxels of structures with a lower priority
+    pub fn place_structure_voxel(
+        &self,
+        pos: [i32; 3],
+        voxel: u64,
+        priority: u64,
+        reg: &VoxelReg,
+    ) -> Result<(), WorldError> {
+        self.check_removed()?;
+        let (key, idx) = self.chunk_idx(pos);
+        //Chunks keep the priorities once they're generated, until then they're pending
+        match self.pending.entry(key) {
+            Entry::Occupied(mut pending) => pending.get_mut().place_structure(idx, voxel, priority),
+            Entry::Vacant(pending) => {
+                if self.pc.chunk_exists(&key) {
+                    self.pc
+                        .place_structure_voxel_in_chunk(&key, idx, voxel, priority, reg);
+                } else {
+                    let mut voxels = PendingVoxels::default();
+                    voxels.place_structure(idx, voxel, priority);
+                    pending.insert(voxels);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn place(&self, key: ChunkKey, idx: usize, voxel: u64, reg: &VoxelReg) {
+        //The entry stays locked until the voxel is placed, so insert_chunk either sees it
+        //pending or the chunk is already there. Chunks that exist don't get an entry
+        match self.pending.entry(key) {
+            Entry::Occupied(mut pending) => pending.get_mut().voxels.push((idx, voxel)),
+            Entry::Vacant(pending) => {
+                if self.pc.chunk_exists(&key) {
+                    self.pc.set_voxel_in_chunk_idx(&key, idx, voxel, reg);
+                } else {
+                    pending.insert(PendingVoxels {
+                        voxels: vec![(idx, voxel)],
+                        ..PendingVoxels::default()
+                    });
+                }
+            }
+        }
+    }
+
+    //Whether the chunk at key and the 26 around it all exist
+    pub fn neighbours_exist(&self, key: &ChunkKey) -> bool {
+        (-1..=1).all(|x| {
+            (-1..=1).all(|y| {
+                (-1..=1).all(|z| {
+                    self.pc.chunk_exists(&ChunkKey {
+                        x: key.x + x,
+                        y: key.y + y,
+                        z: key.z + z,
+                    })
+                })
+            })
+        })
+    }
+
+    //True the first time it's called for a chunk, so each chunk is decorated once
+    pub fn mark_decorated(&self, key: &ChunkKey) -> bool {
+        self.pc.mark_chunk_decorated(key)
+    }
+
+    pub fn insert_chunk(
+        &self,
+        key: ChunkKey,
+        chunk: Chunk,
+        reg: &VoxelReg,
+    ) -> Result<(), WorldError> {
+        self.check_removed()?;
+        self.pc.insert_chunk(key, chunk);
+        if let Some((_, pending)) = self.pending.remove(&key) {
+            //Structures go first so voxels placed directly win over them
+            for (idx, (voxel, priority)) in pending.structures {
+                self.pc
+                    .place_structure_voxel_in_chunk(&key, idx, voxel, priority, reg);
+            }
+            for (idx, voxel) in pending.voxels {
+                self.pc.set_voxel_in_chunk_idx(&key, idx, voxel, reg);
+            }
+        }
+        Ok(())
+    }
 }
 
+//Factories by name and the world types built from them, or registered directly, by id
 pub struct WorldTypeRegistry {
-    pub world_type_reg: HashMap<u64, Box<dyn WorldType>>,
-    next_type_key: u64,
+    world_type_reg: DashMap<u64, Arc<dyn WorldType>>,
+    factories: DashMap<&'static str, Arc<WorldTypeFactory>>,
+    next_type_key: AtomicU64,
 }
 
 impl WorldTypeRegistry {
-    #[flame("WorldTypeRegistry")]
     pub fn new() -> WorldTypeRegistry {
         WorldTypeRegistry {
-            world_type_reg: HashMap::new(),
-            next_type_key: 1,
+            world_type_reg: DashMap::new(),
+            factories: DashMap::new(),
+            next_type_key: AtomicU64::new(1),
         }
     }
 
-    #[flame("WorldTypeRegistry")]
-    pub fn register_world_type(&mut self, world_type: Box<dyn WorldType>) -> u64 {
-        let id = self.get_next_type_key();
+    //With the world types that come with the renderer
+    pub fn with_builtin() -> WorldTypeRegistry {
+        let reg = WorldTypeRegistry::new();
+        reg.register_factory(FlatWorldType::FACTORY);
+        reg.register_factory(HeightmapWorldType::FACTORY);
+        reg
+    }
+
+    //Replaces a factory of the same name, world types built from the old one stay registered
+    pub fn register_factory(&self, factory: WorldTypeFactory) {
+        self.factories.insert(factory.name, Arc::new(factory));
+    }
+
+    pub fn factory_names(&self) -> Vec<&'static str> {
+        let mut names: Vec<&'static str> = self.factories.iter().map(|f| *f.key()).collect();
+        names.sort();
+        names
+    }
+
+    //Builds a world type with the named factory from a world's parameters and registers it
+    pub fn instantiate(
+        &self,
+        name: &str,
+        seed: u64,
+        chunk_size: usize,
+        params: &toml::value::Table,
+        reg: &VoxelReg,
+    ) -> Result<u64, WorldError> {
+        let factory = self
+            .factories
+            .get(name)
+            .map(|factory| factory.clone())
+            .ok_or_else(|| WorldError::UnknownFactory(name.to_string()))?;
+        let params = WorldParams::parse(factory.params, seed, chunk_size, params, reg)?;
+        let world_type = (factory.build)(&params)?;
+        Ok(self.register_world_type(world_type))
+    }
+
+    pub fn register_world_type(&self, world_type: Box<dyn WorldType>) -> u64 {
+        self.register_shared(Arc::from(world_type))
+    }
+
+    //Registers a world type again after unregister_world_type, under a new id
+    pub fn register_shared(&self, world_type: Arc<dyn WorldType>) -> u64 {
+        let id = self.next_type_key.fetch_add(1, Ordering::Relaxed);
         self.world_type_reg.insert(id, world_type);
         id
     }
 
-    #[flame("WorldTypeRegistry")]
-    fn get_next_type_key(&mut self) -> u64 {
-        let out = self.next_type_key;
-        self.next_type_key += 1;
-        out
+    //Worlds of the type stop generating chunks, ones being generated still finish
+    pub fn unregister_world_type(&self, id: &u64) -> Result<Arc<dyn WorldType>, WorldError> {
+        self.world_type_reg
+            .remove(id)
+            .map(|(_, world_type)| world_type)
+            .ok_or(WorldError::UnknownWorldType(*id))
+    }
+
+    pub fn get(&self, id: &u64) -> Option<Arc<dyn WorldType>> {
+        self.world_type_reg
+            .get(id)
+            .map(|world_type| world_type.clone())
     }
 }
 
 pub struct WorldRegistry {
-    world_reg: HashMap<u64, World>,
-    next_world_key: u64,
+    world_reg: DashMap<u64, Arc<World>>,
+    names: DashMap<String, u64>,
+    next_world_key: AtomicU64,
 }
 
 impl WorldRegistry {
-    #[flame("WorldRegistry")]
     pub fn new() -> WorldRegistry {
         WorldRegistry {
-            world_reg: HashMap::new(),
-            next_world_key: 1,
+            world_reg: DashMap::new(),
+            names: DashMap::new(),
+            next_world_key: AtomicU64::new(1),
         }
     }
 
-    #[flame("WorldRegistry")]
-    pub fn new_world(&mut self, world: World) -> u64 {
-        let id = self.get_next_world_key();
-        self.world_reg.insert(id, world);
-        id
+    pub fn new_world(&self, world: World) -> Result<u64, WorldError> {
+        match self.names.entry(world.name.clone()) {
+            Entry::Occupied(_) => Err(WorldError::NameTaken(world.name)),
+            Entry::Vacant(entry) => {
+                let id = self.next_world_key.fetch_add(1, Ordering::Relaxed);
+                self.world_reg.insert(id, Arc::new(world));
+                entry.insert(id);
+                Ok(id)
+            }
+        }
+    }
+
+    //Threads still holding the world keep it until they drop it, but can't add chunks to it
+    pub fn remove_world(&self, id: &u64) -> Result<Arc<World>, WorldError> {
+        let (_, world) = self
+            .world_reg
+            .remove(id)
+            .ok_or(WorldError::UnknownWorld(*id))?;
+        world.removed.store(true, Ordering::Release);
+        self.names.remove(&world.name);
+        Ok(world)
+    }
+
+    pub fn get(&self, id: &u64) -> Option<Arc<World>> {
+        self.world_reg.get(id).map(|world| world.clone())
     }
 
-    #[flame("WorldRegistry")]
-    fn get_next_world_key(&mut self) -> u64 {
-        let out = self.next_world_key;
-        self.next_world_key += 1;
-        out
+    pub fn find(&self, name: &str) -> Option<u64> {
+        self.names.get(name).map(|id| *id)
+    }
+
+    //Ids of every world in the order they were created
+    pub fn ids(&self) -> Vec<u64> {
+        let mut ids: Vec<u64> = self.world_reg.iter().map(|entry| *entry.key()).collect();
+        ids.sort();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_world_registry() {
+        let reg = WorldRegistry::new();
+        let main = reg.new_world(World::new("main", 4, 1, 1.0)).unwrap();
+        let other = reg.new_world(World::new("other", 4, 1, 8.0)).unwrap();
+        assert!(matches!(
+            reg.new_world(World::new("main", 4, 1, 1.0)),
+            Err(WorldError::NameTaken(_))
+        ));
+        assert_eq!(reg.find("other"), Some(other));
+        assert_eq!(reg.ids(), vec![main, other]);
+
+        let held = reg.get(&other).unwrap();
+        reg.remove_world(&other).unwrap();
+        assert!(reg.get(&other).is_none());
+        assert_eq!(reg.find("other"), None);
+        assert!(matches!(
+            reg.remove_world(&other),
+            Err(WorldError::UnknownWorld(_))
+        ));
+        assert!(held.is_removed());
+        assert!(!reg.get(&main).unwrap().is_removed());
+        //The name is free again
+        assert!(reg.new_world(World::new("other", 4, 1, 8.0)).is_ok());
+    }
+
+    #[test]
+    fn test_structure_voxels() {
+        let mut voxreg = VoxelReg::new();
+        let src = "[[voxel]]\nid = \"transparent\"\nkind = \"empty\"\n\
+                   [[voxel]]\nid = \"stone\"\ntextures = { all = \"stone.png\" }\n\
+                   [[voxel]]\nid = \"wood\"\ntextures = { all = \"wood.png\" }\n\
+                   [[voxel]]\nid = \"leaves\"\ntextures = { all = \"leaves.png\" }";
+        crate::voxel_defs::parse(src, "test", &mut voxreg).unwrap();
+        let air = voxreg.key_from_string_id("transparent").unwrap();
+        let stone = voxreg.key_from_string_id("stone").unwrap();
+        let wood = voxreg.key_from_string_id("wood").unwrap();
+        let leaves = voxreg.key_from_string_id("leaves").unwrap();
+        let key = ChunkKey { x: 0, y: 0, z: 0 };
+        //Stone in the bottom layer
+        let terrain = || {
+            let mut voxels = vec![air; 64];
+            for i in 0..16 {
+                voxels[crate::geom::calc_idx(i % 4, 0, i / 4, 4)] = stone;
+            }
+            Chunk::new(4, &key, voxels, &voxreg)
+        };
+        //Two structures write a cell in the air and one in the stone, in both orders and with
+        //the chunk generated before or after
+        let placed = |generated: bool, order: [(u64, u64); 2]| {
+            let world = World::new("w", 4, 1, 1.0);
+            if generated {
+                world.insert_chunk(key, terrain(), &voxreg).unwrap();
+            }
+            for (voxel, priority) in order.iter() {
+                for pos in [[1, 2, 1], [1, 0, 1]].iter() {
+                    world
+                        .place_structure_voxel(*pos, *voxel, *priority, &voxreg)
+                        .unwrap();
+                }
+            }
+            if !generated {
+                world.insert_chunk(key, terrain(), &voxreg).unwrap();
+            }
+            assert!(world.pending.is_empty());
+            (world.voxel([1, 2, 1]), world.voxel([1, 0, 1]))
+        };
+        for generated in [false, true].iter() {
+            for order in [[(wood, 1), (leaves, 2)], [(leaves, 2), (wood, 1)]].iter() {
+                assert_eq!(placed(*generated, *order), (Some(leaves), Some(stone)));
+            }
+        }
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let mut voxreg = VoxelReg::new();
+        let src = "[[voxel]]\nid = \"transparent\"\nkind = \"empty\"\n\
+                   [[voxel]]\nid = \"opaque\"\nkind = \"empty\"";
+        crate::voxel_defs::parse(src, "test", &mut voxreg).unwrap();
+        let opaque = voxreg.key_from_string_id("opaque").unwrap();
+        let reg = WorldTypeRegistry::with_builtin();
+        assert_eq!(reg.factory_names(), vec!["flat", "heightmap"]);
+
+        let params = toml::from_str("height = 2").unwrap();
+        let flat = reg.instantiate("flat", 0, 4, &params, &voxreg).unwrap();
+        let voxels = reg
+            .get(&flat)
+            .unwrap()
+            .gen_chunk(&ChunkKey { x: 0, y: 0, z: 0 }, &voxreg);
+        let at = |y| voxels[crate::geom::calc_idx(1, y, 1, 4)];
+        assert_eq!(
+            (at(2), at(3)),
+            (opaque, voxreg.key_from_string_id("transparent").unwrap())
+        );
+
+        let empty = toml::value::Table::new();
+        assert!(matches!(
+            reg.instantiate("caves", 0, 4, &empty, &voxreg),
+            Err(WorldError::UnknownFactory(_))
+        ));
     }
 
-    #[flame("WorldRegistry")]
-    pub fn world(&self, id: &u64) -> &World {
-        self.world_reg.get(id).unwrap()
+    #[test]
+    fn test_world_type_registry() {
+        let reg = WorldTypeRegistry::new();
+        let flat = reg.register_world_type(Box::new(FlatWorldType {
+            chunk_size: 4,
+            height: 0,
+            voxel: 1,
+        }));
+        assert_eq!(reg.get(&flat).unwrap().world_type(), "FlatWorldType");
+        assert!(reg.unregister_world_type(&flat).is_ok());
+        assert!(reg.get(&flat).is_none());
+        assert!(matches!(
+            reg.unregister_world_type(&flat),
+            Err(WorldError::UnknownWorldType(_))
+        ));
     }
 }
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use super::voxel_registry::VoxelReg;
+use super::world::{WorldError, WorldType};
+
+//A parameter's type and its default value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param {
+    Int(i64),
+    Float(f64),
+    //String id of a voxel type
+    Voxel(&'static str),
+    Text(&'static str),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ParamSpec {
+    pub name: &'static str,
+    pub default: Param,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ParamValue {
+    Int(i64),
+    Float(f64),
+    Voxel(u64),
+    Text(String),
+}
+
+//Parameters of one world checked against a schema, voxel ids are resolved to their keys
+#[derive(Debug, Clone)]
+pub struct WorldParams {
+    pub seed: u64,
+    pub chunk_size: usize,
+    values: HashMap<&'static str, ParamValue>,
+}
+
+impl WorldParams {
+    //Values left out of table take their defaults, names outside the schema or values of the
+    //wrong type are errors
+    pub fn parse(
+        schema: &[ParamSpec],
+        seed: u64,
+        chunk_size: usize,
+        table: &toml::value::Table,
+        reg: &VoxelReg,
+    ) -> Result<WorldParams, WorldError> {
+        if let Some(name) = table
+            .keys()
+            .find(|name| schema.iter().all(|spec| spec.name != name.as_str()))
+        {
+            return Err(WorldError::Param(format!("unknown parameter {}", name)));
+        }
+        let mut values = HashMap::new();
+        for spec in schema.iter() {
+            let wrong_type =
+                |kind: &str| WorldError::Param(format!("{} must be {}", spec.name, kind));
+            let value = table.get(spec.name);
+            let value = match spec.default {
+                Param::Int(default) => match value {
+                    None => ParamValue::Int(default),
+                    Some(v) => {
+                        ParamValue::Int(v.as_integer().ok_or_else(|| wrong_type("an integer"))?)
+                    }
+                },
+                //Integers are taken as floats so heights can be written either way
+                Param::Float(default) => match value {
+                    None => ParamValue::Float(default),
+                    Some(toml::Value::Integer(i)) => ParamValue::Float(*i as f64),
+                    Some(v) => {
+                        ParamValue::Float(v.as_float().ok_or_else(|| wrong_type("a number"))?)
+                    }
+                },
+                Param::Voxel(default) => {
+                    let id = match value {
+                        None => default,
+                        Some(v) => v.as_str().ok_or_else(|| wrong_type("a voxel id"))?,
+                    };
+                    let key = reg.key_from_string_id(id).ok_or_else(|| {
+                        WorldError::Param(format!("{} is an unknown voxel type {}", spec.name, id))
+                    })?;
+                    ParamValue::Voxel(key)
+                }
+                Param::Text(default) => match value {
+                    None => ParamValue::Text(default.to_string()),
+                    Some(v) => ParamValue::Text(
+                        v.as_str()
+                            .ok_or_else(|| wrong_type("a string"))?
+                            .to_string(),
+                    ),
+                },
+            };
+            values.insert(spec.name, value);
+        }
+        Ok(WorldParams {
+            seed,
+            chunk_size,
+            values,
+        })
+    }
+
+    //The getters panic for names the factory's schema doesn't declare with that type
+    pub fn int(&self, name: &str) -> i64 {
+        match self.values.get(name) {
+            Some(ParamValue::Int(v)) => *v,
+            _ => panic!("no integer parameter {}", name),
+        }
+    }
+
+    pub fn float(&self, name: &str) -> f64 {
+        match self.values.get(name) {
+            Some(ParamValue::Float(v)) => *v,
+            _ => panic!("no float parameter {}", name),
+        }
+    }
+
+    pub fn voxel(&self, name: &str) -> u64 {
+        match self.values.get(name) {
+            Some(ParamValue::Voxel(v)) => *v,
+            _ => panic!("no voxel parameter {}", name),
+        }
+    }
+
+    pub fn text(&self, name: &str) -> &str {
+        match self.values.get(name) {
+            Some(ParamValue::Text(v)) => v,
+            _ => panic!("no text parameter {}", name),
+        }
+    }
+}
+
+//Builds world types of one kind from the parameters in a world's config
+pub struct WorldTypeFactory {
+    //What configs select the type by
+    pub name: &'static str,
+    pub params: &'static [ParamSpec],
+    pub build: fn(&WorldParams) -> Result<Box<dyn WorldType>, WorldError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::voxel_defs;
+
+    const SCHEMA: &[ParamSpec] = &[
+        ParamSpec {
+            name: "height",
+            default: Param::Int(0),
+        },
+        ParamSpec {
+            name: "scale",
+            default: Param::Float(1.5),
+        },
+        ParamSpec {
+            name: "surface",
+            default: Param::Voxel("stone"),
+        },
+        ParamSpec {
+            name: "file",
+            default: Param::Text(""),
+        },
+    ];
+
+    fn registry() -> VoxelReg {
+        let mut reg = VoxelReg::new();
+        let src = "[[voxel]]\nid = \"stone\"\nkind = \"empty\"\n\
+                   [[voxel]]\nid = \"grass\"\nkind = \"empty\"";
+        voxel_defs::parse(src, "test", &mut reg).unwrap();
+        reg
+    }
+
+    fn table(src: &str) -> toml::value::Table {
+        toml::from_str(src).unwrap()
+    }
+
+    #[test]
+    fn test_params() {
+        let reg = registry();
+        let params = WorldParams::parse(SCHEMA, 7, 16, &table(""), &reg).unwrap();
+        assert_eq!(params.seed, 7);
+        assert_eq!(params.int("height"), 0);
+        assert_eq!(params.float("scale"), 1.5);
+        assert_eq!(
+            Some(params.voxel("surface")),
+            reg.key_from_string_id("stone")
+        );
+
+        assert_eq!(params.text("file"), "");
+
+        let src = "height = -4\nscale = 2\nsurface = \"grass\"\nfile = \"a.png\"";
+        let params = WorldParams::parse(SCHEMA, 7, 16, &table(src), &reg).unwrap();
+        assert_eq!(params.int("height"), -4);
+        assert_eq!(params.float("scale"), 2.0);
+        assert_eq!(params.text("file"), "a.png");
+        assert_eq!(
+            Some(params.voxel("surface")),
+            reg.key_from_string_id("grass")
+        );
+    }
+
+    #[test]
+    fn test_invalid_params() {
+        let reg = registry();
+        for src in [
+            "depth = 3",
+            "height = 1.5",
+            "scale = \"big\"",
+            "surface = \"lava\"",
+            "file = 3",
+        ]
+        .iter()
+        {
+            assert!(matches!(
+                WorldParams::parse(SCHEMA, 0, 16, &table(src), &reg),
+                Err(WorldError::Param(_))
+            ));
+        }
+    }
+}